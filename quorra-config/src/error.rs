@@ -0,0 +1,168 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Where in the config a problem was found. Lines and columns start at 1.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLocation {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The key that failed, like `responses[2].body.type`.
+    pub key_path: Option<String>,
+}
+
+impl ConfigLocation {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Display for ConfigLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        if let Some(key_path) = &self.key_path {
+            write!(f, " at `{}`", key_path)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("{location}: unable to read file: {source}")]
+    Io {
+        location: Box<ConfigLocation>,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{location}: unsupported file type, expected `.yaml`, `.yml` or `.toml`")]
+    UnsupportedFormat { location: Box<ConfigLocation> },
+    #[error("{location}: {message}")]
+    Parse {
+        location: Box<ConfigLocation>,
+        message: String,
+    },
+    #[error(
+        "{location}: unknown response type `{name}`, expected `static-http`, `websocket`, `grpc`, `tcp` or `smtp`"
+    )]
+    UnknownType {
+        location: Box<ConfigLocation>,
+        name: String,
+    },
+    #[error("{location}: invalid glob `{pattern}`: {source}")]
+    Glob {
+        location: Box<ConfigLocation>,
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+    #[error("{location}: unable to load {}: {source}", .file.display())]
+    DataFile {
+        location: Box<ConfigLocation>,
+        file: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The file loaded, but a plugin could not use the value.
+    #[error("{location}: {message}")]
+    Invalid {
+        location: Box<ConfigLocation>,
+        message: String,
+    },
+}
+
+impl ConfigError {
+    pub fn location(&self) -> &ConfigLocation {
+        match self {
+            ConfigError::Io { location, .. }
+            | ConfigError::UnsupportedFormat { location }
+            | ConfigError::Parse { location, .. }
+            | ConfigError::UnknownType { location, .. }
+            | ConfigError::Glob { location, .. }
+            | ConfigError::DataFile { location, .. }
+            | ConfigError::Invalid { location, .. } => location,
+        }
+    }
+
+    fn location_mut(&mut self) -> &mut ConfigLocation {
+        match self {
+            ConfigError::Io { location, .. }
+            | ConfigError::UnsupportedFormat { location }
+            | ConfigError::Parse { location, .. }
+            | ConfigError::UnknownType { location, .. }
+            | ConfigError::Glob { location, .. }
+            | ConfigError::DataFile { location, .. }
+            | ConfigError::Invalid { location, .. } => location,
+        }
+    }
+
+    /// Prefixes the key path, used while the error bubbles up through nested configs.
+    pub(crate) fn at_key(mut self, key: &str) -> Self {
+        let location = self.location_mut();
+        location.key_path = Some(match location.key_path.take() {
+            Some(inner) => format!("{}.{}", key, inner),
+            None => key.to_string(),
+        });
+        self
+    }
+
+    pub(crate) fn in_file(mut self, path: &Path, line: Option<usize>) -> Self {
+        let location = self.location_mut();
+        location.path = path.to_path_buf();
+        if location.line.is_none() {
+            location.line = line;
+        }
+        self
+    }
+
+    /// The lines around the error with a marker under the column, when the
+    /// line is known and the file can still be read.
+    pub fn snippet(&self) -> Option<String> {
+        let location = self.location();
+        let line = location.line?;
+        let contents = std::fs::read_to_string(&location.path).ok()?;
+        Some(render_snippet(&contents, line, location.column))
+    }
+}
+
+fn render_snippet(contents: &str, line: usize, column: Option<usize>) -> String {
+    let lines: Vec<&str> = contents.lines().collect();
+    let first = line.saturating_sub(1).max(1);
+    let last = (line + 1).min(lines.len());
+    let width = last.to_string().len();
+
+    let mut snippet = format!("{:width$} |\n", "", width = width);
+    for number in first..=last {
+        let text = lines.get(number - 1).unwrap_or(&"");
+        snippet.push_str(&format!("{:width$} | {}\n", number, text, width = width));
+        if number == line {
+            let marker = match column {
+                Some(column) => format!("{}^", " ".repeat(column.saturating_sub(1))),
+                None => "^".repeat(text.len().max(1)),
+            };
+            snippet.push_str(&format!("{:width$} | {}\n", "", marker, width = width));
+        }
+    }
+    snippet
+}
+
+#[test]
+fn test_render_snippet() {
+    let contents = "type: static-http\nresponses:\n  - status: abc\n    body: {}\n";
+    assert_eq!(
+        "  |\n2 | responses:\n3 |   - status: abc\n  |             ^\n4 |     body: {}\n",
+        render_snippet(contents, 3, Some(13))
+    );
+}
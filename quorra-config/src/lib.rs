@@ -1,10 +1,15 @@
-use anyhow::Result as AnyResult;
-use config::Config;
+use error::{ConfigError, ConfigLocation};
 use glob::glob;
-use plugins::MakeStatic;
-use std::path::{Path, PathBuf};
+use parse::load_response_file;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
 use tracing::debug;
 
+mod error;
+mod parse;
 mod plugins;
 mod user;
 
@@ -15,7 +20,45 @@ pub struct ConfigContainer {
 
 pub struct ParsedUserConfig {
     pub root: user::ServerRootConfig,
+    /// The responses of the default listener.
     pub responses: Vec<plugins::ResponseConfig<String>>,
+    /// The responses of every named listener.
+    pub listeners: BTreeMap<String, Vec<plugins::ResponseConfig<String>>>,
+}
+
+impl ParsedUserConfig {
+    /// Adds a response to the listener it was loaded for, `None` being the
+    /// default listener, limited to the host its file is bound to.
+    pub fn push_response(
+        &mut self,
+        file: &ListedFile,
+        mut response: plugins::ResponseConfig<String>,
+    ) {
+        if let Some(host) = &file.host {
+            response.bind_host(host);
+        }
+        match file.listener.as_deref() {
+            None => self.responses.push(response),
+            Some(name) => self
+                .listeners
+                .entry(name.to_string())
+                .or_default()
+                .push(response),
+        }
+    }
+
+    fn new(root: user::ServerRootConfig) -> Self {
+        let listeners = root
+            .listeners
+            .keys()
+            .map(|name| (name.clone(), Vec::new()))
+            .collect();
+        Self {
+            root,
+            responses: Vec::new(),
+            listeners,
+        }
+    }
 }
 
 impl ConfigContainer {
@@ -29,47 +72,255 @@ impl ConfigContainer {
                 .expect("a good dir"),
         }
     }
-    pub fn load_config(&self) -> AnyResult<ParsedUserConfig> {
+    pub fn load_config(&self) -> Result<ParsedUserConfig, ConfigError> {
+        let parsed_root = self.load_root()?;
+        let found = self.find_response_files(&parsed_root)?;
+
+        let mut parsed = ParsedUserConfig::new(parsed_root);
+        for file in found.into_iter().flat_map(|found| found.listed()) {
+            let response = load_response_file(&file.path)?.config;
+            parsed.push_response(&file, response);
+        }
+        Ok(parsed)
+    }
+
+    /// Loads the root config and lists the response files it points to, with
+    /// their listener and host, in the order the server tries them, so that each
+    /// file can be loaded on its own.
+    pub fn list_response_files(&self) -> Result<(ParsedUserConfig, Vec<ListedFile>), ConfigError> {
+        let parsed_root = self.load_root()?;
+        let files = self
+            .find_response_files(&parsed_root)?
+            .into_iter()
+            .flat_map(|found| found.listed())
+            .collect();
+        Ok((ParsedUserConfig::new(parsed_root), files))
+    }
+
+    /// Loads a single response file, along with every data file it references.
+    pub fn load_response_file(&self, response_file: &Path) -> Result<ResponseFile, ConfigError> {
+        load_response_file(response_file)
+    }
+
+    pub fn root_config_file(&self) -> &Path {
+        &self.root_config_file
+    }
+
+    /// Loads every file like `load_config`, but keeps going after a file fails to
+    /// load so that all the problems can be reported at once.
+    pub fn validate_config(&self) -> ValidatedUserConfig {
+        let mut validated = ValidatedUserConfig::default();
+
+        let parsed_root = match self.load_root() {
+            Ok(parsed_root) => parsed_root,
+            Err(e) => {
+                validated.errors.push(e);
+                return validated;
+            }
+        };
+
+        let found = match self.find_response_files(&parsed_root) {
+            Ok(found) => found,
+            Err(e) => {
+                validated.errors.push(e);
+                return validated;
+            }
+        };
+
+        for found in found {
+            if found.files.is_empty() {
+                validated.warnings.push(ConfigIssue {
+                    file: self.root_config_file.clone(),
+                    message: format!("`{}` does not match any files", found.glob),
+                });
+            }
+
+            for file in found.listed() {
+                match load_response_file(&file.path) {
+                    Ok(parsed) => {
+                        let mut config = parsed.config;
+                        if let Some(host) = &file.host {
+                            config.bind_host(host);
+                        }
+                        validated.responses.push(ValidatedResponse {
+                            listener: file.listener,
+                            file: file.path,
+                            config,
+                        })
+                    }
+                    Err(e) => validated.errors.push(e),
+                }
+            }
+        }
+
+        validated.root = Some(parsed_root);
+        validated
+    }
+
+    fn load_root(&self) -> Result<user::ServerRootConfig, ConfigError> {
         debug!("Loading root config {:?}", self.root_config_file);
-        let root_config = Config::builder()
-            .add_source(config::File::from(self.root_config_file.as_path()))
-            .build()?;
+        parse::parse_file(&self.root_config_file)
+    }
 
-        let parsed_root: user::ServerRootConfig = root_config.try_deserialize()?;
+    /// Expands every glob in the root config, relative to the root config. The
+    /// globs of the default listener come first, then the ones of each named
+    /// listener. Within a listener, the globs bound to a host come first.
+    fn find_response_files(
+        &self,
+        parsed_root: &user::ServerRootConfig,
+    ) -> Result<Vec<FoundFiles>, ConfigError> {
+        let listeners = parsed_root
+            .listeners
+            .iter()
+            .map(|(name, listener)| (Some(name.clone()), &listener.responses));
+        let response_sets = std::iter::once((None, &parsed_root.responses)).chain(listeners);
 
-        let mut response_paths = Vec::new();
+        let mut globs = Vec::new();
+        for (listener, responses) in response_sets {
+            for (host, paths) in &responses.hosts {
+                for path in paths {
+                    globs.push((listener.clone(), Some(host.clone()), path));
+                }
+            }
+            for path in &responses.paths {
+                globs.push((listener.clone(), None, path));
+            }
+        }
 
-        for config_path in &parsed_root.responses.paths {
+        let mut found = Vec::new();
+        for (listener, host, config_path) in globs {
             let glob_path = format!("{}/{}", self.base_config_dir.display(), config_path);
 
             debug!("Glob path: {}", glob_path);
-            let mut found_files = glob(&glob_path)?.filter_map(|x| x.ok()).collect();
-            response_paths.append(&mut found_files);
+            let found_files = glob(&glob_path)
+                .map_err(|source| ConfigError::Glob {
+                    location: Box::new(ConfigLocation::new(&self.root_config_file)),
+                    pattern: config_path.clone(),
+                    source,
+                })?
+                .filter_map(|x| x.ok())
+                .collect();
+            found.push(FoundFiles {
+                listener,
+                host,
+                glob: config_path.clone(),
+                files: found_files,
+            });
         }
+        Ok(found)
+    }
+}
 
-        let mut parsed_responses = Vec::new();
-        for response_file in response_paths {
-            debug!("Loading dependent config {:?}", response_file);
-            let response_file = response_file.as_path();
-            let response_config = Config::builder()
-                .add_source(config::File::from(response_file))
-                .build()?;
-            let parsed = response_config
-                .try_deserialize::<plugins::ResponseConfig<plugins::ResponseData>>()?;
-            let parent_folder = response_file.parent().expect("partent path to exist");
-
-            parsed_responses.push(parsed.make_static(parent_folder)?);
-        }
+/// The files matching a glob, with the listener and host of the glob.
+struct FoundFiles {
+    listener: Option<String>,
+    host: Option<String>,
+    glob: String,
+    files: Vec<PathBuf>,
+}
 
-        Ok(ParsedUserConfig {
-            root: parsed_root,
-            responses: parsed_responses,
+impl FoundFiles {
+    fn listed(self) -> impl Iterator<Item = ListedFile> {
+        let (listener, host) = (self.listener, self.host);
+        self.files.into_iter().map(move |path| ListedFile {
+            listener: listener.clone(),
+            host: host.clone(),
+            path,
         })
     }
 }
 
+/// A response file, with the listener it is loaded for and the host it is
+/// bound to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedFile {
+    /// `None` is the default listener.
+    pub listener: Option<String>,
+    pub host: Option<String>,
+    pub path: PathBuf,
+}
+
+/// A loaded response file.
+pub struct ResponseFile {
+    pub config: plugins::ResponseConfig<String>,
+    /// Every file read to load the config, starting with the response file.
+    pub sources: Vec<PathBuf>,
+    /// The `fingerprint` of the sources as they were loaded. Data files are
+    /// hashed before they are read, so that a write landing in between is
+    /// picked up by the next reload instead of being taken as loaded.
+    pub fingerprint: u64,
+}
+
+/// Hashes the content of config files, so that unchanged files don't have to
+/// be loaded again. Missing files hash differently from empty ones.
+#[derive(Default)]
+pub(crate) struct SourceHasher(DefaultHasher);
+
+impl SourceHasher {
+    pub(crate) fn add(&mut self, path: &Path, contents: Option<&[u8]>) {
+        path.hash(&mut self.0);
+        contents.hash(&mut self.0);
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+}
+
+/// The fingerprint of the files as they are now, to compare with the one of a
+/// loaded `ResponseFile`.
+pub fn fingerprint(sources: &[PathBuf]) -> u64 {
+    let mut hasher = SourceHasher::default();
+    for source in sources {
+        hasher.add(source, std::fs::read(source).ok().as_deref());
+    }
+    hasher.finish()
+}
+
+/// A warning found while validating, and the file it was found in.
+#[derive(Debug)]
+pub struct ConfigIssue {
+    pub file: PathBuf,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.message)
+    }
+}
+
+/// Everything that could be loaded, along with the problems found on the way.
+#[derive(Default)]
+pub struct ValidatedUserConfig {
+    pub root: Option<user::ServerRootConfig>,
+    /// The loaded responses, in the order the server tries them.
+    pub responses: Vec<ValidatedResponse>,
+    pub errors: Vec<ConfigError>,
+    pub warnings: Vec<ConfigIssue>,
+}
+
+pub struct ValidatedResponse {
+    /// The listener the file is loaded for, `None` being the default listener.
+    pub listener: Option<String>,
+    pub file: PathBuf,
+    pub config: plugins::ResponseConfig<String>,
+}
+
 pub mod prelude {
+    pub use crate::error::{ConfigError, ConfigLocation};
+    pub use crate::plugins::grpc::*;
+    pub use crate::plugins::smtp::*;
     pub use crate::plugins::static_http::*;
-    pub use crate::plugins::{ResponseConfig, ResponseData};
-    pub use crate::{ConfigContainer, ParsedUserConfig};
+    pub use crate::plugins::tcp::*;
+    pub use crate::plugins::websocket::*;
+    pub use crate::plugins::{never_picked, ResponseConfig, ResponseData, Weighted};
+    pub use crate::user::{
+        FallbackResponseConfig, HttpServerConfig, ListenerConfig, ListenerProtocol,
+        ResponsesConfig, ServerRootConfig, TcpFramingConfig, TlsConfig, QUORRA_HEADERS,
+    };
+    pub use crate::{
+        fingerprint, ConfigContainer, ConfigIssue, ListedFile, ParsedUserConfig, ResponseFile,
+        ValidatedResponse, ValidatedUserConfig,
+    };
 }
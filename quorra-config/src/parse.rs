@@ -0,0 +1,237 @@
+use crate::error::{ConfigError, ConfigLocation};
+use crate::plugins::{
+    grpc::GrpcConfig,
+    smtp::SmtpConfig,
+    static_http::{StaticHttpConfig, StaticResponseBodyConfig},
+    tcp::TcpConfig,
+    websocket::WebsocketConfig,
+    MakeStatic, ResponseConfig, ResponseData,
+};
+use crate::{ResponseFile, SourceHasher};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+enum Format {
+    Yaml,
+    Toml,
+}
+
+/// Reads and deserializes a config file, keeping track of the key that failed.
+pub(crate) fn parse_file<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let (format, contents) = read_file(path)?;
+    deserialize(path, &format, &contents)
+}
+
+/// Loads a response file, along with every data file it references.
+pub(crate) fn load_response_file(path: &Path) -> Result<ResponseFile, ConfigError> {
+    debug!("Loading dependent config {:?}", path);
+    let (format, contents) = read_file(path)?;
+
+    // The `type` tag is read on its own, so that errors inside the payload
+    // keep their key path and position.
+    let tag: ResponseTypeTag = deserialize(path, &format, &contents)?;
+    let parsed = match tag.kind.as_str() {
+        "static-http" => ResponseConfig::StaticHttp(deserialize::<StaticHttpConfig<ResponseData>>(
+            path, &format, &contents,
+        )?),
+        "websocket" => {
+            ResponseConfig::Websocket(deserialize::<WebsocketConfig>(path, &format, &contents)?)
+        }
+        "grpc" => ResponseConfig::Grpc(deserialize::<GrpcConfig>(path, &format, &contents)?),
+        "tcp" => ResponseConfig::Tcp(deserialize::<TcpConfig>(path, &format, &contents)?),
+        "smtp" => ResponseConfig::Smtp(deserialize::<SmtpConfig>(path, &format, &contents)?),
+        other => {
+            return Err(ConfigError::UnknownType {
+                location: Box::new(ConfigLocation {
+                    line: find_key_line(&contents, "type"),
+                    key_path: Some("type".to_string()),
+                    ..ConfigLocation::new(path)
+                }),
+                name: other.to_string(),
+            })
+        }
+    };
+
+    let parent_folder = path.parent().expect("partent path to exist");
+    let mut sources = vec![path.to_path_buf()];
+    sources.extend(data_files(&parsed, parent_folder));
+    let mut hasher = SourceHasher::default();
+    hasher.add(path, Some(contents.as_bytes()));
+    for source in &sources[1..] {
+        hasher.add(source, std::fs::read(source).ok().as_deref());
+    }
+
+    let config = parsed.make_static(parent_folder).map_err(|e| {
+        let line = match &e {
+            ConfigError::DataFile { file, .. } => file
+                .file_name()
+                .and_then(|name| find_text_line(&contents, &name.to_string_lossy())),
+            _ => None,
+        };
+        e.in_file(path, line)
+    })?;
+    Ok(ResponseFile {
+        config,
+        sources,
+        fingerprint: hasher.finish(),
+    })
+}
+
+fn data_files(config: &ResponseConfig<ResponseData>, parent_folder: &Path) -> Vec<PathBuf> {
+    let responses = match config {
+        ResponseConfig::StaticHttp(http) => &http.responses,
+        ResponseConfig::Websocket(_) | ResponseConfig::Tcp(_) | ResponseConfig::Smtp(_) => {
+            return Vec::new()
+        }
+        ResponseConfig::Grpc(grpc) => return vec![parent_folder.join(&grpc.descriptor)],
+    };
+    responses
+        .iter()
+        .filter_map(|response| match &response.body {
+            Some(StaticResponseBodyConfig::Raw(data))
+            | Some(StaticResponseBodyConfig::Json(data))
+            | Some(StaticResponseBodyConfig::Binary(data)) => Some(data),
+            Some(StaticResponseBodyConfig::Sse(_))
+            | Some(StaticResponseBodyConfig::Empty)
+            | None => None,
+        })
+        .filter_map(|data| match data {
+            ResponseData::File(file) => Some(parent_folder.join(file)),
+            ResponseData::Data(_) => None,
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct ResponseTypeTag {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+fn read_file(path: &Path) -> Result<(Format, String), ConfigError> {
+    let format = match path.extension().and_then(|x| x.to_str()) {
+        Some("yaml") | Some("yml") => Format::Yaml,
+        Some("toml") => Format::Toml,
+        _ => {
+            return Err(ConfigError::UnsupportedFormat {
+                location: Box::new(ConfigLocation::new(path)),
+            })
+        }
+    };
+
+    let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        location: Box::new(ConfigLocation::new(path)),
+        source,
+    })?;
+    Ok((format, contents))
+}
+
+fn deserialize<T: DeserializeOwned>(
+    path: &Path,
+    format: &Format,
+    contents: &str,
+) -> Result<T, ConfigError> {
+    let (key_path, line, column, message) = match format {
+        Format::Yaml => {
+            match serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(contents)) {
+                Ok(parsed) => return Ok(parsed),
+                Err(e) => {
+                    let key_path = e.path().to_string();
+                    let inner = e.into_inner();
+                    let location = inner.location();
+                    (
+                        key_path,
+                        location.as_ref().map(|x| x.line()),
+                        location.as_ref().map(|x| x.column()),
+                        inner.to_string(),
+                    )
+                }
+            }
+        }
+        Format::Toml => {
+            match serde_path_to_error::deserialize(&mut toml::Deserializer::new(contents)) {
+                Ok(parsed) => return Ok(parsed),
+                Err(e) => {
+                    let key_path = e.path().to_string();
+                    let inner = e.into_inner();
+                    let position = inner.line_col();
+                    (
+                        key_path,
+                        position.map(|(line, _)| line + 1),
+                        position.map(|(_, column)| column + 1),
+                        inner.to_string(),
+                    )
+                }
+            }
+        }
+    };
+
+    let message = strip_position(&message, &key_path);
+    Err(ConfigError::Parse {
+        location: Box::new(ConfigLocation {
+            path: path.to_path_buf(),
+            line,
+            column,
+            // An empty path is displayed as `.`
+            key_path: Some(key_path).filter(|x| x != "."),
+        }),
+        message,
+    })
+}
+
+/// The position and key are already part of the location, drop them from the message.
+fn strip_position(message: &str, key_path: &str) -> String {
+    let mut message = message
+        .strip_prefix(&format!("{}: ", key_path))
+        .unwrap_or(message)
+        .to_string();
+    if let Some(index) = message.find(" for key `") {
+        message.truncate(index);
+    }
+    if let Some(start) = message.find(" at line ") {
+        let end = message[start..]
+            .find(',')
+            .map(|x| start + x)
+            .unwrap_or(message.len());
+        message.replace_range(start..end, "");
+    }
+    message
+}
+
+/// The first line setting `key`, ignoring indentation.
+fn find_key_line(contents: &str, key: &str) -> Option<usize> {
+    contents
+        .lines()
+        .position(|line| {
+            line.trim_start()
+                .trim_start_matches("- ")
+                .strip_prefix(key)
+                .map(|rest| rest.trim_start().starts_with([':', '=']))
+                .unwrap_or_default()
+        })
+        .map(|index| index + 1)
+}
+
+fn find_text_line(contents: &str, text: &str) -> Option<usize> {
+    contents
+        .lines()
+        .position(|line| line.contains(text))
+        .map(|index| index + 1)
+}
+
+#[test]
+fn test_deserialize_reports_key_path() {
+    let contents = "id: a\nmatches: []\nresponses:\n  - status: 200\n    body:\n      type: rw\n";
+    let error =
+        deserialize::<StaticHttpConfig<ResponseData>>(Path::new("a.yaml"), &Format::Yaml, contents)
+            .unwrap_err();
+
+    let location = error.location();
+    assert_eq!(Some("responses[0].body.type"), location.key_path.as_deref());
+    assert_eq!((Some(6), Some(13)), (location.line, location.column));
+    assert_eq!(
+        "a.yaml:6:13 at `responses[0].body.type`: unknown variant `rw`, expected one of `raw`, `json`, `binary`, `sse`, `Empty`",
+        error.to_string()
+    );
+}
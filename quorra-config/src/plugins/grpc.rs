@@ -0,0 +1,128 @@
+use super::{
+    static_http::{bind_matchers, default_weight, unique_id, HostMatcher},
+    Weighted,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// Answers the calls to a gRPC method, with messages described by a
+/// `FileDescriptorSet`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct GrpcConfig {
+    /// Unique ID that is included with the response.
+    /// When not provided, a random one will be generated.
+    #[serde(default = "unique_id")]
+    pub id: String,
+
+    /// A `FileDescriptorSet`, like the one written by `protoc --descriptor_set_out`.
+    /// Relative to the config file, and made absolute when loaded.
+    pub descriptor: PathBuf,
+
+    /// The full name of the service, like `acme.users.v1.UserService`.
+    pub service: String,
+
+    /// The name of the method, like `GetUser`.
+    pub method: String,
+
+    /// Ways that the call can be matched against. The call always matches when
+    /// there are none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<GrpcMatchesConfig>,
+
+    pub responses: Vec<GrpcResponseConfig>,
+}
+
+impl GrpcConfig {
+    /// Limits the matchers to calls for `host`, like `StaticHttpConfig::bind_host`.
+    pub fn bind_host(&mut self, host: &str) {
+        bind_matchers(&mut self.matches, host);
+    }
+}
+
+/// Every field that is set has to match.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct GrpcMatchesConfig {
+    /// A regex matched against the host, without the port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// The request message, as JSON, has to contain every field of it with the
+    /// same value. Fields use their names from the `.proto` file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+
+    /// Request metadata, each value being a regex.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl HostMatcher for GrpcMatchesConfig {
+    fn any() -> Self {
+        Self::default()
+    }
+
+    fn host_mut(&mut self) -> &mut Option<String> {
+        &mut self.host
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct GrpcResponseConfig {
+    #[serde(default = "unique_id")]
+    pub id: String,
+    #[serde(default = "default_weight")]
+    pub weight: u16,
+
+    /// The messages sent back, as JSON. Unary methods send at most one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<GrpcMessageConfig>,
+
+    /// The gRPC status code, `0` being OK.
+    #[serde(default)]
+    pub status: u32,
+
+    /// Sent as `grpc-message` along with the status.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message: String,
+
+    /// Sent before the messages.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+
+    /// Sent after the messages, along with the status.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trailers: BTreeMap<String, String>,
+
+    /// How long to wait before responding, in milliseconds.
+    #[serde(default)]
+    pub delay: u64,
+}
+
+impl Weighted for GrpcResponseConfig {
+    fn weight(&self) -> u16 {
+        self.weight
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct GrpcMessageConfig {
+    pub json: Value,
+    /// How long to wait before sending the message, in milliseconds.
+    #[serde(default)]
+    pub delay: u64,
+}
+
+#[test]
+fn test_bind_host() {
+    let mut config: GrpcConfig = serde_yaml::from_str(
+        "descriptor: users.pb\nservice: acme.Users\nmethod: Get\nresponses: []\n",
+    )
+    .unwrap();
+    config.bind_host("users.internal");
+    assert_eq!(Some(r"users\.internal"), config.matches[0].host.as_deref());
+}
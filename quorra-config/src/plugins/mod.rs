@@ -1,16 +1,33 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
 use std::{
     fmt::Debug,
-    fs::read_to_string,
+    fs::{read, read_to_string},
     path::{Path, PathBuf},
 };
 
+use crate::error::ConfigError;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::debug;
 
+pub mod grpc;
+pub mod smtp;
 pub mod static_http;
+pub mod tcp;
+pub mod websocket;
 
 pub trait MakeStatic<T> {
-    fn make_static(&self, file_path: &Path) -> anyhow::Result<T>;
+    fn make_static(&self, file_path: &Path) -> Result<T, ConfigError>;
+}
+
+/// A response that takes turns with the others of its payload, by weight.
+pub trait Weighted {
+    fn weight(&self) -> u16;
+}
+
+/// Whether none of the responses would ever be picked, which leaves the
+/// payload without anything to answer with.
+pub fn never_picked<R: Weighted>(responses: &[R]) -> bool {
+    responses.iter().all(|response| response.weight() == 0)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -22,31 +39,96 @@ pub enum ResponseData {
 }
 
 impl MakeStatic<String> for ResponseData {
-    fn make_static(&self, config_file_path: &Path) -> anyhow::Result<String> {
+    fn make_static(&self, config_file_path: &Path) -> Result<String, ConfigError> {
         match self {
             ResponseData::File(path) => {
                 let file_to_load = config_file_path.join(path);
                 debug!("Loading data file {:?}", file_to_load);
-                Ok(read_to_string(file_to_load)?)
+                read_to_string(&file_to_load).map_err(|e| data_file_error(file_to_load, e))
+            }
+            ResponseData::Data(data) => Ok(data.clone()),
+        }
+    }
+}
+
+impl ResponseData {
+    /// Loads the data as base64. Inline data is expected to already be base64 encoded.
+    pub fn make_static_binary(&self, config_file_path: &Path) -> Result<String, ConfigError> {
+        match self {
+            ResponseData::File(path) => {
+                let file_to_load = config_file_path.join(path);
+                debug!("Loading binary data file {:?}", file_to_load);
+                match read(&file_to_load) {
+                    Ok(bytes) => Ok(STANDARD.encode(bytes)),
+                    Err(e) => Err(data_file_error(file_to_load, e)),
+                }
             }
             ResponseData::Data(data) => Ok(data.clone()),
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// The location is filled in by the loader, which knows the config file.
+fn data_file_error(file: PathBuf, source: std::io::Error) -> ConfigError {
+    ConfigError::DataFile {
+        location: Default::default(),
+        file,
+        source,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", bound = "T: Serialize + DeserializeOwned")]
 pub enum ResponseConfig<T> {
     #[serde(rename = "static-http")]
     StaticHttp(static_http::StaticHttpConfig<T>),
+    #[serde(rename = "websocket")]
+    Websocket(websocket::WebsocketConfig),
+    #[serde(rename = "grpc")]
+    Grpc(grpc::GrpcConfig),
+    #[serde(rename = "tcp")]
+    Tcp(tcp::TcpConfig),
+    #[serde(rename = "smtp")]
+    Smtp(smtp::SmtpConfig),
+}
+
+impl<T> ResponseConfig<T> {
+    /// The id of the payload, logged with everything it answers.
+    pub fn id(&self) -> &str {
+        match self {
+            Self::StaticHttp(plugin) => &plugin.id,
+            Self::Websocket(plugin) => &plugin.id,
+            Self::Grpc(plugin) => &plugin.id,
+            Self::Tcp(plugin) => &plugin.id,
+            Self::Smtp(plugin) => &plugin.id,
+        }
+    }
+
+    /// Limits the response to requests for `host`, see `[responses.hosts]`.
+    pub fn bind_host(&mut self, host: &str) {
+        match self {
+            Self::StaticHttp(plugin) => plugin.bind_host(host),
+            Self::Websocket(plugin) => static_http::bind_matchers(&mut plugin.matches, host),
+            Self::Grpc(plugin) => plugin.bind_host(host),
+            // Neither raw TCP nor SMTP has a host to match.
+            Self::Tcp(_) | Self::Smtp(_) => {}
+        }
+    }
 }
 
 impl MakeStatic<ResponseConfig<String>> for ResponseConfig<ResponseData> {
-    fn make_static(&self, file_path: &Path) -> anyhow::Result<ResponseConfig<String>> {
+    fn make_static(&self, file_path: &Path) -> Result<ResponseConfig<String>, ConfigError> {
         match self {
             Self::StaticHttp(plugin) => {
                 Ok(ResponseConfig::StaticHttp(plugin.make_static(file_path)?))
             }
+            Self::Websocket(plugin) => Ok(ResponseConfig::Websocket(plugin.clone())),
+            Self::Grpc(plugin) => Ok(ResponseConfig::Grpc(grpc::GrpcConfig {
+                descriptor: file_path.join(&plugin.descriptor),
+                ..plugin.clone()
+            })),
+            Self::Tcp(plugin) => Ok(ResponseConfig::Tcp(plugin.clone())),
+            Self::Smtp(plugin) => Ok(ResponseConfig::Smtp(plugin.clone())),
         }
     }
 }
@@ -0,0 +1,78 @@
+use super::{
+    static_http::{default_weight, unique_id},
+    Weighted,
+};
+use serde::{Deserialize, Serialize};
+
+/// Answers an SMTP command of an `smtp` listener, to inject failures. Commands
+/// no payload matches are accepted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct SmtpConfig {
+    /// Unique ID, logged with every command it answers.
+    /// When not provided, a random one will be generated.
+    #[serde(default = "unique_id")]
+    pub id: String,
+
+    /// The command that is answered.
+    pub on: SmtpCommand,
+
+    /// A list of ways that the command can be matched against. Every command
+    /// matches when there are none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<SmtpMatchesConfig>,
+
+    pub responses: Vec<SmtpResponseConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SmtpCommand {
+    /// `MAIL FROM`
+    Mail,
+    /// `RCPT TO`, once for every recipient.
+    Rcpt,
+    /// The end of the message, after `DATA`.
+    Data,
+}
+
+/// Every field that is set has to match.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct SmtpMatchesConfig {
+    /// A regex matched against the sender of the envelope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+
+    /// A regex matched against the recipient being added, or against any of
+    /// the recipients on `data`. Can't be used on `mail`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct SmtpResponseConfig {
+    #[serde(default = "unique_id")]
+    pub id: String,
+    #[serde(default = "default_weight")]
+    pub weight: u16,
+
+    /// The reply code, like `451` or `550`. A `2xx` code accepts the command,
+    /// `3xx` codes can't be used.
+    pub code: u16,
+
+    /// The text of the reply. Defaults to one that fits the code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// How long to wait before responding, in milliseconds.
+    #[serde(default)]
+    pub delay: u64,
+}
+
+impl Weighted for SmtpResponseConfig {
+    fn weight(&self) -> u16 {
+        self.weight
+    }
+}
@@ -1,4 +1,5 @@
-use super::{MakeStatic, ResponseData};
+use super::{MakeStatic, ResponseData, Weighted};
+use crate::error::ConfigError;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::serde_as;
 use std::{collections::BTreeMap, path::Path};
@@ -21,10 +22,13 @@ pub struct StaticHttpConfig<T> {
 }
 
 impl MakeStatic<StaticHttpConfig<String>> for StaticHttpConfig<ResponseData> {
-    fn make_static(&self, file_path: &Path) -> anyhow::Result<StaticHttpConfig<String>> {
+    fn make_static(&self, file_path: &Path) -> Result<StaticHttpConfig<String>, ConfigError> {
         let mut responses: Vec<_> = Default::default();
-        for resp in &self.responses {
-            responses.push(resp.make_static(file_path)?);
+        for (index, resp) in self.responses.iter().enumerate() {
+            responses.push(
+                resp.make_static(file_path)
+                    .map_err(|e| e.at_key(&format!("responses[{}]", index)))?,
+            );
         }
 
         Ok(StaticHttpConfig {
@@ -35,6 +39,54 @@ impl MakeStatic<StaticHttpConfig<String>> for StaticHttpConfig<ResponseData> {
     }
 }
 
+impl<T> StaticHttpConfig<T> {
+    /// Limits the payload to requests for `host`, see `bind_matchers`.
+    pub fn bind_host(&mut self, host: &str) {
+        bind_matchers(&mut self.matches, host);
+    }
+}
+
+/// A matcher that can be limited to the requests for a host.
+pub(crate) trait HostMatcher {
+    /// A matcher that lets every request through.
+    fn any() -> Self;
+    fn host_mut(&mut self) -> &mut Option<String>;
+}
+
+impl HostMatcher for StaticMatchesConfig {
+    fn any() -> Self {
+        StaticMatchesConfig {
+            path: ".*".to_string(),
+            host: None,
+            protocol: None,
+            query: Default::default(),
+            headers: Default::default(),
+            methods: Default::default(),
+            graphql: None,
+            body: None,
+        }
+    }
+
+    fn host_mut(&mut self) -> &mut Option<String> {
+        &mut self.host
+    }
+}
+
+/// Limits the matchers to requests for `host`, unless a matcher already has
+/// its own `host`. Without matchers, one matching any request is added. The
+/// `host` matchers are regexes, so the name is escaped.
+pub(crate) fn bind_matchers<M: HostMatcher>(matches: &mut Vec<M>, host: &str) {
+    if matches.is_empty() {
+        matches.push(M::any());
+    }
+    for matcher in matches {
+        let bound = matcher.host_mut();
+        if bound.is_none() {
+            *bound = Some(regex::escape(host));
+        }
+    }
+}
+
 /// The possible options to match against. All fields are optional. When all
 /// fields are missing, the request will match.
 #[serde_as]
@@ -45,6 +97,16 @@ pub struct StaticMatchesConfig {
     #[serde(default)]
     pub path: String,
 
+    /// A regex matched against the host of the request, from the URI authority or
+    /// the `Host` header, without the port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// A regex matched against the protocol of the request, one of `HTTP/1.0`,
+    /// `HTTP/1.1` or `HTTP/2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+
     /// A list of String=String pairs that will be matched against the query params
     #[serde_as(as = "serde_with::Map<_, _>")]
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -62,6 +124,10 @@ pub struct StaticMatchesConfig {
     /// Configuration for GraphQL body matchers
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub graphql: Option<GraphqlStaticMatchConfig>,
+
+    /// Configuration for generic body matchers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<BodyStaticMatchConfig>,
 }
 
 /// GraphQL body matcher
@@ -73,6 +139,20 @@ pub struct GraphqlStaticMatchConfig {
     pub operation_name: String,
 }
 
+/// Request body matcher. When both fields are set, both must match.
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, Ord, PartialOrd, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BodyStaticMatchConfig {
+    /// A regex to be used to match against the request body.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+
+    /// A JSON document. The request body must be JSON and contain every field
+    /// in this document with the same value. Extra fields in the request are ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 #[serde(bound = "T: Serialize + DeserializeOwned")]
 pub struct StaticResponseConfig<T> {
@@ -83,23 +163,37 @@ pub struct StaticResponseConfig<T> {
     pub status: u16,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub headers: BTreeMap<String, String>,
+    /// Values for `Set-Cookie`, each sent as its own header.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cookies: Vec<String>,
+    /// Headers sent after the body. Only HTTP/2 clients receive them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trailers: BTreeMap<String, String>,
     #[serde(default)]
     pub body: Option<StaticResponseBodyConfig<T>>,
     #[serde(default)]
     pub delay: u64,
 }
 
+impl<T> Weighted for StaticResponseConfig<T> {
+    fn weight(&self) -> u16 {
+        self.weight
+    }
+}
+
 impl MakeStatic<StaticResponseConfig<String>> for StaticResponseConfig<ResponseData> {
-    fn make_static(&self, file_path: &Path) -> anyhow::Result<StaticResponseConfig<String>> {
+    fn make_static(&self, file_path: &Path) -> Result<StaticResponseConfig<String>, ConfigError> {
         let body = match &self.body {
             None => None,
-            Some(body) => Some(body.make_static(file_path)?),
+            Some(body) => Some(body.make_static(file_path).map_err(|e| e.at_key("body"))?),
         };
         Ok(StaticResponseConfig {
             id: self.id.clone(),
             weight: self.weight,
             status: self.status,
             headers: self.headers.clone(),
+            cookies: self.cookies.clone(),
+            trailers: self.trailers.clone(),
             body,
             delay: self.delay,
         })
@@ -107,17 +201,113 @@ impl MakeStatic<StaticResponseConfig<String>> for StaticResponseConfig<ResponseD
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
-#[serde(tag = "type", bound = "T: Serialize + DeserializeOwned")]
+#[serde(
+    tag = "type",
+    try_from = "BodyConfigFields<T>",
+    bound = "T: Serialize + DeserializeOwned"
+)]
 pub enum StaticResponseBodyConfig<T> {
     #[serde(rename = "raw")]
     Raw(T),
     #[serde(rename = "json")]
     Json(T),
+    /// Sent as-is without templating. Inline `data` is base64 encoded, a `file` is read as bytes.
+    #[serde(rename = "binary")]
+    Binary(T),
+    /// Server-sent events, streamed as `text/event-stream`.
+    #[serde(rename = "sse")]
+    Sse(SseBodyConfig),
+    Empty,
+}
+
+/// A stream of server-sent events, sent one at a time as they come due.
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub struct SseBodyConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<SseEventConfig>,
+
+    /// How many times the events are played, `0` plays them forever.
+    #[serde(default = "default_times")]
+    pub times: u32,
+
+    /// Sent before the first event, telling the client how long to wait
+    /// before reconnecting, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<u64>,
+
+    /// Keeps the connection open once every event is sent, instead of closing it.
+    #[serde(default)]
+    pub keep_open: bool,
+
+    /// Ends the stream this many milliseconds after it started, even when
+    /// events are left.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close_after: Option<u64>,
+}
+
+/// A single event. Every field is a template.
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct SseEventConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    pub data: String,
+    /// How long to wait before sending the event, in milliseconds.
+    #[serde(default)]
+    pub delay: u64,
+}
+
+#[derive(Deserialize)]
+enum BodyType {
+    #[serde(rename = "raw")]
+    Raw,
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "binary")]
+    Binary,
+    #[serde(rename = "sse")]
+    Sse,
     Empty,
 }
 
+/// Read with a plain `type` field instead of serde's internal tagging, which
+/// buffers the body and loses the position of a bad `type`.
+#[derive(Deserialize)]
+#[serde(bound = "T: DeserializeOwned")]
+struct BodyConfigFields<T> {
+    #[serde(rename = "type")]
+    kind: BodyType,
+    #[serde(flatten)]
+    data: Option<T>,
+    #[serde(flatten)]
+    sse: SseBodyConfig,
+}
+
+impl<T> TryFrom<BodyConfigFields<T>> for StaticResponseBodyConfig<T> {
+    type Error = &'static str;
+
+    fn try_from(fields: BodyConfigFields<T>) -> Result<Self, Self::Error> {
+        match (fields.kind, fields.data) {
+            (BodyType::Empty, _) => Ok(StaticResponseBodyConfig::Empty),
+            (BodyType::Sse, _) if fields.sse.events.is_empty() => {
+                Err("an `sse` body needs at least one of `events`")
+            }
+            (BodyType::Sse, _) => Ok(StaticResponseBodyConfig::Sse(fields.sse)),
+            (BodyType::Raw, Some(data)) => Ok(StaticResponseBodyConfig::Raw(data)),
+            (BodyType::Json, Some(data)) => Ok(StaticResponseBodyConfig::Json(data)),
+            (BodyType::Binary, Some(data)) => Ok(StaticResponseBodyConfig::Binary(data)),
+            (_, None) => Err("body needs either `data` or `file`"),
+        }
+    }
+}
+
 impl MakeStatic<StaticResponseBodyConfig<String>> for StaticResponseBodyConfig<ResponseData> {
-    fn make_static(&self, file_path: &Path) -> anyhow::Result<StaticResponseBodyConfig<String>> {
+    fn make_static(
+        &self,
+        file_path: &Path,
+    ) -> Result<StaticResponseBodyConfig<String>, ConfigError> {
         match self {
             StaticResponseBodyConfig::Json(json) => {
                 Ok(StaticResponseBodyConfig::Json(json.make_static(file_path)?))
@@ -125,15 +315,81 @@ impl MakeStatic<StaticResponseBodyConfig<String>> for StaticResponseBodyConfig<R
             StaticResponseBodyConfig::Raw(raw) => {
                 Ok(StaticResponseBodyConfig::Raw(raw.make_static(file_path)?))
             }
+            StaticResponseBodyConfig::Binary(binary) => Ok(StaticResponseBodyConfig::Binary(
+                binary.make_static_binary(file_path)?,
+            )),
+            StaticResponseBodyConfig::Sse(sse) => Ok(StaticResponseBodyConfig::Sse(sse.clone())),
             StaticResponseBodyConfig::Empty => Ok(StaticResponseBodyConfig::Empty),
         }
     }
 }
 
-fn default_weight() -> u16 {
+impl SseBodyConfig {
+    /// One play of the events, untemplated and without the delays.
+    pub fn to_event_stream(&self) -> String {
+        self.events
+            .iter()
+            .map(|event| format_sse_event(event.id.as_deref(), event.event.as_deref(), &event.data))
+            .collect()
+    }
+}
+
+/// Formats an event for `text/event-stream`, with a `data` line for each line
+/// of the data.
+pub fn format_sse_event(id: Option<&str>, event: Option<&str>, data: &str) -> String {
+    let mut formatted = String::new();
+    if let Some(id) = id {
+        formatted.push_str(&format!("id: {}\n", id));
+    }
+    if let Some(event) = event {
+        formatted.push_str(&format!("event: {}\n", event));
+    }
+    for line in data.lines() {
+        formatted.push_str(&format!("data: {}\n", line));
+    }
+    if data.is_empty() {
+        formatted.push_str("data:\n");
+    }
+    formatted.push('\n');
+    formatted
+}
+
+pub(crate) fn default_weight() -> u16 {
+    1
+}
+
+fn default_times() -> u32 {
     1
 }
 
 pub fn unique_id() -> String {
     uuid::Uuid::new_v4().to_string()
 }
+
+#[test]
+fn test_bind_host() {
+    let mut config: StaticHttpConfig<String> = StaticHttpConfig {
+        id: "a".to_string(),
+        matches: vec![],
+        responses: vec![],
+    };
+    config.bind_host("api.example.test");
+    assert_eq!(
+        Some(r"api\.example\.test"),
+        config.matches[0].host.as_deref()
+    );
+    assert_eq!(".*", config.matches[0].path);
+
+    config.matches[0].host = Some(".*".to_string());
+    config.bind_host("other.test");
+    assert_eq!(Some(".*"), config.matches[0].host.as_deref());
+}
+
+#[test]
+fn test_format_sse_event() {
+    assert_eq!(
+        "id: 1\nevent: token\ndata: {\"a\":\ndata: 1}\n\n",
+        format_sse_event(Some("1"), Some("token"), "{\"a\":\n1}")
+    );
+    assert_eq!("data:\n\n", format_sse_event(None, None, ""));
+}
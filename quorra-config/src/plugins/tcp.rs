@@ -0,0 +1,81 @@
+use super::{
+    static_http::{default_weight, unique_id},
+    Weighted,
+};
+use serde::{Deserialize, Serialize};
+
+/// Answers the frames read by a `tcp` listener.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct TcpConfig {
+    /// Unique ID, logged with every frame it answers.
+    /// When not provided, a random one will be generated.
+    #[serde(default = "unique_id")]
+    pub id: String,
+
+    /// A list of ways that a frame can be matched against. Every frame matches
+    /// when there are none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<TcpMatchesConfig>,
+
+    pub responses: Vec<TcpResponseConfig>,
+}
+
+/// Every field that is set has to match.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct TcpMatchesConfig {
+    /// A regex searched for in the frame. Its groups can be used in the reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+
+    /// Bytes searched for in the frame, like `02 0a ?? ff`. `??` matches any byte.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hex: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct TcpResponseConfig {
+    #[serde(default = "unique_id")]
+    pub id: String,
+    #[serde(default = "default_weight")]
+    pub weight: u16,
+
+    /// Sent in order, each framed the way the listener reads frames.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub send: Vec<TcpMessageConfig>,
+
+    /// Closes the connection once the messages are sent.
+    #[serde(default)]
+    pub close: bool,
+
+    /// How long to wait before responding, in milliseconds.
+    #[serde(default)]
+    pub delay: u64,
+}
+
+impl Weighted for TcpResponseConfig {
+    fn weight(&self) -> u16 {
+        self.weight
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct TcpMessageConfig {
+    #[serde(flatten)]
+    pub data: TcpDataConfig,
+    /// How long to wait before sending, in milliseconds.
+    #[serde(default)]
+    pub delay: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum TcpDataConfig {
+    /// A template.
+    Text(String),
+    /// Hex encoded bytes, whitespace is ignored. Not templated.
+    Hex(String),
+}
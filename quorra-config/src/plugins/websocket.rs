@@ -0,0 +1,108 @@
+use super::static_http::{unique_id, StaticMatchesConfig};
+use serde::{Deserialize, Serialize};
+
+/// A WebSocket endpoint. Once the upgrade request matches, the connection plays
+/// a scripted conversation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WebsocketConfig {
+    /// Unique ID that is included with the upgrade response.
+    /// When not provided, a random one will be generated.
+    #[serde(default = "unique_id")]
+    pub id: String,
+
+    /// A list of ways that the upgrade request can be matched against.
+    pub matches: Vec<StaticMatchesConfig>,
+
+    /// Messages sent as soon as the connection is upgraded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_connect: Vec<WebsocketMessageConfig>,
+
+    /// Checked in order against every message from the client, the first reply
+    /// that matches answers it. Messages without a reply are ignored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replies: Vec<WebsocketReplyConfig>,
+
+    /// Messages sent on a timer, counted from the upgrade.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pushes: Vec<WebsocketPushConfig>,
+
+    /// Closes the connection after a while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close: Option<WebsocketCloseConfig>,
+}
+
+/// A message sent to the client. Text messages are templates.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WebsocketMessageConfig {
+    #[serde(flatten)]
+    pub frame: WebsocketFrameConfig,
+    /// How long to wait before sending, in milliseconds.
+    #[serde(default)]
+    pub delay: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebsocketFrameConfig {
+    Text(String),
+    /// Base64 encoded.
+    Binary(String),
+}
+
+/// Answers the messages from the client that match. Every matcher that is set
+/// has to match, a reply without matchers answers every message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WebsocketReplyConfig {
+    /// A regex matched against text messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+
+    /// A JSONPath, like `$.type`, that has to find a value in a JSON message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_path: Option<String>,
+
+    /// When set, one of the values found by `json-path` has to be equal to it.
+    /// Values that aren't strings are compared as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+
+    /// The messages sent back, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub send: Vec<WebsocketMessageConfig>,
+
+    /// Closes the connection once the messages are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close: Option<WebsocketCloseConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WebsocketPushConfig {
+    #[serde(flatten)]
+    pub frame: WebsocketFrameConfig,
+    /// When to send the message, in milliseconds after the upgrade.
+    #[serde(default)]
+    pub after: u64,
+    /// Sends the message again every so many milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WebsocketCloseConfig {
+    /// How long to wait before closing, in milliseconds.
+    #[serde(default)]
+    pub after: u64,
+    #[serde(default = "default_close_code")]
+    pub code: u16,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+}
+
+fn default_close_code() -> u16 {
+    1000
+}
@@ -1,16 +1,212 @@
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, OneOrMany};
+use std::{collections::BTreeMap, path::PathBuf};
 
 /// The root confguration for Quorra providing configuration options
 /// for the different server backends.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct ServerRootConfig {
+    /// Settings for the HTTP server.
+    #[serde(default, skip_serializing_if = "HttpServerConfig::is_default")]
+    pub http: HttpServerConfig,
+    /// The responses of the default listener, on `http.address`.
+    #[serde(default)]
     pub responses: ResponsesConfig,
+    /// Extra listeners, each with their own addresses and responses.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub listeners: BTreeMap<String, ListenerConfig>,
+}
+
+impl ServerRootConfig {
+    /// The default listener is left out when only named listeners have responses.
+    pub fn has_default_listener(&self) -> bool {
+        self.listeners.is_empty() || !self.responses.is_empty()
+    }
+}
+
+/// A named listener, answering on its own addresses with its own responses.
+/// The other `[http]` settings, except `tls`, are shared by every listener.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ListenerConfig {
+    /// One or more addresses to listen on, like `0.0.0.0:4001`.
+    #[serde_as(as = "OneOrMany<_>")]
+    pub address: Vec<String>,
+    pub responses: ResponsesConfig,
+    /// What the listener speaks, HTTP unless set.
+    #[serde(default, skip_serializing_if = "ListenerProtocol::is_http")]
+    pub protocol: ListenerProtocol,
+    /// How a `tcp` listener splits what it reads into frames.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framing: Option<TcpFramingConfig>,
+    /// Serve HTTPS instead of HTTP. Only for `http` listeners.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListenerProtocol {
+    #[default]
+    Http,
+    /// Raw TCP, answered by `tcp` payloads.
+    Tcp,
+    /// An SMTP server keeping every message it accepts. `smtp` payloads
+    /// inject failures.
+    Smtp,
+}
+
+impl ListenerProtocol {
+    fn is_http(&self) -> bool {
+        self == &Self::Http
+    }
+}
+
+/// Frames end with a delimiter, or start with their length.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TcpFramingConfig {
+    /// Like `"\n"` for line based protocols. Left out of the frame.
+    Delimiter(String),
+    /// The size of a big-endian length in front of every frame, 1, 2 or 4 bytes.
+    /// The length doesn't count itself.
+    LengthPrefix(u8),
+}
+
+impl Default for TcpFramingConfig {
+    fn default() -> Self {
+        Self::Delimiter("\n".to_string())
+    }
 }
 
 /// Container of response configs
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct ResponsesConfig {
     /// Glob of paths to load responses from
+    #[serde(default)]
     pub paths: Vec<String>,
+    /// Globs of paths whose responses only answer requests for a host, keyed
+    /// by the host name. They are tried before the responses in `paths`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, Vec<String>>,
+}
+
+impl ResponsesConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.hosts.is_empty()
+    }
+}
+
+/// The headers Quorra adds to every response, to tell which payload answered.
+pub const QUORRA_HEADERS: [&str; 4] = [
+    "x-quorra",
+    "x-quorra-plugin-id",
+    "x-quorra-payload-id",
+    "x-quorra-response-id",
+];
+
+/// Settings for the HTTP server. Every field is optional.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct HttpServerConfig {
+    /// One or more addresses to listen on, like `127.0.0.1:3000`.
+    #[serde_as(as = "OneOrMany<_>")]
+    pub address: Vec<String>,
+
+    /// The largest request body accepted, in bytes. Larger requests get a `413`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_body_size: Option<usize>,
+
+    /// How long a request may take, in milliseconds, including response delays.
+    /// Slower requests get a `408`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_timeout: Option<u64>,
+
+    /// How long a client may take to send the request headers, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_read_timeout: Option<u64>,
+
+    /// Compress responses when the client accepts it.
+    pub compression: bool,
+
+    /// Accept HTTP/2, negotiated with ALPN over TLS, and with prior knowledge
+    /// (h2c) over plain TCP.
+    pub http2: bool,
+
+    /// Which of the `x-quorra-*` headers to add to responses.
+    pub quorra_headers: Vec<String>,
+
+    /// The response sent when no payload matches the request.
+    pub fallback: FallbackResponseConfig,
+
+    /// Serve HTTPS instead of HTTP, on the default listener. Named listeners
+    /// have their own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl HttpServerConfig {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        Self {
+            address: vec!["127.0.0.1:3000".to_string()],
+            max_body_size: None,
+            request_timeout: None,
+            header_read_timeout: None,
+            compression: true,
+            http2: false,
+            quorra_headers: QUORRA_HEADERS.iter().map(|x| x.to_string()).collect(),
+            fallback: Default::default(),
+            tls: None,
+        }
+    }
+}
+
+/// Where the TLS certificates come from. Either `cert` and `key` are set, or
+/// `local-ca-cert` is. Paths are relative to the root config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsConfig {
+    /// A PEM file with the certificate chain, starting with the server's certificate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert: Option<PathBuf>,
+
+    /// A PEM file with the private key of the certificate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+
+    /// Generates a CA on startup, which issues a certificate for every server
+    /// name clients ask for. The CA certificate is written to this path, so that
+    /// clients can trust it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_ca_cert: Option<PathBuf>,
+}
+
+/// The response sent when no payload matches the request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct FallbackResponseConfig {
+    pub status: u16,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub body: String,
+}
+
+impl Default for FallbackResponseConfig {
+    fn default() -> Self {
+        Self {
+            status: 404,
+            headers: Default::default(),
+            body: Default::default(),
+        }
+    }
 }
@@ -1,90 +1,344 @@
+use crate::merge::{merge_entries, MergeChange, MergeReport};
 use crate::models::*;
+use crate::reader::{open_input, read_entries};
+use crate::scrub::Scrubber;
 use anyhow::Error;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lazy_static::lazy_static;
 use quorra_config::prelude::*;
+use regex::Regex;
 use std::{
     collections::BTreeMap,
+    ops::RangeInclusive,
     path::{Path, PathBuf},
 };
-use tracing::info;
+use tracing::{debug, info, warn};
 use url::Url;
 
+#[derive(Clone)]
 pub struct HarConvertor {
-    path: PathBuf,
+    inputs: Vec<PathBuf>,
+    options: HarConvertOptions,
+}
+
+/// Options controlling how HAR entries are turned into matchers and responses.
+#[derive(Debug, Default, Clone)]
+pub struct HarConvertOptions {
+    /// Derive a body matcher from the request `postData`. JSON bodies with an
+    /// `operationName` are matched as GraphQL operations.
+    pub match_body: bool,
+
+    /// Request headers that will be matched on when present in the recording.
+    pub match_headers: Vec<String>,
+
+    /// Replace numeric, UUID and hash path segments with a regex, so recordings of
+    /// `/users/123` and `/users/456` become a single payload.
+    pub generalize_paths: bool,
+
+    /// Only convert requests to these hosts. A leading `*.` matches any subdomain.
+    pub include_hosts: Vec<String>,
+
+    /// Only convert requests whose full URL matches this regex.
+    pub url_filter: Option<Regex>,
+
+    /// Only convert responses whose MIME type starts with one of these values.
+    pub mime_types: Vec<String>,
+
+    /// Only convert responses with a status in this range.
+    pub status_range: Option<RangeInclusive<u16>>,
+
+    /// Write every host into its own subdirectory, matching on the `Host` header,
+    /// and generate a root `config.toml` covering all of them.
+    pub split_by_host: bool,
+
+    /// Response headers to keep in addition to `Content-Type` and
+    /// `Access-Control-Allow-Origin`. `*` keeps every header.
+    pub keep_headers: Vec<String>,
+
+    /// Response headers to always drop, even when kept by `keep_headers`.
+    pub drop_headers: Vec<String>,
+
+    /// Convert the recorded response cookies into `Set-Cookie` headers.
+    pub keep_cookies: bool,
+
+    /// Use the recorded server wait time as the response delay.
+    pub keep_timing: bool,
+
+    /// Replaces sensitive values in every entry before it is converted.
+    pub scrubber: Scrubber,
+
+    /// Update payloads already in the destination instead of writing new files.
+    /// Payloads are recognized by their matcher, and responses by their status.
+    pub merge: bool,
 }
 
 pub struct EntryWrapperWithBody {
-    matcher: StaticMatchesConfig,
-    response_config: StaticResponseConfig<()>,
-    body: Option<String>,
+    /// The recorded path, used to name the generated files.
+    pub(crate) name: String,
+    pub(crate) matcher: StaticMatchesConfig,
+    pub(crate) response_config: StaticResponseConfig<()>,
+    pub(crate) body: Option<EntryBody>,
+}
+
+pub enum EntryBody {
+    Text(String),
+    Binary(Vec<u8>),
 }
 
+/// An entry whose body has already been written to an asset file.
 pub struct EntryWrapper {
-    matcher: StaticMatchesConfig,
-    response_config: StaticResponseConfig<ResponseData>,
+    pub(crate) name: String,
+    pub(crate) matcher: StaticMatchesConfig,
+    pub(crate) response_config: StaticResponseConfig<ResponseData>,
 }
 
+pub(crate) type GroupedEntries = BTreeMap<StaticMatchesConfig, (String, Vec<EntryWrapper>)>;
+
 impl HarConvertor {
-    pub fn new(path: &Path) -> Self {
+    /// Creates a convertor reading the entries of every input in order. An input
+    /// of `-` reads from stdin.
+    pub fn new(inputs: &[PathBuf], options: HarConvertOptions) -> Self {
         Self {
-            path: path.to_owned(),
+            inputs: inputs.to_vec(),
+            options,
+        }
+    }
+
+    pub async fn convert(&self, dest: &Path) -> Result<MergeReport, Error> {
+        let convertor = self.clone();
+        let dest = dest.to_owned();
+        tokio::task::spawn_blocking(move || convertor.convert_blocking(&dest)).await?
+    }
+
+    fn convert_blocking(&self, dest: &Path) -> Result<MergeReport, Error> {
+        let mut converted: BTreeMap<String, Vec<EntryWrapper>> = Default::default();
+        for input in &self.inputs {
+            info!("Reading {}", input.display());
+            read_entries(open_input(input)?, |entry| {
+                if let Some((host, wrapper)) = self.convert_one(dest, entry)? {
+                    converted.entry(host).or_default().push(wrapper);
+                }
+                Ok(())
+            })?;
         }
+
+        let mut report = MergeReport::default();
+        if !self.options.split_by_host {
+            let entries = converted.remove("").unwrap_or_default();
+            self.write(dest, entries, &mut report)?;
+            return Ok(report);
+        }
+
+        let mut paths = Vec::new();
+        for (host, entries) in converted {
+            let dir_name = host_dir_name(&host);
+            self.write(&dest.join(&dir_name), entries, &mut report)?;
+            paths.push(format!("{}/*.yaml", dir_name));
+        }
+
+        write_root_config(dest, paths)?;
+        Ok(report)
     }
 
-    pub async fn convert(&self, dest: &Path) -> Result<(), Error> {
-        let har_contents: HarFile = serde_json::from_str(&std::fs::read_to_string(&self.path)?)?;
-        let mut wrapper_map: BTreeMap<
-            StaticMatchesConfig,
-            Vec<StaticResponseConfig<ResponseData>>,
-        > = Default::default();
+    /// Converts a single entry, writing its body straight away so that only the
+    /// matchers are kept in memory. Returns the host the entry is grouped under.
+    fn convert_one(
+        &self,
+        dest: &Path,
+        mut entry: RequestWrapper,
+    ) -> Result<Option<(String, EntryWrapper)>, Error> {
+        if !self.options.scrubber.is_empty() {
+            self.options.scrubber.scrub_entry(&mut entry)?;
+        }
 
-        for entry in har_contents.log.entries {
-            let converted_config = convert_entry(&entry).await?;
-            let wrapper = write_body(dest, converted_config).await?;
-            wrapper_map
-                .entry(wrapper.matcher)
-                .and_modify(|value| value.push(wrapper.response_config.clone()))
-                .or_insert_with(|| vec![wrapper.response_config]);
+        let url = Url::parse(&entry.request.url)?;
+        if !is_included(&entry, &url, &self.options) {
+            debug!("Skipping {} {}", entry.request.method, url);
+            return Ok(None);
         }
 
-        for (key, value) in wrapper_map {
-            let config = ResponseConfig::StaticHttp(StaticHttpConfig {
-                id: unique_id(),
-                matches: vec![key.clone()],
-                responses: value,
-            });
+        let mut wrapper = convert_entry(&entry, &self.options)?;
+        let (host, dir) = if self.options.split_by_host {
+            let host = host_with_port(&url);
+            wrapper
+                .matcher
+                .headers
+                .insert("Host".to_string(), format!("^{}$", regex::escape(&host)));
+            let dir = dest.join(host_dir_name(&host));
+            (host, dir)
+        } else {
+            (String::new(), dest.to_path_buf())
+        };
 
-            let output = serde_yaml::to_string(&config)?;
+        std::fs::create_dir_all(&dir)?;
+        Ok(Some((host, write_body(&dir, wrapper)?)))
+    }
 
-            let path = if key.path == "/" {
-                "/root.html"
-            } else {
-                &key.path
-            };
-            let filename = path.replace('/', "__");
-            let filename = filename.trim_matches('_');
-            let unique = format!("{:x}", md5::compute(&output));
-
-            let filename = format!(
-                "{}_{}_{}.yaml",
-                key.methods[0],
-                filename,
-                unique[..6].chars().as_str()
-            );
-            let path = dest.join(filename);
-            info!("Writing file {}", path.display().to_string());
-            std::fs::write(path, output)?;
+    fn write(
+        &self,
+        dest: &Path,
+        entries: Vec<EntryWrapper>,
+        report: &mut MergeReport,
+    ) -> Result<(), Error> {
+        if self.options.merge {
+            return merge_entries(dest, entries, report);
         }
 
+        for path in write_entries(dest, entries)? {
+            report.changes.push(MergeChange::PayloadAdded(path));
+        }
         Ok(())
     }
 }
 
-fn matcher_to_filename(wrapper: &EntryWrapperWithBody, unique: String) -> String {
+fn host_dir_name(host: &str) -> String {
+    host.replace(':', "_")
+}
+
+fn host_with_port(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+fn is_included(entry: &RequestWrapper, url: &Url, options: &HarConvertOptions) -> bool {
+    if !options.include_hosts.is_empty() {
+        // Host names aren't case sensitive.
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let host_matches = options.include_hosts.iter().any(|filter| {
+            let filter = filter.to_ascii_lowercase();
+            match filter.strip_prefix("*.") {
+                Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+                None => host == filter,
+            }
+        });
+        if !host_matches {
+            return false;
+        }
+    }
+
+    if let Some(url_filter) = &options.url_filter {
+        if !url_filter.is_match(url.as_str()) {
+            return false;
+        }
+    }
+
+    if !options.mime_types.is_empty() {
+        let mime_type = entry.response.content.mime_type.to_ascii_lowercase();
+        if !options
+            .mime_types
+            .iter()
+            .any(|filter| mime_type.starts_with(&filter.to_ascii_lowercase()))
+        {
+            return false;
+        }
+    }
+
+    if let Some(status_range) = &options.status_range {
+        let status = u16::try_from(entry.response.status).unwrap_or_default();
+        if !status_range.contains(&status) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Writes a root config that loads every host directory. An existing root config
+/// is left alone, as it may hold settings that are not generated.
+fn write_root_config(dest: &Path, paths: Vec<String>) -> Result<(), Error> {
+    let path = dest.join("config.toml");
+    if path.exists() {
+        warn!(
+            "{} already exists, make sure `responses.paths` includes {:?}",
+            path.display(),
+            paths
+        );
+        return Ok(());
+    }
+
+    let root = ServerRootConfig {
+        http: Default::default(),
+        responses: ResponsesConfig {
+            paths,
+            hosts: Default::default(),
+        },
+        listeners: Default::default(),
+    };
+    info!("Writing file {}", path.display());
+    std::fs::write(path, toml::to_string(&root)?)?;
+    Ok(())
+}
+
+/// Groups entries sharing a matcher, keeping the name of the first entry.
+pub(crate) fn group_entries(entries: Vec<EntryWrapper>) -> GroupedEntries {
+    let mut grouped: GroupedEntries = Default::default();
+    for entry in entries {
+        grouped
+            .entry(entry.matcher.clone())
+            .or_insert_with(|| (entry.name.clone(), Vec::new()))
+            .1
+            .push(entry);
+    }
+    grouped
+}
+
+/// Writes the converted entries into `dest`. Entries sharing a matcher are grouped
+/// into a single payload file, and every body is written to its own asset file.
+/// Returns the payload files that were written.
+pub(crate) fn write_entries(
+    dest: &Path,
+    entries: Vec<EntryWrapper>,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut written = Vec::new();
+    for (key, (name, group)) in group_entries(entries) {
+        let value = group
+            .into_iter()
+            .map(|wrapper| wrapper.response_config)
+            .collect();
+
+        let config = ResponseConfig::StaticHttp(StaticHttpConfig {
+            id: unique_id(),
+            matches: vec![key.clone()],
+            responses: value,
+        });
+
+        let output = serde_yaml::to_string(&config)?;
+
+        let path = if name == "/" { "/root.html" } else { &name };
+        let filename = path.replace('/', "__");
+        let filename = filename.trim_matches('_');
+        let unique = format!("{:x}", md5::compute(&output));
+
+        let filename = format!(
+            "{}_{}_{}.yaml",
+            key.methods[0],
+            filename,
+            unique[..6].chars().as_str()
+        );
+        let path = dest.join(filename);
+        info!("Writing file {}", path.display().to_string());
+        std::fs::write(&path, output)?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Only a small set of response headers are kept, the rest are generated by Quorra.
+pub(crate) fn is_kept_response_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Content-Type")
+        || name.eq_ignore_ascii_case("Access-Control-Allow-Origin")
+}
+
+pub(crate) fn matcher_to_filename(wrapper: &EntryWrapperWithBody, unique: String) -> String {
     let request = &wrapper.matcher;
-    let path = if request.path == "/" {
+    let path = if wrapper.name == "/" {
         "/root.html"
     } else {
-        &request.path
+        &wrapper.name
     };
     let filename = path.replace('/', "__");
     let path = filename.trim_matches('_');
@@ -114,67 +368,188 @@ fn matcher_to_filename(wrapper: &EntryWrapperWithBody, unique: String) -> String
     )
 }
 
-async fn write_body(dir: &Path, wrapper: EntryWrapperWithBody) -> Result<EntryWrapper, Error> {
-    let body: Option<StaticResponseBodyConfig<ResponseData>> = match wrapper.body.clone() {
-        Some(mut body_text) => {
-            let unique = format!("{:x}", md5::compute(&body_text));
-            let filename = matcher_to_filename(&wrapper, unique);
-
-            info!("Creating asset file {}", filename);
-
-            if let Ok(json_body) = serde_json::from_str::<serde_json::Value>(&body_text) {
-                if let Ok(pretty_body) = serde_json::to_string_pretty(&json_body) {
-                    body_text = pretty_body;
-                }
+/// The contents written to the asset file. JSON text is pretty printed.
+fn body_contents(body: &EntryBody) -> Vec<u8> {
+    match body {
+        EntryBody::Binary(bytes) => bytes.clone(),
+        EntryBody::Text(body_text) => {
+            match serde_json::from_str::<serde_json::Value>(body_text)
+                .map(|json_body| serde_json::to_string_pretty(&json_body))
+            {
+                Ok(Ok(pretty_body)) => pretty_body.into_bytes(),
+                _ => body_text.clone().into_bytes(),
             }
+        }
+    }
+}
 
-            let path = dir.join(&filename);
-            std::fs::write(path, body_text)?;
+/// Writes the body of the entry into an asset file in `dir`.
+fn write_asset(
+    dir: &Path,
+    wrapper: &EntryWrapperWithBody,
+) -> Result<Option<StaticResponseBodyConfig<ResponseData>>, Error> {
+    let body = match &wrapper.body {
+        Some(body) => body,
+        None => return Ok(None),
+    };
 
-            Some(StaticResponseBodyConfig::Raw(ResponseData::File(
-                filename.into(),
-            )))
-        }
-        None => None,
+    let unique = match body {
+        EntryBody::Binary(bytes) => format!("{:x}", md5::compute(bytes)),
+        EntryBody::Text(body_text) => format!("{:x}", md5::compute(body_text)),
     };
+    let filename = matcher_to_filename(wrapper, unique);
+
+    info!("Creating asset file {}", filename);
+    std::fs::write(dir.join(&filename), body_contents(body))?;
 
-    let response_config: StaticResponseConfig<ResponseData> = StaticResponseConfig {
+    let data = ResponseData::File(filename.into());
+    Ok(Some(match body {
+        EntryBody::Binary(_) => StaticResponseBodyConfig::Binary(data),
+        EntryBody::Text(_) => StaticResponseBodyConfig::Raw(data),
+    }))
+}
+
+/// Writes the body of the entry into an asset file in `dir`, so it doesn't have
+/// to be kept in memory until the payload is written.
+pub(crate) fn write_body(dir: &Path, wrapper: EntryWrapperWithBody) -> Result<EntryWrapper, Error> {
+    let body = write_asset(dir, &wrapper)?;
+
+    let response_config = StaticResponseConfig {
         id: wrapper.response_config.id,
         weight: wrapper.response_config.weight,
         status: wrapper.response_config.status,
         headers: wrapper.response_config.headers,
+        cookies: wrapper.response_config.cookies,
+        trailers: wrapper.response_config.trailers,
         body,
         delay: wrapper.response_config.delay,
     };
     Ok(EntryWrapper {
+        name: wrapper.name,
         matcher: wrapper.matcher,
         response_config,
     })
 }
 
-async fn convert_entry(wrapper: &RequestWrapper) -> Result<EntryWrapperWithBody, Error> {
-    let request_matcher = covert_request(&wrapper.request).await?;
-    let (response_config, body) = covert_response(&wrapper.response).await?;
+fn convert_entry(
+    wrapper: &RequestWrapper,
+    options: &HarConvertOptions,
+) -> Result<EntryWrapperWithBody, Error> {
+    let (name, request_matcher) = covert_request(&wrapper.request, options)?;
+    let (mut response_config, body) = covert_response(&wrapper.response, options)?;
+
+    if options.keep_timing {
+        let wait = wrapper
+            .timings
+            .as_ref()
+            .and_then(|timings| timings.wait)
+            .filter(|wait| *wait >= 0.0)
+            .or(wrapper.time)
+            .unwrap_or_default();
+        response_config.delay = wait.max(0.0).round() as u64;
+    }
 
     Ok(EntryWrapperWithBody {
+        name,
         matcher: request_matcher,
         response_config,
         body,
     })
 }
 
-async fn covert_response(
+/// Headers that describe the recorded transfer rather than the response, Quorra
+/// sets these itself.
+const TRANSFER_HEADERS: &[&str] = &[
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+    "connection",
+    "keep-alive",
+    "set-cookie",
+];
+
+fn keep_response_header(name: &str, options: &HarConvertOptions) -> bool {
+    if name.starts_with(':')
+        || TRANSFER_HEADERS
+            .iter()
+            .any(|x| name.eq_ignore_ascii_case(x))
+    {
+        return false;
+    }
+
+    if options
+        .drop_headers
+        .iter()
+        .any(|x| name.eq_ignore_ascii_case(x))
+    {
+        return false;
+    }
+
+    is_kept_response_header(name)
+        || options
+            .keep_headers
+            .iter()
+            .any(|x| x == "*" || name.eq_ignore_ascii_case(x))
+}
+
+/// The cookie `Domain` is left out, the mock is not served from the recorded domain.
+fn cookie_to_header(cookie: &Cookie) -> String {
+    let mut header = format!("{}={}", cookie.name, cookie.value);
+    if let Some(path) = &cookie.path {
+        header.push_str(&format!("; Path={}", path));
+    }
+    if let Some(expires) = &cookie.expires {
+        header.push_str(&format!("; Expires={}", expires));
+    }
+    if cookie.http_only.unwrap_or_default() {
+        header.push_str("; HttpOnly");
+    }
+    if cookie.secure.unwrap_or_default() {
+        header.push_str("; Secure");
+    }
+    if let Some(same_site) = &cookie.same_site {
+        header.push_str(&format!("; SameSite={}", same_site));
+    }
+    header
+}
+
+fn decode_content(content: &Content) -> Result<Option<EntryBody>, Error> {
+    let text = match &content.text {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+
+    match content.encoding.as_deref() {
+        Some("base64") => {
+            let bytes = STANDARD.decode(text.trim())?;
+            Ok(Some(match String::from_utf8(bytes) {
+                Ok(text) => EntryBody::Text(text),
+                Err(e) => EntryBody::Binary(e.into_bytes()),
+            }))
+        }
+        _ => Ok(Some(EntryBody::Text(text.clone()))),
+    }
+}
+
+fn covert_response(
     response: &ResponseEntry,
-) -> Result<(StaticResponseConfig<()>, Option<String>), Error> {
+    options: &HarConvertOptions,
+) -> Result<(StaticResponseConfig<()>, Option<EntryBody>), Error> {
     let status = response.status;
     let mut header_map: BTreeMap<String, String> = Default::default();
     for header in &response.headers {
-        if header.name == "Content-Type" || header.name == "Access-Control-Allow-Origin" {
+        if keep_response_header(&header.name, options) {
             header_map.insert(header.name.clone(), header.value.clone());
         }
     }
 
-    let body_text = response.content.text.clone();
+    let cookies = if options.keep_cookies {
+        response.cookies.iter().map(cookie_to_header).collect()
+    } else {
+        Vec::new()
+    };
+
+    let body_content = decode_content(&response.content)?;
     let body = if response.content.mime_type == "application/json" {
         StaticResponseBodyConfig::Json(())
     } else {
@@ -186,29 +561,294 @@ async fn covert_response(
             status: status as u16,
             weight: 1,
             headers: header_map,
+            cookies,
+            trailers: Default::default(),
             id: unique_id(),
-            body: body_text.clone().map(|_| body),
+            body: body_content.as_ref().map(|_| body),
             delay: 0,
         },
-        body_text,
+        body_content,
     ))
 }
 
-async fn covert_request(request: &RequestEntry) -> Result<StaticMatchesConfig, Error> {
+fn covert_request(
+    request: &RequestEntry,
+    options: &HarConvertOptions,
+) -> Result<(String, StaticMatchesConfig), Error> {
     let mut query_params: Vec<(String, String)> = Default::default();
     for query in &request.query_string {
-        query_params.push((query.name.clone(), query.value.clone()));
+        query_params.push((
+            query.name.clone(),
+            format!("^{}$", regex::escape(&query.value)),
+        ));
     }
 
+    let mut headers: BTreeMap<String, String> = Default::default();
+    for name in &options.match_headers {
+        if let Some(header) = request
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+        {
+            headers.insert(name.clone(), format!("^{}$", regex::escape(&header.value)));
+        }
+    }
+
+    let (graphql, body) = match &request.post_data {
+        Some(post_data) if options.match_body => convert_post_data(post_data),
+        _ => (None, None),
+    };
+
     let method = request.method.clone();
     let url = Url::parse(&request.url)?;
-    let path = url.path();
-
-    Ok(StaticMatchesConfig {
-        headers: Default::default(),
-        query: query_params,
-        path: path.to_string(),
-        methods: vec![method],
-        graphql: None,
-    })
+    let (name, path) = convert_path(url.path(), options.generalize_paths);
+
+    Ok((
+        name,
+        StaticMatchesConfig {
+            headers,
+            query: query_params,
+            path,
+            host: None,
+            protocol: None,
+            methods: vec![method],
+            graphql,
+            body,
+        },
+    ))
+}
+
+lazy_static! {
+    static ref GENERALIZED_SEGMENTS: Vec<(Regex, &'static str, &'static str)> = vec![
+        (Regex::new("^[0-9]+$").unwrap(), "[0-9]+", "{id}"),
+        (
+            Regex::new(
+                "^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$"
+            )
+            .unwrap(),
+            "[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}",
+            "{uuid}"
+        ),
+        (
+            Regex::new("^[0-9a-fA-F]{16,}$").unwrap(),
+            "[0-9a-fA-F]+",
+            "{hash}"
+        ),
+    ];
+}
+
+/// Turns a recorded path into a regex for `StaticMatchesConfig::path`, along with a
+/// readable name for the generated files. When `generalize` is set, id-like segments
+/// are replaced with a regex matching any value of the same kind.
+fn convert_path(path: &str, generalize: bool) -> (String, String) {
+    let mut names = Vec::new();
+    let mut regexes = Vec::new();
+    for segment in path.split('/') {
+        let generalized = GENERALIZED_SEGMENTS
+            .iter()
+            .find(|(matcher, _, _)| generalize && matcher.is_match(segment));
+        match generalized {
+            Some((_, regex, name)) => {
+                regexes.push(regex.to_string());
+                names.push(name.to_string());
+            }
+            None => {
+                regexes.push(regex::escape(segment));
+                names.push(segment.to_string());
+            }
+        }
+    }
+
+    (names.join("/"), regexes.join("/"))
+}
+
+/// Builds the body matchers for a recorded request. GraphQL requests are matched by
+/// operation name and variables, other JSON bodies by their content and everything
+/// else by the exact text.
+fn convert_post_data(
+    post_data: &PostData,
+) -> (
+    Option<GraphqlStaticMatchConfig>,
+    Option<BodyStaticMatchConfig>,
+) {
+    let text = match &post_data.text {
+        Some(text) if !text.is_empty() => text,
+        _ => return (None, None),
+    };
+
+    if let Ok(json_body) = serde_json::from_str::<serde_json::Value>(text) {
+        if let Some(operation_name) = json_body["operationName"].as_str() {
+            let graphql = GraphqlStaticMatchConfig {
+                operation_name: format!("^{}$", regex::escape(operation_name)),
+            };
+            let body = match json_body.get("variables") {
+                Some(variables) if !variables.is_null() => Some(BodyStaticMatchConfig {
+                    regex: None,
+                    json: Some(serde_json::json!({ "variables": variables }).to_string()),
+                }),
+                _ => None,
+            };
+            return (Some(graphql), body);
+        }
+
+        if json_body.is_object() || json_body.is_array() {
+            return (
+                None,
+                Some(BodyStaticMatchConfig {
+                    regex: None,
+                    json: Some(json_body.to_string()),
+                }),
+            );
+        }
+    }
+
+    (
+        None,
+        Some(BodyStaticMatchConfig {
+            regex: Some(format!("^{}$", regex::escape(text))),
+            json: None,
+        }),
+    )
+}
+
+#[test]
+fn test_convert_post_data_graphql() {
+    let post_data = PostData {
+        mime_type: "application/json".to_string(),
+        text: Some(
+            r#"{"operationName": "getUser", "query": "query getUser { id }", "variables": {"id": 1}}"#
+                .to_string(),
+        ),
+    };
+    let (graphql, body) = convert_post_data(&post_data);
+    assert_eq!("^getUser$", graphql.unwrap().operation_name);
+    assert_eq!(
+        Some(r#"{"variables":{"id":1}}"#.to_string()),
+        body.unwrap().json
+    );
+
+    let post_data = PostData {
+        mime_type: "application/x-www-form-urlencoded".to_string(),
+        text: Some("a=1&b=2".to_string()),
+    };
+    let (graphql, body) = convert_post_data(&post_data);
+    assert!(graphql.is_none());
+    assert_eq!(Some("^a=1\\&b=2$".to_string()), body.unwrap().regex);
+}
+
+#[test]
+fn test_convert_path() {
+    assert_eq!(
+        (
+            "/v1.0/users/123".to_string(),
+            "/v1\\.0/users/123".to_string()
+        ),
+        convert_path("/v1.0/users/123", false)
+    );
+    assert_eq!(
+        (
+            "/users/{id}/files/{uuid}/{hash}".to_string(),
+            "/users/[0-9]+/files/[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}/[0-9a-fA-F]+".to_string()
+        ),
+        convert_path(
+            "/users/456/files/0f8fad5b-d9cb-469f-a165-70867728950e/d41d8cd98f00b204e9800998ecf8427e",
+            true
+        )
+    );
+    assert_eq!(
+        ("/users/me".to_string(), "/users/me".to_string()),
+        convert_path("/users/me", true)
+    );
+}
+
+#[test]
+fn test_is_included() {
+    let entry: RequestWrapper = serde_json::from_value(serde_json::json!({
+        "startedDateTime": "2023-05-29T17:29:27.384Z",
+        "request": {
+            "method": "GET",
+            "url": "https://api.example.com/users",
+            "headers": [],
+            "cookies": [],
+            "queryString": []
+        },
+        "response": {
+            "status": 404,
+            "statusText": "Not Found",
+            "headers": [],
+            "cookies": [],
+            "content": { "mimeType": "application/json; charset=utf-8" },
+            "redirectURL": ""
+        }
+    }))
+    .unwrap();
+    let url = Url::parse(&entry.request.url).unwrap();
+
+    let included = |options: HarConvertOptions| is_included(&entry, &url, &options);
+
+    assert!(included(Default::default()));
+    assert!(included(HarConvertOptions {
+        include_hosts: vec!["*.example.com".to_string()],
+        mime_types: vec!["application/json".to_string()],
+        ..Default::default()
+    }));
+    assert!(included(HarConvertOptions {
+        include_hosts: vec!["*.Example.COM".to_string()],
+        ..Default::default()
+    }));
+    assert!(!included(HarConvertOptions {
+        include_hosts: vec!["cdn.example.com".to_string()],
+        ..Default::default()
+    }));
+    assert!(!included(HarConvertOptions {
+        url_filter: Some(Regex::new("/orders").unwrap()),
+        ..Default::default()
+    }));
+    assert!(!included(HarConvertOptions {
+        status_range: Some(200..=299),
+        ..Default::default()
+    }));
+}
+
+#[test]
+fn test_keep_response_header() {
+    let options = HarConvertOptions {
+        keep_headers: vec!["*".to_string()],
+        drop_headers: vec!["Server".to_string()],
+        ..Default::default()
+    };
+    assert!(keep_response_header("Cache-Control", &options));
+    assert!(!keep_response_header("server", &options));
+    assert!(!keep_response_header("Content-Length", &options));
+    assert!(!keep_response_header(":status", &options));
+    assert!(!keep_response_header("Cache-Control", &Default::default()));
+    assert!(keep_response_header("Content-Type", &Default::default()));
+}
+
+#[test]
+fn test_decode_content() {
+    let content = Content {
+        mime_type: "image/png".to_string(),
+        text: Some("iVBORw0KGgo=".to_string()),
+        encoding: Some("base64".to_string()),
+    };
+    match decode_content(&content).unwrap() {
+        Some(EntryBody::Binary(bytes)) => {
+            assert_eq!(
+                vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'],
+                bytes
+            )
+        }
+        _ => panic!("expected a binary body"),
+    }
+
+    let content = Content {
+        mime_type: "text/plain".to_string(),
+        text: Some("aGVsbG8=".to_string()),
+        encoding: Some("base64".to_string()),
+    };
+    assert!(matches!(
+        decode_content(&content).unwrap(),
+        Some(EntryBody::Text(text)) if text == "hello"
+    ));
 }
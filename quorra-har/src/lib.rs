@@ -1,4 +1,15 @@
 mod exec;
+mod merge;
 mod models;
+mod openapi;
+mod postman;
+mod reader;
+mod scrub;
+mod wiremock;
 
-pub use exec::HarConvertor;
+pub use exec::{HarConvertOptions, HarConvertor};
+pub use merge::{MergeChange, MergeReport};
+pub use openapi::OpenApiExporter;
+pub use postman::PostmanConvertor;
+pub use scrub::{ScrubRule, Scrubber};
+pub use wiremock::{ConversionReport, WiremockConvertor, WiremockExporter};
@@ -0,0 +1,343 @@
+use crate::exec::{group_entries, write_entries, EntryWrapper};
+use anyhow::Error;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use quorra_config::prelude::*;
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, info};
+
+/// What changed in the destination while converting a recording.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub changes: Vec<MergeChange>,
+}
+
+#[derive(Debug)]
+pub enum MergeChange {
+    /// A payload file was created for a request that was not configured yet.
+    PayloadAdded(PathBuf),
+    /// A response was added to an existing payload.
+    ResponseAdded { payload: PathBuf, status: u16 },
+    /// The recorded headers, cookies or body of an existing response changed.
+    ResponseUpdated { payload: PathBuf, id: String },
+    /// An existing response was not in the recording. It is kept as is.
+    ResponseNotRecorded { payload: PathBuf, id: String },
+}
+
+impl MergeReport {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl Display for MergeChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeChange::PayloadAdded(payload) => write!(f, "{}: added", payload.display()),
+            MergeChange::ResponseAdded { payload, status } => write!(
+                f,
+                "{}: added response with status {}",
+                payload.display(),
+                status
+            ),
+            MergeChange::ResponseUpdated { payload, id } => {
+                write!(f, "{}: updated response {}", payload.display(), id)
+            }
+            MergeChange::ResponseNotRecorded { payload, id } => write!(
+                f,
+                "{}: response {} was not recorded, keeping it",
+                payload.display(),
+                id
+            ),
+        }
+    }
+}
+
+struct ExistingPayload {
+    path: PathBuf,
+    config: StaticHttpConfig<ResponseData>,
+    changed: bool,
+}
+
+/// Merges the converted entries into the payloads already in `dest`. Payloads are
+/// found by their matcher, and responses inside a payload by their status. Ids,
+/// weights, delays and hand-added headers of existing responses are kept.
+pub(crate) fn merge_entries(
+    dest: &Path,
+    entries: Vec<EntryWrapper>,
+    report: &mut MergeReport,
+) -> Result<(), Error> {
+    let mut existing = load_payloads(dest)?;
+    let mut new_entries = Vec::new();
+    // Recorded assets whose contents were copied into an existing response.
+    let mut superseded = Vec::new();
+
+    for (matcher, (_, group)) in group_entries(entries) {
+        let payload = existing
+            .iter_mut()
+            .find(|payload| payload.config.matches.contains(&matcher));
+        match payload {
+            Some(payload) => merge_payload(dest, payload, group, report, &mut superseded)?,
+            None => new_entries.extend(group),
+        }
+    }
+
+    let mut referenced = BTreeSet::new();
+    for response in existing
+        .iter()
+        .flat_map(|payload| payload.config.responses.iter())
+        .chain(new_entries.iter().map(|wrapper| &wrapper.response_config))
+    {
+        if let Some(file) = asset_file(response) {
+            referenced.insert(dest.join(file));
+        }
+    }
+
+    for payload in existing.iter().filter(|payload| payload.changed) {
+        info!("Updating file {}", payload.path.display());
+        let config = ResponseConfig::StaticHttp(payload.config.clone());
+        std::fs::write(&payload.path, serde_yaml::to_string(&config)?)?;
+    }
+
+    for path in write_entries(dest, new_entries)? {
+        report.changes.push(MergeChange::PayloadAdded(path));
+    }
+
+    for path in superseded {
+        if !referenced.contains(&path) {
+            debug!("Removing unused asset file {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn asset_file(response: &StaticResponseConfig<ResponseData>) -> Option<&PathBuf> {
+    match &response.body {
+        Some(StaticResponseBodyConfig::Raw(ResponseData::File(file)))
+        | Some(StaticResponseBodyConfig::Json(ResponseData::File(file)))
+        | Some(StaticResponseBodyConfig::Binary(ResponseData::File(file))) => Some(file),
+        _ => None,
+    }
+}
+
+fn load_payloads(dest: &Path) -> Result<Vec<ExistingPayload>, Error> {
+    let mut payloads = Vec::new();
+    if !dest.exists() {
+        return Ok(payloads);
+    }
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dest)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|x| x.to_str()),
+                Some("yaml") | Some("yml")
+            )
+        })
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let contents = std::fs::read_to_string(&path)?;
+        match serde_yaml::from_str::<ResponseConfig<ResponseData>>(&contents) {
+            Ok(ResponseConfig::StaticHttp(config)) => payloads.push(ExistingPayload {
+                path,
+                config,
+                changed: false,
+            }),
+            Ok(ResponseConfig::Websocket(_))
+            | Ok(ResponseConfig::Grpc(_))
+            | Ok(ResponseConfig::Tcp(_))
+            | Ok(ResponseConfig::Smtp(_)) => {
+                debug!("Skipping {}, not an HTTP payload", path.display())
+            }
+            Err(e) => debug!("Skipping {}, not a payload: {}", path.display(), e),
+        }
+    }
+
+    Ok(payloads)
+}
+
+fn merge_payload(
+    dest: &Path,
+    payload: &mut ExistingPayload,
+    group: Vec<EntryWrapper>,
+    report: &mut MergeReport,
+    superseded: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let mut used = vec![false; payload.config.responses.len()];
+
+    for wrapper in group {
+        let recorded = wrapper.response_config;
+        let position = payload
+            .config
+            .responses
+            .iter()
+            .enumerate()
+            .position(|(i, response)| !used[i] && response.status == recorded.status);
+
+        match position {
+            Some(i) => {
+                used[i] = true;
+                let response = &mut payload.config.responses[i];
+                if update_response(dest, response, &recorded, superseded)? {
+                    payload.changed = true;
+                    report.changes.push(MergeChange::ResponseUpdated {
+                        payload: payload.path.clone(),
+                        id: response.id.clone(),
+                    });
+                }
+            }
+            None => {
+                report.changes.push(MergeChange::ResponseAdded {
+                    payload: payload.path.clone(),
+                    status: recorded.status,
+                });
+                payload.config.responses.push(recorded);
+                payload.changed = true;
+            }
+        }
+    }
+
+    for (i, response) in payload.config.responses.iter().enumerate() {
+        if i < used.len() && !used[i] {
+            report.changes.push(MergeChange::ResponseNotRecorded {
+                payload: payload.path.clone(),
+                id: response.id.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the recorded headers, cookies and body to an existing response.
+/// Returns true when anything about the response changed.
+fn update_response(
+    dest: &Path,
+    response: &mut StaticResponseConfig<ResponseData>,
+    recorded: &StaticResponseConfig<ResponseData>,
+    superseded: &mut Vec<PathBuf>,
+) -> Result<bool, Error> {
+    let mut changed = false;
+
+    for (name, value) in &recorded.headers {
+        if response.headers.get(name) != Some(value) {
+            response.headers.insert(name.clone(), value.clone());
+            changed = true;
+        }
+    }
+
+    if !recorded.cookies.is_empty() && response.cookies != recorded.cookies {
+        response.cookies = recorded.cookies.clone();
+        changed = true;
+    }
+
+    let recorded_file = match asset_file(recorded) {
+        Some(file) => file,
+        None => return Ok(changed),
+    };
+
+    let (data, is_binary) = match &mut response.body {
+        Some(StaticResponseBodyConfig::Raw(data)) | Some(StaticResponseBodyConfig::Json(data)) => {
+            (data, false)
+        }
+        Some(StaticResponseBodyConfig::Binary(data)) => (data, true),
+        // A recording holds the whole stream as one body, the scripted events are kept.
+        Some(StaticResponseBodyConfig::Sse(_)) => return Ok(changed),
+        Some(StaticResponseBodyConfig::Empty) | None => {
+            response.body = recorded.body.clone();
+            return Ok(true);
+        }
+    };
+
+    if matches!(data, ResponseData::File(file) if file == recorded_file) {
+        return Ok(changed);
+    }
+
+    let recorded_path = dest.join(recorded_file);
+    let contents = std::fs::read(&recorded_path)?;
+    superseded.push(recorded_path);
+
+    match data {
+        ResponseData::File(file) => {
+            // The asset is rewritten in place so the payload keeps pointing at it.
+            let path = dest.join(file);
+            if std::fs::read(&path).ok().as_deref() != Some(contents.as_slice()) {
+                info!("Updating asset file {}", path.display());
+                std::fs::write(&path, contents)?;
+                changed = true;
+            }
+        }
+        ResponseData::Data(current) => {
+            let updated = if is_binary {
+                STANDARD.encode(&contents)
+            } else {
+                String::from_utf8_lossy(&contents).to_string()
+            };
+            if *current != updated {
+                *current = updated;
+                changed = true;
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+#[test]
+fn test_update_response_keeps_hand_edits() {
+    use std::collections::BTreeMap;
+
+    let dest = tempfile::tempdir().unwrap();
+    std::fs::write(dest.path().join("recorded.txt"), "new").unwrap();
+
+    let mut response = StaticResponseConfig {
+        id: "kept".to_string(),
+        weight: 3,
+        status: 200,
+        headers: BTreeMap::from([
+            ("Content-Type".to_string(), "text/plain".to_string()),
+            ("X-Hand-Added".to_string(), "yes".to_string()),
+        ]),
+        cookies: Vec::new(),
+        trailers: Default::default(),
+        body: Some(StaticResponseBodyConfig::Raw(ResponseData::Data(
+            "old".to_string(),
+        ))),
+        delay: 50,
+    };
+    let recorded = StaticResponseConfig {
+        id: "new".to_string(),
+        weight: 1,
+        status: 200,
+        headers: BTreeMap::from([("Content-Type".to_string(), "text/html".to_string())]),
+        cookies: Vec::new(),
+        trailers: Default::default(),
+        body: Some(StaticResponseBodyConfig::Raw(ResponseData::File(
+            "recorded.txt".into(),
+        ))),
+        delay: 0,
+    };
+
+    let mut superseded = Vec::new();
+    assert!(update_response(dest.path(), &mut response, &recorded, &mut superseded).unwrap());
+    assert_eq!(
+        ("kept", 3, 50),
+        (response.id.as_str(), response.weight, response.delay)
+    );
+    assert_eq!("text/html", response.headers["Content-Type"]);
+    assert_eq!("yes", response.headers["X-Hand-Added"]);
+    assert!(matches!(
+        &response.body,
+        Some(StaticResponseBodyConfig::Raw(ResponseData::Data(body))) if body == "new"
+    ));
+    assert_eq!(vec![dest.path().join("recorded.txt")], superseded);
+
+    assert!(!update_response(dest.path(), &mut response, &recorded, &mut superseded).unwrap());
+}
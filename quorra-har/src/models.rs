@@ -1,21 +1,11 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct HarFile {
-    pub log: LogEntry,
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NameValueEntry {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct LogEntry {
-    pub entries: Vec<RequestWrapper>,
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RequestEntry {
@@ -24,6 +14,17 @@ pub struct RequestEntry {
     pub headers: Vec<NameValueEntry>,
     pub cookies: Vec<NameValueEntry>,
     pub query_string: Vec<NameValueEntry>,
+    #[serde(default)]
+    pub post_data: Option<PostData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -31,6 +32,33 @@ pub struct RequestEntry {
 pub struct Content {
     pub mime_type: String,
     pub text: Option<String>,
+    #[serde(default)]
+    pub encoding: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub http_only: Option<bool>,
+    #[serde(default)]
+    pub secure: Option<bool>,
+    #[serde(default)]
+    pub same_site: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Timings {
+    #[serde(default)]
+    pub wait: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -39,7 +67,7 @@ pub struct ResponseEntry {
     pub status: i32,
     pub status_text: String,
     pub headers: Vec<NameValueEntry>,
-    pub cookies: Vec<NameValueEntry>,
+    pub cookies: Vec<Cookie>,
     pub content: Content,
     #[serde(rename = "redirectURL")]
     pub redirect_url: serde_json::Value,
@@ -49,6 +77,10 @@ pub struct ResponseEntry {
 #[serde(rename_all = "camelCase")]
 pub struct RequestWrapper {
     pub started_date_time: String,
+    #[serde(default)]
+    pub time: Option<f64>,
+    #[serde(default)]
+    pub timings: Option<Timings>,
     pub request: RequestEntry,
     pub response: ResponseEntry,
 }
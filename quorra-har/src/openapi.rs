@@ -0,0 +1,570 @@
+use crate::wiremock::ConversionReport;
+use anyhow::Error;
+use quorra_config::prelude::*;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+/// Methods a payload without a method matcher is listed under.
+const ALL_METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Describes the loaded Quorra configs as an OpenAPI 3 document. Matchers that
+/// OpenAPI can't express are kept in `x-quorra-*` vendor extensions.
+pub struct OpenApiExporter {
+    root_config: PathBuf,
+    title: String,
+}
+
+impl OpenApiExporter {
+    pub fn new(root_config: &Path, title: &str) -> Self {
+        Self {
+            root_config: root_config.to_path_buf(),
+            title: title.to_string(),
+        }
+    }
+
+    /// Writes the document to `dest`, as YAML when the extension is `yaml` or `yml`
+    /// and as JSON otherwise.
+    pub async fn export(&self, dest: &Path) -> Result<ConversionReport, Error> {
+        let (document, report) = self.document()?;
+        let output = match dest.extension().and_then(|x| x.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::to_string(&document)?,
+            _ => serde_json::to_string_pretty(&document)?,
+        };
+
+        info!("Writing file {}", dest.display());
+        std::fs::write(dest, output)?;
+        Ok(report)
+    }
+
+    /// The document, and the payloads that were left out of it.
+    pub fn document(&self) -> Result<(Value, ConversionReport), Error> {
+        let mut report = ConversionReport::default();
+        let loaded = ConfigContainer::new(&self.root_config).load_config()?;
+
+        let mut builder = DocumentBuilder::default();
+        let listeners = std::iter::once((None, &loaded.responses)).chain(
+            loaded
+                .listeners
+                .iter()
+                .map(|(name, responses)| (Some(name.as_str()), responses)),
+        );
+        for (listener, responses) in listeners {
+            for response_config in responses {
+                match response_config {
+                    ResponseConfig::StaticHttp(http) => builder.add_payload(http, listener),
+                    // Only HTTP requests are documented as operations, leaving out
+                    // the upgrade and gRPC calls.
+                    ResponseConfig::Websocket(websocket) => {
+                        report.add(
+                            &websocket.id,
+                            "WebSocket payloads are not supported, skipping",
+                        );
+                    }
+                    ResponseConfig::Grpc(grpc) => {
+                        report.add(&grpc.id, "gRPC payloads are not supported, skipping");
+                    }
+                    ResponseConfig::Tcp(tcp) => {
+                        report.add(&tcp.id, "TCP payloads are not supported, skipping");
+                    }
+                    ResponseConfig::Smtp(smtp) => {
+                        report.add(&smtp.id, "SMTP payloads are not supported, skipping");
+                    }
+                }
+            }
+        }
+
+        let mut document = json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": self.title,
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": format!("Generated from {}", self.root_config.display()),
+            },
+            "paths": builder.paths,
+        });
+        if !builder.tags.is_empty() {
+            let tags: Vec<Value> = builder
+                .tags
+                .iter()
+                .map(|name| {
+                    json!({
+                        "name": name,
+                        "description": format!("Served by the `{}` listener", name),
+                    })
+                })
+                .collect();
+            document["tags"] = Value::from(tags);
+        }
+        Ok((document, report))
+    }
+}
+
+#[derive(Default)]
+struct DocumentBuilder {
+    paths: Map<String, Value>,
+    operation_ids: BTreeSet<String>,
+    /// The named listeners with a documented operation.
+    tags: BTreeSet<String>,
+}
+
+impl DocumentBuilder {
+    /// Operations of a named listener are tagged with its name, those of the
+    /// default listener aren't tagged.
+    fn add_payload(&mut self, config: &StaticHttpConfig<String>, listener: Option<&str>) {
+        let responses = export_responses(&config.responses);
+
+        for matcher in &config.matches {
+            let (path, path_parameters, path_regex) = export_path(&matcher.path);
+
+            let mut parameters = path_parameters;
+            for (name, value) in &matcher.query {
+                parameters.push(export_parameter(name, "query", value));
+            }
+            for (name, value) in &matcher.headers {
+                parameters.push(export_parameter(name, "header", value));
+            }
+
+            let mut operation = Map::new();
+            if let Some(listener) = listener {
+                operation.insert("tags".to_string(), json!([listener]));
+                self.tags.insert(listener.to_string());
+            }
+            if !parameters.is_empty() {
+                operation.insert("parameters".to_string(), Value::from(parameters));
+            }
+            if let Some(body) = export_request_body(matcher) {
+                operation.insert("requestBody".to_string(), body);
+            }
+            operation.insert("responses".to_string(), responses.clone());
+
+            if let Some(path_regex) = path_regex {
+                operation.insert("x-quorra-path-regex".to_string(), Value::from(path_regex));
+            }
+            if let Some(host) = &matcher.host {
+                operation.insert("x-quorra-host".to_string(), Value::from(host.clone()));
+            }
+            if let Some(protocol) = &matcher.protocol {
+                operation.insert(
+                    "x-quorra-protocol".to_string(),
+                    Value::from(protocol.clone()),
+                );
+            }
+            if let Some(graphql) = &matcher.graphql {
+                operation.insert(
+                    "x-quorra-graphql-operation".to_string(),
+                    Value::from(graphql.operation_name.clone()),
+                );
+            }
+            if let Some(regex) = matcher.body.as_ref().and_then(|body| body.regex.as_ref()) {
+                operation.insert(
+                    "x-quorra-body-regex".to_string(),
+                    Value::from(regex.clone()),
+                );
+            }
+
+            let methods: Vec<String> = if matcher.methods.is_empty() {
+                operation.insert("x-quorra-any-method".to_string(), Value::from(true));
+                ALL_METHODS.iter().map(|x| x.to_string()).collect()
+            } else {
+                matcher.methods.iter().map(|x| x.to_lowercase()).collect()
+            };
+
+            let mut operations = Vec::new();
+            for method in methods {
+                let mut operation = operation.clone();
+                operation.insert(
+                    "operationId".to_string(),
+                    Value::from(self.operation_id(&config.id)),
+                );
+                operations.push((method, Value::Object(operation)));
+            }
+
+            let path_item = self
+                .paths
+                .entry(path)
+                .or_insert_with(|| Value::Object(Map::new()));
+            for (method, operation) in operations {
+                add_operation(path_item, &method, operation);
+            }
+        }
+    }
+
+    /// Operation ids must be unique, payloads listed more than once get a suffix.
+    fn operation_id(&mut self, id: &str) -> String {
+        let mut operation_id = id.to_string();
+        let mut index = 1;
+        while self.operation_ids.contains(&operation_id) {
+            operation_id = format!("{}_{}", id, index);
+            index += 1;
+        }
+        self.operation_ids.insert(operation_id.clone());
+        operation_id
+    }
+}
+
+/// OpenAPI has a single operation per path and method. Payloads that only differ
+/// by other matchers are listed under `x-quorra-alternatives` of the first one,
+/// in the order Quorra tries them.
+fn add_operation(path_item: &mut Value, method: &str, operation: Value) {
+    let path_item = path_item.as_object_mut().unwrap();
+    match path_item.get_mut(method) {
+        None => {
+            path_item.insert(method.to_string(), operation);
+        }
+        Some(existing) => {
+            let alternatives = existing
+                .as_object_mut()
+                .unwrap()
+                .entry("x-quorra-alternatives")
+                .or_insert_with(|| Value::Array(Vec::new()));
+            alternatives.as_array_mut().unwrap().push(operation);
+        }
+    }
+}
+
+/// Turns the path regex into a path template. Segments that aren't literal become
+/// parameters with the segment regex as their pattern. Regexes that can't be split
+/// into segments are listed under a single `{path}` parameter.
+fn export_path(path: &str) -> (String, Vec<Value>, Option<String>) {
+    if !path.starts_with('/') || path.contains(".*") || path.contains(".+") || path.contains('|') {
+        let parameter = json!({
+            "name": "path",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        });
+        return (
+            "/{path}".to_string(),
+            vec![parameter],
+            Some(path.to_string()),
+        );
+    }
+
+    let mut segments = Vec::new();
+    let mut parameters = Vec::new();
+    for segment in split_segments(path).into_iter().skip(1) {
+        match unescape_literal(segment) {
+            Some(literal) => segments.push(literal),
+            None => {
+                let name = format!("param{}", parameters.len() + 1);
+                segments.push(format!("{{{}}}", name));
+                parameters.push(json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string", "pattern": format!("^{}$", segment) },
+                }));
+            }
+        }
+    }
+
+    (format!("/{}", segments.join("/")), parameters, None)
+}
+
+/// Splits the path regex on `/`, except inside character classes and groups.
+fn split_segments(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in path.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth -= 1,
+            '/' if depth == 0 => {
+                segments.push(&path[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&path[start..]);
+    segments
+}
+
+/// Returns the literal text when the regex only matches a single string.
+fn unescape_literal(regex: &str) -> Option<String> {
+    let mut literal = String::new();
+    let mut chars = regex.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped.is_ascii_punctuation() => literal.push(escaped),
+                _ => return None,
+            },
+            '.' | '^' | '$' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' => {
+                return None
+            }
+            c => literal.push(c),
+        }
+    }
+    Some(literal)
+}
+
+fn export_parameter(name: &str, location: &str, value: &str) -> Value {
+    let schema = match value
+        .strip_prefix('^')
+        .and_then(|x| x.strip_suffix('$'))
+        .and_then(unescape_literal)
+    {
+        Some(literal) => json!({ "type": "string", "enum": [literal] }),
+        None => json!({ "type": "string", "pattern": value }),
+    };
+
+    json!({
+        "name": name,
+        "in": location,
+        "required": true,
+        "schema": schema,
+    })
+}
+
+/// GraphQL and JSON body matchers are shown as a request body example.
+fn export_request_body(matcher: &StaticMatchesConfig) -> Option<Value> {
+    let json_body = matcher.body.as_ref().and_then(|body| body.json.as_ref());
+    let mut example = match json_body {
+        Some(json_body) => serde_json::from_str::<Value>(json_body)
+            .unwrap_or_else(|_| Value::from(json_body.clone())),
+        None if matcher.graphql.is_some() => json!({}),
+        None => return None,
+    };
+
+    if let (Some(graphql), Some(object)) = (&matcher.graphql, example.as_object_mut()) {
+        object.insert(
+            "operationName".to_string(),
+            Value::from(graphql.operation_name.clone()),
+        );
+    }
+
+    Some(json!({
+        "required": true,
+        "content": { "application/json": { "example": example } },
+    }))
+}
+
+/// Responses are keyed by status. Every weighted response sharing a status
+/// becomes a named example.
+fn export_responses(responses: &[StaticResponseConfig<String>]) -> Value {
+    let mut by_status: Map<String, Value> = Map::new();
+    for response in responses {
+        let entry = by_status
+            .entry(response.status.to_string())
+            .or_insert_with(|| export_response_headers(response));
+
+        let (content_type, mut example) = match export_body(response) {
+            Some(body) => body,
+            None => continue,
+        };
+        if response.weight != 1 {
+            example["x-quorra-weight"] = Value::from(response.weight);
+        }
+        if response.delay != 0 {
+            example["x-quorra-delay"] = Value::from(response.delay);
+        }
+
+        let media = entry
+            .as_object_mut()
+            .unwrap()
+            .entry("content")
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .unwrap()
+            .entry(content_type)
+            .or_insert_with(|| json!({ "examples": {} }));
+        media["examples"][&response.id] = example;
+    }
+
+    Value::Object(by_status)
+}
+
+fn export_response_headers(response: &StaticResponseConfig<String>) -> Value {
+    let mut headers = Map::new();
+    for (name, value) in &response.headers {
+        if !name.eq_ignore_ascii_case("Content-Type") {
+            headers.insert(
+                name.clone(),
+                json!({ "schema": { "type": "string" }, "example": value }),
+            );
+        }
+    }
+    if let Some(cookie) = response.cookies.first() {
+        headers.insert(
+            "Set-Cookie".to_string(),
+            json!({ "schema": { "type": "string" }, "example": cookie }),
+        );
+    }
+
+    let mut entry = json!({ "description": format!("Status {}", response.status) });
+    if !headers.is_empty() {
+        entry["headers"] = Value::Object(headers);
+    }
+    entry
+}
+
+/// The media type and example object of the response body. Binary bodies are
+/// kept base64 encoded in an extension, templates are flagged as such.
+fn export_body(response: &StaticResponseConfig<String>) -> Option<(String, Value)> {
+    let content_type = response.headers.iter().find_map(|(name, value)| {
+        if name.eq_ignore_ascii_case("Content-Type") {
+            Some(value.clone())
+        } else {
+            None
+        }
+    });
+
+    let (default_type, mut example) = match response.body.as_ref()? {
+        StaticResponseBodyConfig::Empty => return None,
+        StaticResponseBodyConfig::Json(body) => (
+            "application/json",
+            json!({ "value": serde_json::from_str::<Value>(body)
+                .unwrap_or_else(|_| Value::from(body.clone())) }),
+        ),
+        StaticResponseBodyConfig::Raw(body) => ("text/plain", json!({ "value": body })),
+        StaticResponseBodyConfig::Binary(body) => (
+            "application/octet-stream",
+            json!({ "x-quorra-base64": body }),
+        ),
+        StaticResponseBodyConfig::Sse(sse) => (
+            "text/event-stream",
+            json!({ "value": sse.to_event_stream() }),
+        ),
+    };
+
+    let is_template = match &response.body {
+        Some(StaticResponseBodyConfig::Json(body)) | Some(StaticResponseBodyConfig::Raw(body)) => {
+            body.contains("{{")
+        }
+        Some(StaticResponseBodyConfig::Sse(sse)) => sse.to_event_stream().contains("{{"),
+        _ => false,
+    };
+    if is_template {
+        example["x-quorra-template"] = Value::from(true);
+    }
+
+    Some((
+        content_type.unwrap_or_else(|| default_type.to_string()),
+        example,
+    ))
+}
+
+#[test]
+fn test_export_path() {
+    let (path, parameters, regex) = export_path(r"/users/[0-9]+/posts\.json");
+    assert_eq!("/users/{param1}/posts.json", path);
+    assert_eq!("^[0-9]+$", parameters[0]["schema"]["pattern"]);
+    assert_eq!(None, regex);
+
+    let (path, _, _) = export_path("/users/[^/]+");
+    assert_eq!("/users/{param1}", path);
+
+    let (path, _, regex) = export_path("/static/.*");
+    assert_eq!("/{path}", path);
+    assert_eq!(Some("/static/.*".to_string()), regex);
+}
+
+#[test]
+fn test_export_parameter() {
+    assert_eq!(
+        json!(["a.b"]),
+        export_parameter("q", "query", r"^a\.b$")["schema"]["enum"]
+    );
+    assert_eq!(
+        "a+",
+        export_parameter("q", "query", "a+")["schema"]["pattern"]
+    );
+}
+
+#[test]
+fn test_document_reports_skipped_payloads() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("config.toml"),
+        "[responses]\npaths = [\"*.yaml\"]\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("users.yaml"),
+        "type: static-http\nid: users\nmatches:\n  - path: /users\n    methods: [GET]\nresponses:\n  - status: 200\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("chat.yaml"),
+        "type: websocket\nid: chat\nmatches:\n  - path: /chat\n",
+    )
+    .unwrap();
+
+    let exporter = OpenApiExporter::new(&dir.path().join("config.toml"), "test");
+    let (document, report) = exporter.document().unwrap();
+    assert_eq!("users", document["paths"]["/users"]["get"]["operationId"]);
+    assert!(document["paths"].get("/chat").is_none());
+    let skipped: Vec<&str> = report
+        .entries
+        .iter()
+        .map(|entry| entry.source.as_str())
+        .collect();
+    assert_eq!(vec!["chat"], skipped);
+}
+
+#[test]
+fn test_document_tags_listener_operations() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("config.toml"),
+        "[responses]\npaths = [\"users.yaml\"]\n\n[listeners.payments]\naddress = \"127.0.0.1:4001\"\nresponses.paths = [\"payments.yaml\"]\n",
+    )
+    .unwrap();
+    let payload = |id: &str, path: &str| {
+        format!(
+            "type: static-http\nid: {}\nmatches:\n  - path: {}\n    methods: [GET]\nresponses:\n  - status: 200\n",
+            id, path
+        )
+    };
+    std::fs::write(dir.path().join("users.yaml"), payload("users", "/users")).unwrap();
+    std::fs::write(dir.path().join("payments.yaml"), payload("pay", "/pay")).unwrap();
+
+    let exporter = OpenApiExporter::new(&dir.path().join("config.toml"), "test");
+    let (document, report) = exporter.document().unwrap();
+    assert!(report.is_empty());
+    assert!(document["paths"]["/users"]["get"].get("tags").is_none());
+    assert_eq!(
+        json!(["payments"]),
+        document["paths"]["/pay"]["get"]["tags"]
+    );
+    assert_eq!("payments", document["tags"][0]["name"]);
+}
+
+#[test]
+fn test_document_keeps_matchers_of_alternatives() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("config.toml"),
+        "[responses]\npaths = [\"*.yaml\"]\n",
+    )
+    .unwrap();
+    let payload = |id: &str, host: &str| {
+        format!(
+            "type: static-http\nid: {}\nmatches:\n  - path: /users\n    host: {}\n    protocol: HTTP/2\n    methods: [GET]\nresponses:\n  - status: 200\n",
+            id, host
+        )
+    };
+    std::fs::write(dir.path().join("a.yaml"), payload("a", "a\\.test")).unwrap();
+    std::fs::write(dir.path().join("b.yaml"), payload("b", "b\\.test")).unwrap();
+
+    let exporter = OpenApiExporter::new(&dir.path().join("config.toml"), "test");
+    let (document, _) = exporter.document().unwrap();
+    let operation = &document["paths"]["/users"]["get"];
+    assert_eq!("a\\.test", operation["x-quorra-host"]);
+    assert_eq!("HTTP/2", operation["x-quorra-protocol"]);
+    assert_eq!(
+        "b\\.test",
+        operation["x-quorra-alternatives"][0]["x-quorra-host"]
+    );
+}
@@ -0,0 +1,306 @@
+use crate::exec::{
+    is_kept_response_header, write_body, write_entries, EntryBody, EntryWrapperWithBody,
+};
+use anyhow::{anyhow, Error};
+use quorra_config::prelude::*;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+use url::Url;
+
+mod models;
+
+use models::*;
+
+pub struct PostmanConvertor {
+    path: PathBuf,
+}
+
+impl PostmanConvertor {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+        }
+    }
+
+    pub async fn convert(&self, dest: &Path) -> Result<(), Error> {
+        let collection: PostmanCollection =
+            serde_json::from_str(&std::fs::read_to_string(&self.path)?)?;
+
+        let mut variables: BTreeMap<String, String> = Default::default();
+        for variable in collection.variable.iter().filter(|x| !x.disabled) {
+            variables.insert(variable.key.clone(), variable_value(&variable.value));
+        }
+
+        let mut folders = Vec::new();
+        collect_items(dest, &collection.item, &variables, &mut folders)?;
+
+        for (folder, converted) in folders {
+            info!(
+                "Writing {} examples to {}",
+                converted.len(),
+                folder.display()
+            );
+            std::fs::create_dir_all(&folder)?;
+            let written = converted
+                .into_iter()
+                .map(|wrapper| write_body(&folder, wrapper))
+                .collect::<Result<_, _>>()?;
+            write_entries(&folder, written)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Walks the collection, converting every saved example. Folders become
+/// subdirectories of `dest`.
+fn collect_items(
+    dest: &Path,
+    items: &[CollectionItem],
+    variables: &BTreeMap<String, String>,
+    folders: &mut Vec<(PathBuf, Vec<EntryWrapperWithBody>)>,
+) -> Result<(), Error> {
+    let mut converted = Vec::new();
+    for item in items {
+        if let Some(children) = &item.item {
+            collect_items(
+                &dest.join(folder_name(&item.name)),
+                children,
+                variables,
+                folders,
+            )?;
+            continue;
+        }
+
+        for response in &item.response {
+            let request = match response.original_request.as_ref().or(item.request.as_ref()) {
+                Some(request) => request,
+                None => {
+                    warn!(
+                        "Example {} of {} has no request, skipping",
+                        response.name, item.name
+                    );
+                    continue;
+                }
+            };
+
+            let (name, matcher) = convert_request(request, variables)?;
+            converted.push(EntryWrapperWithBody {
+                name,
+                matcher,
+                response_config: convert_response(response, variables),
+                body: response
+                    .body
+                    .as_ref()
+                    .map(|body| EntryBody::Text(resolve_variables(body, variables))),
+            });
+        }
+    }
+
+    if !converted.is_empty() {
+        folders.push((dest.to_path_buf(), converted));
+    }
+
+    Ok(())
+}
+
+fn convert_request(
+    request: &RequestEntry,
+    variables: &BTreeMap<String, String>,
+) -> Result<(String, StaticMatchesConfig), Error> {
+    let (raw, path_variables) = match &request.url {
+        Some(RequestUrl::Raw(raw)) => (raw.clone(), Default::default()),
+        Some(RequestUrl::Detailed(detail)) => (detail.raw.clone(), detail.variable.clone()),
+        None => return Err(anyhow!("Request is missing a url")),
+    };
+
+    let raw = resolve_variables(&raw, variables);
+    let url = match Url::parse(&raw) {
+        Ok(url) => url,
+        Err(_) => Url::parse(&format!("http://localhost/{}", raw.trim_start_matches('/')))?,
+    };
+
+    let path: Vec<String> = url
+        .path()
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => path_variables
+                .iter()
+                .find(|x| x.key == name && !x.disabled)
+                .map(|x| variable_value(&x.value))
+                .filter(|x| !x.is_empty())
+                .map(|x| regex::escape(&x))
+                .unwrap_or_else(|| "[^/]+".to_string()),
+            None => regex::escape(segment),
+        })
+        .collect();
+
+    let query = url
+        .query_pairs()
+        .map(|(name, value)| (name.to_string(), format!("^{}$", regex::escape(&value))))
+        .collect();
+
+    let method = request.method.clone().unwrap_or_else(|| "GET".to_string());
+
+    Ok((
+        url.path().to_string(),
+        StaticMatchesConfig {
+            headers: Default::default(),
+            query,
+            path: path.join("/"),
+            host: None,
+            protocol: None,
+            methods: vec![method.to_uppercase()],
+            graphql: None,
+            body: None,
+        },
+    ))
+}
+
+fn convert_response(
+    response: &SavedResponse,
+    variables: &BTreeMap<String, String>,
+) -> StaticResponseConfig<()> {
+    let mut header_map: BTreeMap<String, String> = Default::default();
+    for header in response.header.iter().flatten() {
+        if !header.disabled && is_kept_response_header(&header.key) {
+            header_map.insert(
+                header.key.clone(),
+                resolve_variables(&header.value, variables),
+            );
+        }
+    }
+
+    let is_json = header_map
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        .map(|(_, x)| x.contains("application/json"))
+        .unwrap_or_default();
+    let body = if is_json {
+        StaticResponseBodyConfig::Json(())
+    } else {
+        StaticResponseBodyConfig::Raw(())
+    };
+
+    StaticResponseConfig {
+        status: response.code.unwrap_or(200),
+        weight: 1,
+        headers: header_map,
+        cookies: Vec::new(),
+        trailers: Default::default(),
+        id: unique_id(),
+        body: response.body.as_ref().map(|_| body),
+        delay: 0,
+    }
+}
+
+fn variable_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => value.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Replaces `{{name}}` with the collection variable value. Unknown variables are left as is.
+fn resolve_variables(text: &str, variables: &BTreeMap<String, String>) -> String {
+    let mut resolved = String::with_capacity(text.len());
+    let mut remaining = text;
+    while let Some(start) = remaining.find("{{") {
+        let (before, after) = remaining.split_at(start);
+        resolved.push_str(before);
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[2..end].trim();
+                match variables.get(name) {
+                    Some(value) => resolved.push_str(value),
+                    None => resolved.push_str(&after[..end + 2]),
+                }
+                remaining = &after[end + 2..];
+            }
+            None => {
+                resolved.push_str(after);
+                remaining = "";
+            }
+        }
+    }
+    resolved.push_str(remaining);
+    resolved
+}
+
+fn folder_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('-');
+    if name.is_empty() {
+        "folder".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+#[test]
+fn test_resolve_variables() {
+    let variables = BTreeMap::from([("baseUrl".to_string(), "https://example.com".to_string())]);
+    assert_eq!(
+        "https://example.com/users/{{ missing }}",
+        resolve_variables("{{baseUrl}}/users/{{ missing }}", &variables)
+    );
+    assert_eq!("{{baseUrl", resolve_variables("{{baseUrl", &variables));
+}
+
+#[test]
+fn test_convert_request_path_variables() {
+    let variables = BTreeMap::from([("baseUrl".to_string(), "https://example.com".to_string())]);
+    let request: RequestEntry = serde_json::from_value(serde_json::json!({
+        "method": "get",
+        "url": {
+            "raw": "{{baseUrl}}/users/:id/posts/:post?limit=10",
+            "variable": [{"key": "id", "value": "42"}]
+        }
+    }))
+    .unwrap();
+
+    let (name, matcher) = convert_request(&request, &variables).unwrap();
+    assert_eq!("/users/:id/posts/:post", name);
+    assert_eq!("/users/42/posts/[^/]+", matcher.path);
+    assert_eq!(
+        vec![("limit".to_string(), "^10$".to_string())],
+        matcher.query
+    );
+    assert_eq!(vec!["GET".to_string()], matcher.methods);
+}
+
+#[test]
+fn test_convert_response_header_case() {
+    let response: SavedResponse = serde_json::from_value(serde_json::json!({
+        "code": 201,
+        "header": [
+            {"key": "content-type", "value": "application/json"},
+            {"key": "x-trace", "value": "abc"}
+        ],
+        "body": "{}"
+    }))
+    .unwrap();
+
+    let config = convert_response(&response, &Default::default());
+    assert_eq!(
+        BTreeMap::from([("content-type".to_string(), "application/json".to_string())]),
+        config.headers
+    );
+    assert!(matches!(
+        config.body,
+        Some(StaticResponseBodyConfig::Json(()))
+    ));
+}
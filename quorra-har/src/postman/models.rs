@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+/// A Postman collection using the v2.1 schema. Only the fields needed to build
+/// static responses are modeled.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PostmanCollection {
+    #[serde(default)]
+    pub item: Vec<CollectionItem>,
+    #[serde(default)]
+    pub variable: Vec<Variable>,
+}
+
+/// Either a folder (with `item`) or a request (with `request` and `response`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionItem {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub item: Option<Vec<CollectionItem>>,
+    #[serde(default)]
+    pub request: Option<RequestEntry>,
+    #[serde(default)]
+    pub response: Vec<SavedResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Variable {
+    pub key: String,
+    #[serde(default)]
+    pub value: serde_json::Value,
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Header {
+    pub key: String,
+    #[serde(default)]
+    pub value: String,
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum RequestUrl {
+    Raw(String),
+    Detailed(UrlDetail),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UrlDetail {
+    #[serde(default)]
+    pub raw: String,
+    #[serde(default)]
+    pub variable: Vec<Variable>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RequestEntry {
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub header: Vec<Header>,
+    #[serde(default)]
+    pub url: Option<RequestUrl>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedResponse {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub original_request: Option<RequestEntry>,
+    #[serde(default)]
+    pub code: Option<u16>,
+    #[serde(default)]
+    pub header: Option<Vec<Header>>,
+    #[serde(default)]
+    pub body: Option<String>,
+}
@@ -0,0 +1,183 @@
+use crate::models::RequestWrapper;
+use anyhow::Error;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// Opens a HAR file for reading, `-` reads from stdin.
+pub(crate) fn open_input(path: &Path) -> Result<Box<dyn Read>, Error> {
+    if path == Path::new("-") {
+        return Ok(Box::new(BufReader::new(std::io::stdin())));
+    }
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+/// Calls `on_entry` for every entry in `log.entries`, one at a time, without
+/// holding the whole HAR in memory.
+pub(crate) fn read_entries<R, F>(reader: R, on_entry: F) -> Result<(), Error>
+where
+    R: Read,
+    F: FnMut(RequestWrapper) -> Result<(), Error>,
+{
+    let mut visitor = EntriesVisitor {
+        on_entry,
+        error: None,
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let result = HarSeed(&mut visitor).deserialize(&mut deserializer);
+
+    // An error from the callback is reported as is, not as a JSON syntax error.
+    if let Some(error) = visitor.error {
+        return Err(error);
+    }
+    result?;
+    deserializer.end()?;
+    Ok(())
+}
+
+struct EntriesVisitor<F> {
+    on_entry: F,
+    error: Option<Error>,
+}
+
+/// Walks `{ "log": { "entries": [...] } }`, ignoring every other field.
+struct HarSeed<'a, F>(&'a mut EntriesVisitor<F>);
+struct LogSeed<'a, F>(&'a mut EntriesVisitor<F>);
+struct EntriesSeed<'a, F>(&'a mut EntriesVisitor<F>);
+
+impl<'de, 'a, F> DeserializeSeed<'de> for HarSeed<'a, F>
+where
+    F: FnMut(RequestWrapper) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, F> Visitor<'de> for HarSeed<'a, F>
+where
+    F: FnMut(RequestWrapper) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a HAR file")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "log" {
+                map.next_value_seed(LogSeed(&mut *self.0))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, 'a, F> DeserializeSeed<'de> for LogSeed<'a, F>
+where
+    F: FnMut(RequestWrapper) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, F> Visitor<'de> for LogSeed<'a, F>
+where
+    F: FnMut(RequestWrapper) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a HAR log")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "entries" {
+                map.next_value_seed(EntriesSeed(&mut *self.0))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, 'a, F> DeserializeSeed<'de> for EntriesSeed<'a, F>
+where
+    F: FnMut(RequestWrapper) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a, F> Visitor<'de> for EntriesSeed<'a, F>
+where
+    F: FnMut(RequestWrapper) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of HAR entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(entry) = seq.next_element::<RequestWrapper>()? {
+            if let Err(e) = (self.0.on_entry)(entry) {
+                let message = e.to_string();
+                self.0.error = Some(e);
+                return Err(serde::de::Error::custom(message));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_read_entries_streams_log_entries() {
+    let har = r#"{
+        "log": {
+            "version": "1.2",
+            "pages": [{"id": "page_1"}],
+            "entries": [
+                {
+                    "startedDateTime": "2023-01-01T00:00:00.000Z",
+                    "request": {"method": "GET", "url": "http://localhost/a", "headers": [], "cookies": [], "queryString": []},
+                    "response": {"status": 200, "statusText": "OK", "redirectURL": "", "headers": [], "cookies": [], "content": {"mimeType": "text/plain", "text": "a"}}
+                },
+                {
+                    "startedDateTime": "2023-01-01T00:00:01.000Z",
+                    "request": {"method": "POST", "url": "http://localhost/b", "headers": [], "cookies": [], "queryString": []},
+                    "response": {"status": 201, "statusText": "Created", "redirectURL": "", "headers": [], "cookies": [], "content": {"mimeType": "text/plain"}}
+                }
+            ],
+            "comment": "after the entries"
+        }
+    }"#;
+
+    let mut urls = Vec::new();
+    read_entries(har.as_bytes(), |entry| {
+        urls.push(entry.request.url);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(vec!["http://localhost/a", "http://localhost/b"], urls);
+
+    let error = read_entries(har.as_bytes(), |_| Err(anyhow::anyhow!("stop"))).unwrap_err();
+    assert_eq!("stop", error.to_string());
+}
@@ -0,0 +1,320 @@
+use crate::models::*;
+use anyhow::Error;
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+lazy_static! {
+    static ref JWT: Regex =
+        Regex::new(r"eyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*").unwrap();
+    static ref EMAIL: Regex =
+        Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}").unwrap();
+    /// 15 or 16 digits starting like the numbers of Amex, Visa, Mastercard or
+    /// Discover, so that timestamps and long ids are left alone.
+    static ref CREDIT_CARD: Regex = Regex::new(r"\b[3-6](?:[ -]?[0-9]){14,15}\b").unwrap();
+}
+
+/// A rule for finding sensitive data in a recording.
+#[derive(Debug, Clone)]
+pub enum ScrubRule {
+    /// JSON Web Tokens, replaced with an unsigned token.
+    Jwt,
+    /// Email addresses, replaced with an `example.com` address.
+    Email,
+    /// Card numbers of 15 or 16 digits starting with 3 to 6 that pass the Luhn
+    /// check, replaced with another valid number.
+    CreditCard,
+    /// Any match of the regex. When the regex has a capture group, only the first
+    /// group is replaced.
+    Regex(Regex),
+    /// Values selected by a JSONPath expression in JSON bodies.
+    JsonPath(String),
+}
+
+/// Replaces sensitive values with pseudonyms. Pseudonyms are derived from a keyed
+/// hash of the real value, so the same value always maps to the same fake value.
+#[derive(Debug, Clone, Default)]
+pub struct Scrubber {
+    rules: Vec<ScrubRule>,
+    salt: String,
+}
+
+impl Scrubber {
+    pub fn new(rules: Vec<ScrubRule>, salt: &str) -> Self {
+        Self {
+            rules,
+            salt: salt.to_string(),
+        }
+    }
+
+    /// The built-in detectors: JWTs, emails and credit cards.
+    pub fn builtin_rules() -> Vec<ScrubRule> {
+        vec![ScrubRule::Jwt, ScrubRule::Email, ScrubRule::CreditCard]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Scrubs every part of a recorded entry that ends up in the generated config.
+    pub fn scrub_entry(&self, entry: &mut RequestWrapper) -> Result<(), Error> {
+        let request = &mut entry.request;
+        request.url = self.scrub_text(&request.url);
+        self.scrub_name_values(&mut request.headers);
+        self.scrub_name_values(&mut request.cookies);
+        self.scrub_name_values(&mut request.query_string);
+        if let Some(post_data) = &mut request.post_data {
+            if let Some(text) = &post_data.text {
+                post_data.text = Some(self.scrub_body(text)?);
+            }
+        }
+
+        let response = &mut entry.response;
+        self.scrub_name_values(&mut response.headers);
+        for cookie in &mut response.cookies {
+            cookie.value = self.scrub_text(&cookie.value);
+        }
+
+        let content = &mut response.content;
+        if let Some(text) = &content.text {
+            match content.encoding.as_deref() {
+                Some("base64") => {
+                    if let Ok(decoded) = String::from_utf8(STANDARD.decode(text.trim())?) {
+                        content.text = Some(self.scrub_body(&decoded)?);
+                        content.encoding = None;
+                    }
+                }
+                _ => content.text = Some(self.scrub_body(text)?),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scrub_name_values(&self, values: &mut [NameValueEntry]) {
+        for value in values {
+            value.value = self.scrub_text(&value.value);
+        }
+    }
+
+    /// Applies the JSONPath rules when the body is JSON, then the text rules.
+    fn scrub_body(&self, body: &str) -> Result<String, Error> {
+        let json_paths: Vec<&String> = self
+            .rules
+            .iter()
+            .filter_map(|rule| match rule {
+                ScrubRule::JsonPath(path) => Some(path),
+                _ => None,
+            })
+            .collect();
+
+        // The body is only written back when a value was replaced, writing it
+        // minifies it and sorts its keys.
+        let body = match serde_json::from_str::<Value>(body) {
+            Ok(mut json) if !json_paths.is_empty() => {
+                let mut replaced = false;
+                for path in json_paths {
+                    json = jsonpath_lib::replace_with(json, path, &mut |value| {
+                        replaced = true;
+                        Some(self.pseudonymize_json(&value))
+                    })
+                    .map_err(|e| anyhow::anyhow!("Invalid JSONPath {}: {:?}", path, e))?;
+                }
+                if replaced {
+                    json.to_string()
+                } else {
+                    body.to_string()
+                }
+            }
+            _ => body.to_string(),
+        };
+
+        Ok(self.scrub_text(&body))
+    }
+
+    pub fn scrub_text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for rule in &self.rules {
+            text = match rule {
+                ScrubRule::Jwt => JWT
+                    .replace_all(&text, |caps: &Captures| self.fake_jwt(&caps[0]))
+                    .to_string(),
+                ScrubRule::Email => EMAIL
+                    .replace_all(&text, |caps: &Captures| self.fake_email(&caps[0]))
+                    .to_string(),
+                ScrubRule::CreditCard => CREDIT_CARD
+                    .replace_all(&text, |caps: &Captures| self.fake_card(&caps[0]))
+                    .to_string(),
+                ScrubRule::Regex(regex) => regex
+                    .replace_all(&text, |caps: &Captures| match caps.get(1) {
+                        Some(group) => {
+                            let whole = caps.get(0).unwrap();
+                            let mut replaced = whole.as_str().to_string();
+                            replaced.replace_range(
+                                group.start() - whole.start()..group.end() - whole.start(),
+                                &self.fake_value(group.as_str()),
+                            );
+                            replaced
+                        }
+                        None => self.fake_value(&caps[0]),
+                    })
+                    .to_string(),
+                ScrubRule::JsonPath(_) => text,
+            };
+        }
+        text
+    }
+
+    fn hash(&self, value: &str) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update([0]);
+        hasher.update(value.as_bytes());
+        hasher.finalize().to_vec()
+    }
+
+    fn short_hash(&self, value: &str) -> String {
+        self.hash(value)
+            .iter()
+            .take(5)
+            .map(|x| format!("{:02x}", x))
+            .collect()
+    }
+
+    fn fake_value(&self, value: &str) -> String {
+        format!("redacted-{}", self.short_hash(value))
+    }
+
+    fn fake_email(&self, value: &str) -> String {
+        format!("user-{}@example.com", self.short_hash(value))
+    }
+
+    fn fake_jwt(&self, value: &str) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none","typ":"JWT"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(format!(r#"{{"sub":"{}"}}"#, self.fake_value(value)));
+        format!("{}.{}.", header, claims)
+    }
+
+    /// Card numbers keep their length and separators, and still pass the Luhn check.
+    fn fake_card(&self, value: &str) -> String {
+        let digits: Vec<u32> = value.chars().filter_map(|c| c.to_digit(10)).collect();
+        if !luhn_valid(&digits) {
+            return value.to_string();
+        }
+
+        let hash = self.hash(value);
+        let mut fake: Vec<u32> = vec![4];
+        for i in 1..digits.len() - 1 {
+            fake.push(u32::from(hash[i % hash.len()]) % 10);
+        }
+        fake.push(0);
+        let check = (10 - luhn_remainder(&fake)) % 10;
+        *fake.last_mut().unwrap() = check;
+
+        let mut fake_digits = fake.into_iter();
+        value
+            .chars()
+            .map(|c| match c.to_digit(10) {
+                Some(_) => char::from_digit(fake_digits.next().unwrap(), 10).unwrap(),
+                None => c,
+            })
+            .collect()
+    }
+
+    fn pseudonymize_json(&self, value: &Value) -> Value {
+        match value {
+            Value::Null => Value::Null,
+            Value::Number(_) => {
+                let hash = self.hash(&value.to_string());
+                Value::from(u32::from_be_bytes([0, hash[0], hash[1], hash[2]]))
+            }
+            Value::String(text) => Value::from(self.fake_value(text)),
+            other => Value::from(self.fake_value(&other.to_string())),
+        }
+    }
+}
+
+/// The Luhn sum of the digits modulo 10, which is 0 for a valid number.
+fn luhn_remainder(digits: &[u32]) -> u32 {
+    digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| {
+            if i % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 {
+                    doubled - 9
+                } else {
+                    doubled
+                }
+            } else {
+                *digit
+            }
+        })
+        .sum::<u32>()
+        % 10
+}
+
+fn luhn_valid(digits: &[u32]) -> bool {
+    matches!(digits.len(), 15 | 16)
+        && matches!(digits.first(), Some(3..=6))
+        && luhn_remainder(digits) == 0
+}
+
+#[test]
+fn test_scrub_text_is_consistent() {
+    let scrubber = Scrubber::new(Scrubber::builtin_rules(), "salt");
+    let text = "from bob@example.org card 4111 1111 1111 1111 order 1234567890123";
+    let scrubbed = scrubber.scrub_text(text);
+
+    assert!(!scrubbed.contains("bob@example.org"));
+    assert!(!scrubbed.contains("4111 1111 1111 1111"));
+    assert!(scrubbed.contains("order 1234567890123"));
+
+    // A millisecond timestamp passing the Luhn check isn't a card.
+    let timestamp = "at 1760781600000";
+    assert_eq!(0, luhn_remainder(&[1, 7, 6, 0, 7, 8, 1, 6, 0, 0, 0, 0, 0]));
+    assert_eq!(timestamp, scrubber.scrub_text(timestamp));
+    assert_ne!("6011000000000004", scrubber.scrub_text("6011000000000004"));
+    assert_eq!(scrubbed, scrubber.scrub_text(text));
+
+    let card = scrubbed.split("card ").nth(1).unwrap()[..19].to_string();
+    let digits: Vec<u32> = card.chars().filter_map(|c| c.to_digit(10)).collect();
+    assert!(luhn_valid(&digits));
+    assert_eq!(" ", &card[4..5]);
+}
+
+#[test]
+fn test_scrub_body_json_path_and_regex() {
+    let scrubber = Scrubber::new(
+        vec![
+            ScrubRule::JsonPath("$.account.number".to_string()),
+            ScrubRule::Regex(Regex::new("Bearer ([a-z0-9]+)").unwrap()),
+        ],
+        "",
+    );
+    let scrubbed = scrubber
+        .scrub_body(r#"{"account": {"number": "12-34", "name": "x"}, "auth": "Bearer abc123"}"#)
+        .unwrap();
+    let json: Value = serde_json::from_str(&scrubbed).unwrap();
+
+    assert_eq!(scrubber.fake_value("12-34"), json["account"]["number"]);
+    assert_eq!("x", json["account"]["name"]);
+    assert_eq!(
+        format!("Bearer {}", scrubber.fake_value("abc123")),
+        json["auth"]
+    );
+}
+
+#[test]
+fn test_scrub_body_keeps_unmatched_json() {
+    let scrubber = Scrubber::new(vec![ScrubRule::JsonPath("$.password".to_string())], "");
+    let body = "{\n  \"name\": \"x\",\n  \"age\": 3\n}";
+    assert_eq!(body, scrubber.scrub_body(body).unwrap());
+}
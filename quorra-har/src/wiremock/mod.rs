@@ -0,0 +1,712 @@
+use anyhow::{bail, Error};
+use quorra_config::prelude::*;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+};
+use tracing::{debug, info};
+
+mod models;
+
+use models::*;
+
+/// Everything a conversion was not able to express in the target format.
+#[derive(Debug, Default)]
+pub struct ConversionReport {
+    pub entries: Vec<ReportEntry>,
+}
+
+#[derive(Debug)]
+pub struct ReportEntry {
+    /// The mapping or payload the problem was found in.
+    pub source: String,
+    pub message: String,
+}
+
+impl ConversionReport {
+    pub(crate) fn add(&mut self, source: &str, message: impl Into<String>) {
+        self.entries.push(ReportEntry {
+            source: source.to_string(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Display for ReportEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.source, self.message)
+    }
+}
+
+/// Converts a directory of WireMock mappings into Quorra static-http configs.
+pub struct WiremockConvertor {
+    mappings_dir: PathBuf,
+    files_dir: PathBuf,
+}
+
+impl WiremockConvertor {
+    /// `files_dir` is where `bodyFileName` is resolved from. When not provided, the
+    /// WireMock default of `__files` next to the mappings directory is used.
+    pub fn new(mappings_dir: &Path, files_dir: Option<&Path>) -> Self {
+        let files_dir = match files_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => mappings_dir
+                .parent()
+                .unwrap_or(mappings_dir)
+                .join("__files"),
+        };
+        Self {
+            mappings_dir: mappings_dir.to_path_buf(),
+            files_dir,
+        }
+    }
+
+    pub async fn convert(&self, dest: &Path) -> Result<ConversionReport, Error> {
+        let mut report = ConversionReport::default();
+        let mut mapping_files = Vec::new();
+        find_json_files(&self.mappings_dir, &mut mapping_files)?;
+        mapping_files.sort();
+
+        let mut index = 0;
+        for mapping_file in mapping_files {
+            debug!("Reading mapping {}", mapping_file.display());
+            let mappings = match serde_json::from_str(&std::fs::read_to_string(&mapping_file)?)? {
+                MappingsFile::Many { mappings } => mappings,
+                MappingsFile::Single(mapping) => vec![*mapping],
+            };
+
+            for mapping in mappings {
+                index += 1;
+                let source = mapping
+                    .name
+                    .clone()
+                    .or_else(|| mapping.id.clone())
+                    .unwrap_or_else(|| format!("{}#{}", mapping_file.display(), index));
+
+                let config = self.import_mapping(&source, &mapping, dest, &mut report)?;
+                let config = ResponseConfig::StaticHttp(config);
+
+                let filename = format!("{:03}_{}.yaml", index, file_safe_name(&source));
+                let path = dest.join(filename);
+                info!("Writing file {}", path.display());
+                std::fs::write(path, serde_yaml::to_string(&config)?)?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn import_mapping(
+        &self,
+        source: &str,
+        mapping: &StubMapping,
+        dest: &Path,
+        report: &mut ConversionReport,
+    ) -> Result<StaticHttpConfig<ResponseData>, Error> {
+        if mapping.priority.is_some() {
+            report.add(
+                source,
+                "`priority` is not supported, Quorra matches payloads in file order",
+            );
+        }
+        report_unsupported(source, "", &mapping.unsupported, report);
+
+        let request = &mapping.request;
+        report_unsupported(source, "request.", &request.unsupported, report);
+        if !request.body_patterns.is_empty() {
+            report.add(source, "`request.bodyPatterns` are not supported");
+        }
+
+        let mut query = Vec::new();
+        let path = if let Some(url) = &request.url {
+            let (path, query_string) = url.split_once('?').unwrap_or((url, ""));
+            for (name, value) in url::form_urlencoded::parse(query_string.as_bytes()) {
+                query.push((name.to_string(), format!("^{}$", regex::escape(&value))));
+            }
+            regex::escape(path)
+        } else if let Some(path) = &request.url_path {
+            regex::escape(path)
+        } else if let Some(pattern) = &request.url_path_pattern {
+            pattern.clone()
+        } else if let Some(pattern) = &request.url_pattern {
+            if pattern.contains('?') {
+                report.add(
+                    source,
+                    "`request.urlPattern` with a query is matched against the path only",
+                );
+            }
+            pattern.clone()
+        } else {
+            ".*".to_string()
+        };
+
+        for (name, pattern) in &request.query_parameters {
+            if let Some(value) = import_value_pattern(source, "query", name, pattern, report) {
+                query.push((name.clone(), value));
+            }
+        }
+
+        let mut headers = BTreeMap::new();
+        for (name, pattern) in &request.headers {
+            if let Some(value) = import_value_pattern(source, "header", name, pattern, report) {
+                headers.insert(name.clone(), value);
+            }
+        }
+
+        let methods = match request.method.as_deref() {
+            None | Some("ANY") => vec![],
+            Some(method) => vec![method.to_string()],
+        };
+
+        let matcher = StaticMatchesConfig {
+            path,
+            host: None,
+            protocol: None,
+            query,
+            headers,
+            methods,
+            graphql: None,
+            body: None,
+        };
+
+        let response = self.import_response(source, &mapping.response, dest, report)?;
+
+        Ok(StaticHttpConfig {
+            id: mapping.id.clone().unwrap_or_else(unique_id),
+            matches: vec![matcher],
+            responses: vec![response],
+        })
+    }
+
+    fn import_response(
+        &self,
+        source: &str,
+        response: &ResponseDefinition,
+        dest: &Path,
+        report: &mut ConversionReport,
+    ) -> Result<StaticResponseConfig<ResponseData>, Error> {
+        report_unsupported(source, "response.", &response.unsupported, report);
+
+        let mut headers = BTreeMap::new();
+        let mut cookies = Vec::new();
+        for (name, value) in &response.headers {
+            if name.eq_ignore_ascii_case("Set-Cookie") {
+                match value {
+                    Value::Array(values) => cookies.extend(
+                        values
+                            .iter()
+                            .map(|x| x.as_str().map(str::to_string).unwrap_or(x.to_string())),
+                    ),
+                    Value::String(value) => cookies.push(value.clone()),
+                    other => cookies.push(other.to_string()),
+                }
+                continue;
+            }
+
+            let value = match value {
+                Value::String(value) => value.clone(),
+                Value::Array(values) => {
+                    report.add(
+                        source,
+                        format!("multi-value header `{}` was joined into one value", name),
+                    );
+                    values
+                        .iter()
+                        .map(|x| x.as_str().map(str::to_string).unwrap_or(x.to_string()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+                other => other.to_string(),
+            };
+            headers.insert(name.clone(), value);
+        }
+
+        let body = if let Some(body) = &response.body {
+            Some(StaticResponseBodyConfig::Raw(ResponseData::Data(
+                body.clone(),
+            )))
+        } else if let Some(json_body) = &response.json_body {
+            Some(StaticResponseBodyConfig::Json(ResponseData::Data(
+                serde_json::to_string_pretty(json_body)?,
+            )))
+        } else if let Some(body_file_name) = &response.body_file_name {
+            // Both the source and the copy have to stay in their directories.
+            let inside = Path::new(body_file_name)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if !inside {
+                bail!(
+                    "{}: `bodyFileName` {} has to be relative, without `..`",
+                    source,
+                    body_file_name
+                );
+            }
+            let target = dest.join(body_file_name);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            info!("Creating asset file {}", target.display());
+            std::fs::copy(self.files_dir.join(body_file_name), &target)?;
+            Some(StaticResponseBodyConfig::Raw(ResponseData::File(
+                body_file_name.into(),
+            )))
+        } else {
+            response.base64_body.as_ref().map(|base64_body| {
+                StaticResponseBodyConfig::Binary(ResponseData::Data(base64_body.clone()))
+            })
+        };
+
+        Ok(StaticResponseConfig {
+            id: unique_id(),
+            weight: 1,
+            status: response.status,
+            headers,
+            cookies,
+            trailers: Default::default(),
+            body,
+            delay: response.fixed_delay_milliseconds.unwrap_or_default(),
+        })
+    }
+}
+
+fn import_value_pattern(
+    source: &str,
+    kind: &str,
+    name: &str,
+    pattern: &ValuePattern,
+    report: &mut ConversionReport,
+) -> Option<String> {
+    report_unsupported(
+        source,
+        &format!("{} `{}` matcher ", kind, name),
+        &pattern.unsupported,
+        report,
+    );
+
+    let flags = if pattern.case_insensitive { "(?i)" } else { "" };
+    if let Some(value) = &pattern.equal_to {
+        Some(format!("{}^{}$", flags, regex::escape(value)))
+    } else if let Some(value) = &pattern.matches {
+        Some(format!("{}^(?:{})$", flags, value))
+    } else if let Some(value) = &pattern.contains {
+        Some(format!("{}{}", flags, regex::escape(value)))
+    } else {
+        report.add(
+            source,
+            format!("{} `{}` has no matcher Quorra understands", kind, name),
+        );
+        None
+    }
+}
+
+fn report_unsupported(
+    source: &str,
+    prefix: &str,
+    fields: &BTreeMap<String, Value>,
+    report: &mut ConversionReport,
+) {
+    for name in fields.keys() {
+        report.add(source, format!("{}`{}` is not supported", prefix, name));
+    }
+}
+
+fn find_json_files(dir: &Path, found: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_json_files(&path, found)?;
+        } else if path.extension().map(|x| x == "json").unwrap_or_default() {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn file_safe_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Writes loaded Quorra configs out as WireMock mappings.
+pub struct WiremockExporter {
+    root_config: PathBuf,
+}
+
+impl WiremockExporter {
+    pub fn new(root_config: &Path) -> Self {
+        Self {
+            root_config: root_config.to_path_buf(),
+        }
+    }
+
+    /// The mappings of the default listener are written to `dest/mappings`. A
+    /// WireMock server only listens on one port, so every named listener gets its
+    /// own root, with its mappings in `dest/listeners/<name>/mappings`.
+    pub async fn export(&self, dest: &Path) -> Result<ConversionReport, Error> {
+        let mut report = ConversionReport::default();
+        let loaded = ConfigContainer::new(&self.root_config).load_config()?;
+
+        export_mappings(&dest.join("mappings"), &loaded.responses, &mut report)?;
+        for (name, responses) in &loaded.listeners {
+            let mappings_dir = dest
+                .join("listeners")
+                .join(file_safe_name(name))
+                .join("mappings");
+            export_mappings(&mappings_dir, responses, &mut report)?;
+        }
+
+        Ok(report)
+    }
+}
+
+fn export_mappings(
+    mappings_dir: &Path,
+    responses: &[ResponseConfig<String>],
+    report: &mut ConversionReport,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(mappings_dir)?;
+
+    for response_config in responses {
+        match response_config {
+            ResponseConfig::StaticHttp(http) => {
+                let mappings = export_payload(http, report);
+                for (index, mapping) in mappings.iter().enumerate() {
+                    let filename = format!("{}_{}.json", file_safe_name(&http.id), index);
+                    let path = mappings_dir.join(filename);
+                    info!("Writing file {}", path.display());
+                    std::fs::write(path, serde_json::to_string_pretty(mapping)?)?;
+                }
+            }
+            ResponseConfig::Websocket(websocket) => {
+                report.add(
+                    &websocket.id,
+                    "WebSocket payloads are not supported, skipping",
+                );
+            }
+            ResponseConfig::Grpc(grpc) => {
+                report.add(&grpc.id, "gRPC payloads are not supported, skipping");
+            }
+            ResponseConfig::Tcp(tcp) => {
+                report.add(&tcp.id, "TCP payloads are not supported, skipping");
+            }
+            ResponseConfig::Smtp(smtp) => {
+                report.add(&smtp.id, "SMTP payloads are not supported, skipping");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn export_payload(
+    config: &StaticHttpConfig<String>,
+    report: &mut ConversionReport,
+) -> Vec<StubMapping> {
+    let source = &config.id;
+    let response = match config.responses.first() {
+        Some(response) => response,
+        None => {
+            report.add(source, "payload has no responses, skipping");
+            return vec![];
+        }
+    };
+
+    if config.responses.len() > 1 {
+        report.add(
+            source,
+            format!(
+                "only the first of {} weighted responses was exported",
+                config.responses.len()
+            ),
+        );
+    }
+
+    let response = export_response(source, response, report);
+
+    let mut mappings = Vec::new();
+    for matcher in &config.matches {
+        let mut request = RequestPattern {
+            url_path_pattern: Some(if matcher.path.is_empty() {
+                ".*".to_string()
+            } else {
+                matcher.path.clone()
+            }),
+            ..Default::default()
+        };
+
+        for (name, value) in &matcher.query {
+            request
+                .query_parameters
+                .insert(name.clone(), export_value_pattern(value));
+        }
+
+        for (name, value) in &matcher.headers {
+            request
+                .headers
+                .insert(name.clone(), export_value_pattern(value));
+        }
+
+        // The port is ignored when matching hosts, but is part of the `Host` header.
+        if let Some(host) = &matcher.host {
+            request.headers.insert(
+                "Host".to_string(),
+                ValuePattern {
+                    matches: Some(format!("(?:{})(?::[0-9]+)?", host)),
+                    ..Default::default()
+                },
+            );
+        }
+
+        if let Some(protocol) = &matcher.protocol {
+            report.add(
+                source,
+                format!(
+                    "the `{}` protocol matcher is not supported, the mapping matches every protocol",
+                    protocol
+                ),
+            );
+        }
+
+        if let Some(graphql) = &matcher.graphql {
+            request.body_patterns.push(serde_json::json!({
+                "matchesJsonPath": {
+                    "expression": "$.operationName",
+                    "matches": graphql.operation_name,
+                }
+            }));
+        }
+
+        let methods = if matcher.methods.is_empty() {
+            vec!["ANY".to_string()]
+        } else {
+            matcher.methods.clone()
+        };
+
+        for method in methods {
+            mappings.push(StubMapping {
+                name: Some(config.id.clone()),
+                request: RequestPattern {
+                    method: Some(method),
+                    ..request.clone()
+                },
+                response: response.clone(),
+                ..Default::default()
+            });
+        }
+    }
+
+    mappings
+}
+
+fn export_response(
+    source: &str,
+    response: &StaticResponseConfig<String>,
+    report: &mut ConversionReport,
+) -> ResponseDefinition {
+    let mut definition = ResponseDefinition {
+        status: response.status,
+        headers: response
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::from(value.clone())))
+            .collect(),
+        ..Default::default()
+    };
+
+    if !response.cookies.is_empty() {
+        definition.headers.insert(
+            "Set-Cookie".to_string(),
+            Value::from(response.cookies.clone()),
+        );
+    }
+
+    if response.delay != 0 {
+        definition.fixed_delay_milliseconds = Some(response.delay);
+    }
+
+    let event_stream;
+    let body = match &response.body {
+        None | Some(StaticResponseBodyConfig::Empty) => None,
+        Some(StaticResponseBodyConfig::Json(body)) => {
+            definition
+                .headers
+                .entry("Content-Type".to_string())
+                .or_insert_with(|| Value::from("application/json"));
+            Some(body)
+        }
+        Some(StaticResponseBodyConfig::Raw(body)) => Some(body),
+        Some(StaticResponseBodyConfig::Binary(body)) => {
+            definition.base64_body = Some(body.clone());
+            None
+        }
+        Some(StaticResponseBodyConfig::Sse(sse)) => {
+            definition
+                .headers
+                .entry("Content-Type".to_string())
+                .or_insert_with(|| Value::from("text/event-stream"));
+            report.add(
+                source,
+                format!(
+                    "response `{}` streams events, WireMock will send them at once without their delays",
+                    response.id
+                ),
+            );
+            event_stream = sse.to_event_stream();
+            Some(&event_stream)
+        }
+    };
+
+    if let Some(body) = body {
+        if body.contains("{{") {
+            report.add(
+                source,
+                format!(
+                    "response `{}` uses Handlebars templating, which WireMock will return verbatim",
+                    response.id
+                ),
+            );
+        }
+        definition.body = Some(body.clone());
+    }
+
+    definition
+}
+
+/// Quorra header and query regexes are not anchored, WireMock's `matches` is.
+fn export_value_pattern(value: &str) -> ValuePattern {
+    let matches = if value.starts_with('^') && value.ends_with('$') {
+        value[1..value.len() - 1].to_string()
+    } else {
+        format!(".*(?:{}).*", value)
+    };
+
+    ValuePattern {
+        matches: Some(matches),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_import_mapping() {
+    let mapping: StubMapping = serde_json::from_value(serde_json::json!({
+        "id": "abc",
+        "scenarioName": "login",
+        "request": {
+            "method": "GET",
+            "url": "/v1.0/users?id=1",
+            "headers": {
+                "Accept": { "matches": "application/.*" },
+                "X-Trace": { "absent": true }
+            }
+        },
+        "response": {
+            "status": 201,
+            "body": "hello",
+            "fixedDelayMilliseconds": 50
+        }
+    }))
+    .unwrap();
+
+    let mut report = ConversionReport::default();
+    let convertor = WiremockConvertor::new(Path::new("mappings"), None);
+    let config = convertor
+        .import_mapping("abc", &mapping, Path::new("."), &mut report)
+        .unwrap();
+
+    assert_eq!("abc", config.id);
+    let matcher = &config.matches[0];
+    assert_eq!("/v1\\.0/users", matcher.path);
+    assert_eq!(vec![("id".to_string(), "^1$".to_string())], matcher.query);
+    assert_eq!(
+        Some(&"^(?:application/.*)$".to_string()),
+        matcher.headers.get("Accept")
+    );
+    assert!(!matcher.headers.contains_key("X-Trace"));
+    assert_eq!(201, config.responses[0].status);
+    assert_eq!(50, config.responses[0].delay);
+
+    let messages: Vec<String> = report.entries.iter().map(|x| x.to_string()).collect();
+    assert_eq!(3, messages.len(), "{:?}", messages);
+    assert!(messages[0].contains("scenarioName"));
+}
+
+#[test]
+fn test_export_value_pattern() {
+    assert_eq!(
+        Some("foo".to_string()),
+        export_value_pattern("^foo$").matches
+    );
+    assert_eq!(
+        Some(".*(?:foo).*".to_string()),
+        export_value_pattern("foo").matches
+    );
+}
+
+#[test]
+fn test_import_rejects_body_files_outside() {
+    let convertor = WiremockConvertor::new(Path::new("mappings"), None);
+    for name in ["../secret.json", "/etc/passwd", "a/../../b"] {
+        let response: ResponseDefinition =
+            serde_json::from_value(serde_json::json!({ "status": 200, "bodyFileName": name }))
+                .unwrap();
+        let mut report = ConversionReport::default();
+        let error = convertor
+            .import_response("abc", &response, Path::new("."), &mut report)
+            .unwrap_err();
+        assert!(error.to_string().contains(name), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn test_export_listeners_to_their_own_root() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("config.toml"),
+        "[responses]\npaths = [\"users.yaml\"]\n\n[listeners.payments]\naddress = \"127.0.0.1:4001\"\nresponses.paths = [\"payments.yaml\"]\n",
+    )
+    .unwrap();
+    let payload = |id: &str| {
+        format!(
+            "type: static-http\nid: {}\nmatches:\n  - path: /{}\n    methods: [GET]\nresponses:\n  - status: 200\n",
+            id, id
+        )
+    };
+    std::fs::write(dir.path().join("users.yaml"), payload("users")).unwrap();
+    std::fs::write(dir.path().join("payments.yaml"), payload("pay")).unwrap();
+
+    let dest = dir.path().join("wiremock");
+    let report = WiremockExporter::new(&dir.path().join("config.toml"))
+        .export(&dest)
+        .await
+        .unwrap();
+    assert!(report.is_empty());
+    assert!(dest.join("mappings/users_0.json").exists());
+    assert!(!dest.join("mappings/pay_0.json").exists());
+    assert!(dest.join("listeners/payments/mappings/pay_0.json").exists());
+}
+
+#[test]
+fn test_export_reports_protocol_matcher() {
+    let config: StaticHttpConfig<String> = serde_yaml::from_str(
+        "id: h2\nmatches:\n  - path: /a\n    protocol: HTTP/2\nresponses:\n  - status: 200\n",
+    )
+    .unwrap();
+    let mut report = ConversionReport::default();
+    let mappings = export_payload(&config, &mut report);
+    assert_eq!(1, mappings.len());
+    assert_eq!(1, report.entries.len());
+    assert!(report.entries[0].message.contains("HTTP/2"));
+}
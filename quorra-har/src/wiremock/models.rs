@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// A WireMock mapping file holds either a single stub or a list of them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MappingsFile {
+    Many { mappings: Vec<StubMapping> },
+    Single(Box<StubMapping>),
+}
+
+/// A single WireMock stub. Fields that Quorra does not understand are kept in
+/// `unsupported` so they can be reported instead of being dropped.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StubMapping {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
+    #[serde(default)]
+    pub request: RequestPattern,
+    #[serde(default)]
+    pub response: ResponseDefinition,
+    #[serde(flatten)]
+    pub unsupported: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestPattern {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_path_pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub query_parameters: BTreeMap<String, ValuePattern>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, ValuePattern>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub body_patterns: Vec<Value>,
+    #[serde(flatten)]
+    pub unsupported: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ValuePattern {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equal_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub case_insensitive: bool,
+    #[serde(flatten)]
+    pub unsupported: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseDefinition {
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_body: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base64_body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_file_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_delay_milliseconds: Option<u64>,
+    #[serde(flatten)]
+    pub unsupported: BTreeMap<String, Value>,
+}
+
+impl Default for ResponseDefinition {
+    fn default() -> Self {
+        Self {
+            status: default_status(),
+            headers: Default::default(),
+            body: None,
+            json_body: None,
+            base64_body: None,
+            body_file_name: None,
+            fixed_delay_milliseconds: None,
+            unsupported: Default::default(),
+        }
+    }
+}
+
+fn default_status() -> u16 {
+    200
+}
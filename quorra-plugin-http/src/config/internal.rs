@@ -1,12 +1,15 @@
-use crate::HttpPluginError;
+use crate::{sse::EventStream, HttpPluginError};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use handlebars::Handlebars;
 use http::{
-    header::CONTENT_TYPE,
     header::{HeaderMap, HeaderName, HeaderValue},
+    header::{CACHE_CONTROL, CONTENT_TYPE, SET_COOKIE},
     Method, StatusCode,
 };
 use quorra_config::prelude::*;
 use rand::{seq::SliceRandom, thread_rng};
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 use std::str::FromStr;
 use std::sync::{atomic::AtomicUsize, Arc};
 use std::{collections::BTreeMap, time::Duration};
@@ -14,15 +17,24 @@ use std::{collections::BTreeMap, time::Duration};
 #[derive(Debug, Clone)]
 pub struct PluginBackendConfig {
     pub payloads: Vec<PayloadBackendConfig>,
+    /// The response bodies, registered under the id of their response.
+    pub handlebars: Arc<Handlebars<'static>>,
 }
 
 impl PluginBackendConfig {
     pub fn try_from(configs: &[StaticHttpConfig<String>]) -> Result<Self, HttpPluginError> {
+        let mut handlebars = crate::new_registry();
         let mut payloads = Vec::new();
         for http_config in configs {
-            payloads.push(PayloadBackendConfig::from_http_config(http_config)?);
+            payloads.push(PayloadBackendConfig::from_http_config(
+                http_config,
+                &mut handlebars,
+            )?);
         }
-        Ok(Self { payloads })
+        Ok(Self {
+            payloads,
+            handlebars: Arc::new(handlebars),
+        })
     }
 }
 
@@ -34,10 +46,40 @@ pub struct PayloadBackendConfig {
 }
 
 impl PayloadBackendConfig {
-    fn from_http_config(config: &StaticHttpConfig<String>) -> Result<Self, HttpPluginError> {
+    /// Builds every matcher and response separately, collecting all the errors
+    /// along with the part of the payload they were found in.
+    pub fn validate(config: &StaticHttpConfig<String>) -> Vec<(String, HttpPluginError)> {
+        let mut errors = Vec::new();
+        for (index, matcher) in config.matches.iter().enumerate() {
+            if let Err(e) = RequestMatcher::from_matches_config(matcher) {
+                errors.push((format!("matches[{}]", index), e));
+            }
+        }
+
+        let mut handlebars = crate::new_registry();
+        for (index, response) in config.responses.iter().enumerate() {
+            if let Err(e) = StaticResponse::try_from(&config.id, response, &mut handlebars) {
+                errors.push((format!("responses[{}]", index), e));
+            }
+        }
+
+        if never_picked(&config.responses) {
+            errors.push((
+                "responses".to_string(),
+                HttpPluginError::NoResponsesProvided,
+            ));
+        }
+
+        errors
+    }
+
+    fn from_http_config(
+        config: &StaticHttpConfig<String>,
+        handlebars: &mut Handlebars<'static>,
+    ) -> Result<Self, HttpPluginError> {
         let payload_id = config.id.clone();
         let responses: StaticResponseContainer =
-            StaticResponseContainer::try_from(&payload_id, &config.responses)?;
+            StaticResponseContainer::try_from(&payload_id, &config.responses, handlebars)?;
         let matchers: Result<Vec<_>, _> = config
             .matches
             .iter()
@@ -59,18 +101,41 @@ pub struct RequestMatcher {
     pub query_params: Vec<QueryMatcher>,
     pub headers: Vec<HeaderMatcher>,
     pub graphql_operations: Option<Regex>,
+    pub body: Option<BodyMatcher>,
+    pub host: Option<Regex>,
+    pub protocol: Option<Regex>,
 }
 
 impl RequestMatcher {
-    fn from_matches_config(config: &StaticMatchesConfig) -> Result<Self, HttpPluginError> {
+    pub(crate) fn from_matches_config(
+        config: &StaticMatchesConfig,
+    ) -> Result<Self, HttpPluginError> {
         let gql_operations = config.graphql.clone().map(|x| x.operation_name);
-        RequestMatcher::new(
+        let mut matcher = RequestMatcher::new(
             &config.methods,
             &config.path,
             &config.query,
             &config.headers,
             &gql_operations,
-        )
+        )?;
+        matcher.body = match &config.body {
+            None => None,
+            Some(body) => Some(BodyMatcher::new(body)?),
+        };
+        // Host names are case insensitive.
+        matcher.host = match &config.host {
+            None => None,
+            Some(host) => Some(
+                RegexBuilder::new(&format!("^(?:{})$", host))
+                    .case_insensitive(true)
+                    .build()?,
+            ),
+        };
+        matcher.protocol = match &config.protocol {
+            None => None,
+            Some(protocol) => Some(Regex::new(&format!("^(?:{})$", protocol))?),
+        };
+        Ok(matcher)
     }
 
     pub fn new(
@@ -107,10 +172,33 @@ impl RequestMatcher {
             headers: matched_headers,
             methods: parsed_methods,
             graphql_operations: matched_graphql,
+            body: None,
+            host: None,
+            protocol: None,
         })
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct BodyMatcher {
+    pub regex: Option<Regex>,
+    pub json: Option<serde_json::Value>,
+}
+
+impl BodyMatcher {
+    pub fn new(config: &BodyStaticMatchConfig) -> Result<Self, HttpPluginError> {
+        let regex = match &config.regex {
+            None => None,
+            Some(regex) => Some(Regex::new(regex)?),
+        };
+        let json = match &config.json {
+            None => None,
+            Some(json) => Some(serde_json::from_str(json)?),
+        };
+        Ok(Self { regex, json })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct HeaderMatcher {
     pub name: HeaderName,
@@ -151,6 +239,7 @@ impl StaticResponseContainer {
     fn try_from(
         payload_id: &str,
         configs: &[StaticResponseConfig<String>],
+        handlebars: &mut Handlebars<'static>,
     ) -> Result<Self, HttpPluginError> {
         let mut configs = configs.to_owned();
         configs.sort_by(|a, b| a.weight.cmp(&b.weight));
@@ -158,7 +247,7 @@ impl StaticResponseContainer {
         let mut responses = Vec::new();
 
         for config in configs {
-            let static_response = StaticResponse::try_from(payload_id, &config)?;
+            let static_response = StaticResponse::try_from(payload_id, &config, handlebars)?;
             let static_response: Arc<StaticResponse> = Arc::new(static_response);
             for _i in 0..config.weight {
                 responses.push(static_response.clone());
@@ -182,7 +271,12 @@ impl StaticResponseContainer {
 pub struct StaticResponse {
     pub status: StatusCode,
     pub headers: HeaderMap,
+    pub trailers: HeaderMap,
     pub handlebar_template_id: String,
+    /// Binary bodies are sent as-is instead of rendering the template.
+    pub binary_body: Option<Bytes>,
+    /// Streamed in place of the body.
+    pub event_stream: Option<Arc<EventStream>>,
     pub delay: Duration,
 }
 
@@ -190,10 +284,13 @@ impl StaticResponse {
     fn try_from(
         payload_id: &str,
         value: &StaticResponseConfig<String>,
+        handlebars: &mut Handlebars<'static>,
     ) -> Result<Self, HttpPluginError> {
         let status_code = StatusCode::from_u16(value.status)?;
 
         let mut headers = HeaderMap::new();
+        let mut binary_body = None;
+        let mut event_stream = None;
 
         let body_text = match &value.body {
             None | Some(StaticResponseBodyConfig::Empty) => "".to_string(),
@@ -202,9 +299,18 @@ impl StaticResponse {
                 data.to_string()
             }
             Some(StaticResponseBodyConfig::Raw(data)) => data.to_string(),
+            Some(StaticResponseBodyConfig::Binary(data)) => {
+                binary_body = Some(Bytes::from(STANDARD.decode(data)?));
+                "".to_string()
+            }
+            Some(StaticResponseBodyConfig::Sse(sse)) => {
+                headers.insert(&CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+                headers.insert(&CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+                event_stream = Some(Arc::new(EventStream::try_from(&value.id, sse)?));
+                "".to_string()
+            }
         };
 
-        let mut handlebars = crate::HANDLEBARS.write().unwrap();
         handlebars.register_template_string(&value.id, body_text)?;
 
         {
@@ -215,20 +321,35 @@ impl StaticResponse {
                 );
             }
 
+            for cookie in &value.cookies {
+                headers.append(SET_COOKIE, HeaderValue::from_bytes(cookie.as_bytes())?);
+            }
+
             add_header(&mut headers, "response-id", &value.id);
             add_header(&mut headers, "payload-id", payload_id);
         }
 
+        let mut trailers = HeaderMap::new();
+        for (name, value) in &value.trailers {
+            trailers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_bytes(value.as_bytes())?,
+            );
+        }
+
         Ok(StaticResponse {
             status: status_code,
             headers,
+            trailers,
             handlebar_template_id: value.id.clone(),
+            binary_body,
+            event_stream,
             delay: Duration::from_millis(value.delay),
         })
     }
 }
 
-fn add_header(headers: &mut HeaderMap, name: &str, value: &str) {
+pub(crate) fn add_header(headers: &mut HeaderMap, name: &str, value: &str) {
     let value = match HeaderValue::from_str(value) {
         Ok(value) => value,
         Err(_) => HeaderValue::from_str(&format!("{} invalid header", name)).unwrap(),
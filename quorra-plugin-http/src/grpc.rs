@@ -0,0 +1,553 @@
+use crate::{
+    config::internal::{add_header, RequestMatcher},
+    http_static::json_contains,
+    HttpPluginError,
+};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use http::{
+    header::{HeaderName, CONTENT_TYPE},
+    HeaderMap, HeaderValue, Method, Response, StatusCode, Uri, Version,
+};
+use prost_reflect::{
+    prost::Message, DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions,
+};
+use quorra_config::prelude::*;
+use quorra_plugin::{BodyStream, Trailers};
+use rand::{seq::SliceRandom, thread_rng};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::sleep};
+use tracing::{debug, instrument};
+
+#[derive(Debug, Default)]
+pub struct GrpcPluginBuilder {
+    configs: Vec<GrpcConfig>,
+}
+
+impl GrpcPluginBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_config(&mut self, config: &GrpcConfig) {
+        self.configs.push(config.clone());
+    }
+
+    /// Checks a payload the way `build` would, returning every problem found
+    /// instead of stopping at the first one.
+    pub fn validate(config: &GrpcConfig) -> Vec<(String, HttpPluginError)> {
+        let method = match GrpcMethod::load(config) {
+            Ok(method) => method,
+            Err(e) => return vec![("descriptor".to_string(), e)],
+        };
+
+        let mut errors = Vec::new();
+        for (index, matcher) in config.matches.iter().enumerate() {
+            if let Err(e) = GrpcMatcher::try_from(config, matcher) {
+                errors.push((format!("matches[{}]", index), e));
+            }
+        }
+        for (index, response) in config.responses.iter().enumerate() {
+            if let Err(e) = GrpcResponse::try_from(config, &method, response) {
+                errors.push((format!("responses[{}]", index), e));
+            }
+        }
+        if never_picked(&config.responses) {
+            errors.push((
+                "responses".to_string(),
+                HttpPluginError::NoResponsesProvided,
+            ));
+        }
+        errors
+    }
+
+    pub fn build(self) -> Result<GrpcPlugin, HttpPluginError> {
+        debug!("{} gRPC payloads loaded", self.configs.len());
+        let mut payloads = Vec::new();
+        for config in &self.configs {
+            payloads.push(GrpcPayload::try_from(config)?);
+        }
+        Ok(GrpcPlugin { payloads })
+    }
+}
+
+/// Answers gRPC calls with messages encoded from JSON.
+#[derive(Debug, Clone)]
+pub struct GrpcPlugin {
+    payloads: Vec<GrpcPayload>,
+}
+
+#[derive(Debug, Clone)]
+struct GrpcPayload {
+    id: String,
+    input: MessageDescriptor,
+    matchers: Vec<GrpcMatcher>,
+    pointer: Arc<AtomicUsize>,
+    responses: Vec<Arc<GrpcResponse>>,
+}
+
+/// The method being mocked, found in the descriptor.
+struct GrpcMethod {
+    name: String,
+    input: MessageDescriptor,
+    output: MessageDescriptor,
+    server_streaming: bool,
+}
+
+#[derive(Debug, Clone)]
+struct GrpcMatcher {
+    /// Matches the path, host and metadata of the call.
+    request: RequestMatcher,
+    message: Option<Value>,
+}
+
+#[derive(Debug)]
+struct GrpcResponse {
+    /// Already framed, each with the delay before it's sent.
+    messages: Vec<(Bytes, Duration)>,
+    metadata: HeaderMap,
+    /// The status, its message and the trailers.
+    status: HeaderMap,
+    delay: Duration,
+}
+
+impl GrpcMethod {
+    fn load(config: &GrpcConfig) -> Result<Self, HttpPluginError> {
+        let bytes = std::fs::read(&config.descriptor).map_err(|source| {
+            HttpPluginError::DescriptorFile {
+                path: config.descriptor.clone(),
+                source,
+            }
+        })?;
+        let pool = DescriptorPool::decode(bytes.as_slice())?;
+        let service = pool
+            .get_service_by_name(&config.service)
+            .ok_or_else(|| HttpPluginError::UnknownService(config.service.clone()))?;
+        let method = service
+            .methods()
+            .find(|method| method.name() == config.method)
+            .ok_or_else(|| HttpPluginError::UnknownMethod(config.method.clone()))?;
+        if method.is_client_streaming() {
+            return Err(HttpPluginError::UnsupportedStreaming(
+                method.full_name().to_string(),
+            ));
+        }
+
+        Ok(Self {
+            name: method.full_name().to_string(),
+            input: method.input(),
+            output: method.output(),
+            server_streaming: method.is_server_streaming(),
+        })
+    }
+}
+
+impl GrpcPayload {
+    fn try_from(config: &GrpcConfig) -> Result<Self, HttpPluginError> {
+        let method = GrpcMethod::load(config)?;
+
+        let mut matchers = Vec::new();
+        for matcher in &config.matches {
+            matchers.push(GrpcMatcher::try_from(config, matcher)?);
+        }
+        if matchers.is_empty() {
+            matchers.push(GrpcMatcher::try_from(config, &Default::default())?);
+        }
+
+        let mut responses = Vec::new();
+        for response_config in &config.responses {
+            let response = Arc::new(GrpcResponse::try_from(config, &method, response_config)?);
+            for _ in 0..response_config.weight {
+                responses.push(response.clone());
+            }
+        }
+        responses.shuffle(&mut thread_rng());
+        if responses.is_empty() {
+            return Err(HttpPluginError::NoResponsesProvided);
+        }
+
+        Ok(Self {
+            id: config.id.clone(),
+            input: method.input,
+            matchers,
+            pointer: Arc::new(AtomicUsize::new(0)),
+            responses,
+        })
+    }
+
+    fn get_response(&self) -> &GrpcResponse {
+        let value = match self
+            .pointer
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+                Some((value + 1) % self.responses.len())
+            }) {
+            Ok(value) => value,
+            Err(value) => value,
+        };
+        &self.responses[value]
+    }
+
+    /// The request message is only decoded when a matcher looks at it.
+    fn matches(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        version: Version,
+        headers: &HeaderMap,
+        body: &Option<&Bytes>,
+    ) -> bool {
+        let mut message = None;
+        self.matchers.iter().any(|matcher| {
+            if !matcher
+                .request
+                .request_matches(method, uri, version, headers, &None)
+            {
+                return false;
+            }
+            let expected = match &matcher.message {
+                None => return true,
+                Some(expected) => expected,
+            };
+            let actual = message.get_or_insert_with(|| decode_request(&self.input, body));
+            match actual {
+                Some(actual) => json_contains(expected, actual),
+                None => false,
+            }
+        })
+    }
+}
+
+impl GrpcMatcher {
+    fn try_from(config: &GrpcConfig, matcher: &GrpcMatchesConfig) -> Result<Self, HttpPluginError> {
+        let path = format!(
+            "/{}/{}",
+            regex::escape(&config.service),
+            regex::escape(&config.method)
+        );
+        let request = RequestMatcher::from_matches_config(&StaticMatchesConfig {
+            path,
+            host: matcher.host.clone(),
+            protocol: None,
+            query: Default::default(),
+            headers: matcher.metadata.clone(),
+            methods: vec!["POST".to_string()],
+            graphql: None,
+            body: None,
+        })?;
+        Ok(Self {
+            request,
+            message: matcher.request.clone(),
+        })
+    }
+}
+
+impl GrpcResponse {
+    fn try_from(
+        config: &GrpcConfig,
+        method: &GrpcMethod,
+        response: &GrpcResponseConfig,
+    ) -> Result<Self, HttpPluginError> {
+        if !method.server_streaming && response.messages.len() > 1 {
+            return Err(HttpPluginError::TooManyMessages(method.name.clone()));
+        }
+
+        let mut messages = Vec::new();
+        for message in &response.messages {
+            let encoded = DynamicMessage::deserialize(method.output.clone(), &message.json)?;
+            messages.push((
+                frame(&encoded.encode_to_vec()),
+                Duration::from_millis(message.delay),
+            ));
+        }
+
+        let mut metadata = HeaderMap::new();
+        metadata.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+        insert_all(&mut metadata, &response.metadata)?;
+        add_header(&mut metadata, "response-id", &response.id);
+        add_header(&mut metadata, "payload-id", &config.id);
+
+        let mut status = HeaderMap::new();
+        status.insert("grpc-status", HeaderValue::from(response.status));
+        if !response.message.is_empty() {
+            status.insert(
+                "grpc-message",
+                HeaderValue::from_str(&percent_encode(&response.message))?,
+            );
+        }
+        insert_all(&mut status, &response.trailers)?;
+
+        Ok(Self {
+            messages,
+            metadata,
+            status,
+            delay: Duration::from_millis(response.delay),
+        })
+    }
+
+    #[instrument(skip_all, fields(payload.id = payload_id))]
+    async fn make_response(&self, payload_id: &str) -> Response<Bytes> {
+        debug!("Starting a wait of {:?}", self.delay);
+        sleep(self.delay).await;
+
+        let mut response = Response::new(Bytes::new());
+        *response.status_mut() = StatusCode::OK;
+        *response.headers_mut() = self.metadata.clone();
+
+        // Without messages the status goes with the headers, a trailers-only response.
+        if self.messages.is_empty() {
+            response.headers_mut().extend(self.status.clone());
+            return response;
+        }
+
+        let (sender, receiver) = mpsc::channel(1);
+        let messages = self.messages.clone();
+        tokio::spawn(async move {
+            for (message, delay) in messages {
+                sleep(delay).await;
+                if sender.send(message).await.is_err() {
+                    debug!("Client went away, stopping the response stream");
+                    return;
+                }
+            }
+        });
+        response.extensions_mut().insert(BodyStream(receiver));
+        response
+            .extensions_mut()
+            .insert(Trailers(self.status.clone()));
+        response
+    }
+}
+
+/// Reads the single request message, as JSON with the field names of the `.proto` file.
+fn decode_request(input: &MessageDescriptor, body: &Option<&Bytes>) -> Option<Value> {
+    let body = body.as_ref()?;
+    // A compression flag, then the length of the message.
+    if body.len() < 5 || body[0] != 0 {
+        debug!("Request isn't a single uncompressed gRPC message");
+        return None;
+    }
+    let length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let message = DynamicMessage::decode(input.clone(), body.get(5..5 + length)?).ok()?;
+
+    let options = SerializeOptions::new()
+        .use_proto_field_name(true)
+        .skip_default_fields(false)
+        .stringify_64_bit_integers(false);
+    message
+        .serialize_with_options(serde_json::value::Serializer, &options)
+        .ok()
+}
+
+/// Adds the length prefix of a gRPC message.
+fn frame(message: &[u8]) -> Bytes {
+    let mut framed = BytesMut::with_capacity(message.len() + 5);
+    framed.put_u8(0);
+    framed.put_u32(message.len() as u32);
+    framed.put_slice(message);
+    framed.freeze()
+}
+
+fn insert_all(
+    headers: &mut HeaderMap,
+    values: &BTreeMap<String, String>,
+) -> Result<(), HttpPluginError> {
+    for (name, value) in values {
+        headers.insert(HeaderName::from_str(name)?, HeaderValue::from_str(value)?);
+    }
+    Ok(())
+}
+
+/// `grpc-message` is percent-encoded, so it can hold any text.
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[async_trait]
+impl quorra_plugin::HttpPlugin for GrpcPlugin {
+    #[instrument(skip_all)]
+    async fn respond_to_request(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        version: Version,
+        headers: &HeaderMap,
+        body: &Option<&Bytes>,
+    ) -> Option<Response<Bytes>> {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        // Like `application/grpc+proto`, but not gRPC-Web, which is framed differently.
+        let is_grpc = matches!(content_type, Some(value) if value == "application/grpc"
+            || value.starts_with("application/grpc+")
+            || value.starts_with("application/grpc;"));
+        if !is_grpc {
+            return None;
+        }
+
+        for payload in &self.payloads {
+            if payload.matches(method, uri, version, headers, body) {
+                return Some(payload.get_response().make_response(&payload.id).await);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+fn test_descriptor(dir: &std::path::Path) -> std::path::PathBuf {
+    use prost_reflect::prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+        MethodDescriptorProto, ServiceDescriptorProto,
+    };
+
+    let field = |name: &str, number: i32, kind: Type| FieldDescriptorProto {
+        name: Some(name.to_string()),
+        number: Some(number),
+        label: Some(Label::Optional as i32),
+        r#type: Some(kind as i32),
+        json_name: Some(name.to_string()),
+        ..Default::default()
+    };
+    let message = |name: &str, fields: Vec<FieldDescriptorProto>| DescriptorProto {
+        name: Some(name.to_string()),
+        field: fields,
+        ..Default::default()
+    };
+    let method = |name: &str, streaming: bool| MethodDescriptorProto {
+        name: Some(name.to_string()),
+        input_type: Some(".acme.GetUserRequest".to_string()),
+        output_type: Some(".acme.User".to_string()),
+        server_streaming: Some(streaming),
+        ..Default::default()
+    };
+    let file = FileDescriptorProto {
+        name: Some("users.proto".to_string()),
+        package: Some("acme".to_string()),
+        syntax: Some("proto3".to_string()),
+        message_type: vec![
+            message("GetUserRequest", vec![field("id", 1, Type::Int64)]),
+            message(
+                "User",
+                vec![field("id", 1, Type::Int64), field("name", 2, Type::String)],
+            ),
+        ],
+        service: vec![ServiceDescriptorProto {
+            name: Some("Users".to_string()),
+            method: vec![method("GetUser", false), method("WatchUser", true)],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let path = dir.join("users.pb");
+    let set = FileDescriptorSet { file: vec![file] };
+    std::fs::write(&path, set.encode_to_vec()).unwrap();
+    path
+}
+
+/// Parses a config for the `acme.Users` service of [`test_descriptor`]. The
+/// descriptor lives in the returned directory, removed once dropped.
+#[cfg(test)]
+fn test_config(config: &str) -> (GrpcConfig, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let descriptor = test_descriptor(dir.path());
+    let config = format!("descriptor: {}\n{}", descriptor.display(), config);
+    (serde_yaml::from_str(&config).unwrap(), dir)
+}
+
+#[tokio::test]
+async fn test_grpc_unary_call() {
+    use quorra_plugin::HttpPlugin;
+
+    let (config, _dir) = test_config(
+        r#"
+id: users
+service: acme.Users
+method: GetUser
+matches:
+  - request:
+      id: 42
+responses:
+  - messages:
+      - json: { "id": 42, "name": "Ada" }
+    trailers:
+      x-served-by: quorra
+"#,
+    );
+
+    assert!(GrpcPluginBuilder::validate(&config).is_empty());
+    let mut builder = GrpcPluginBuilder::new();
+    builder.load_config(&config);
+    let plugin = builder.build().unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, "application/grpc".parse().unwrap());
+    let uri = Uri::from_static("/acme.Users/GetUser");
+    // `id: 42` is field 1 as a varint.
+    let request = frame(&[0x08, 42]);
+    let response = plugin
+        .respond_to_request(
+            &Method::POST,
+            &uri,
+            Version::HTTP_2,
+            &headers,
+            &Some(&request),
+        )
+        .await
+        .unwrap();
+    assert_eq!("application/grpc", response.headers()[CONTENT_TYPE]);
+    let Trailers(trailers) = response.extensions().get::<Trailers>().unwrap();
+    assert_eq!("0", trailers["grpc-status"]);
+    assert_eq!("quorra", trailers["x-served-by"]);
+
+    let other = frame(&[0x08, 7]);
+    assert!(plugin
+        .respond_to_request(
+            &Method::POST,
+            &uri,
+            Version::HTTP_2,
+            &headers,
+            &Some(&other)
+        )
+        .await
+        .is_none());
+}
+
+#[test]
+fn test_validate_zero_weights() {
+    let (config, _dir) = test_config(
+        r#"
+service: acme.Users
+method: GetUser
+responses:
+  - weight: 0
+    messages:
+      - json: { "id": 42 }
+"#,
+    );
+
+    let errors = GrpcPluginBuilder::validate(&config);
+    assert_eq!(1, errors.len());
+    assert_eq!("responses", errors[0].0);
+    let mut builder = GrpcPluginBuilder::new();
+    builder.load_config(&config);
+    assert!(builder.build().is_err());
+}
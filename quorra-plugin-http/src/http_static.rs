@@ -1,19 +1,23 @@
-use std::{collections::BTreeMap, sync::atomic::Ordering};
+use std::{collections::BTreeMap, str::FromStr, sync::atomic::Ordering};
 use tracing::{debug, instrument};
 
 use crate::config::internal::*;
 use async_trait::async_trait;
 use bytes::Bytes;
+use handlebars::Handlebars;
 use serde_json::Value;
 use tokio::time::sleep;
 
-use http::{HeaderMap, Method, Response, Uri};
+use http::{header::HOST, uri::Authority, HeaderMap, Method, Response, Uri, Version};
+use quorra_plugin::{BodyStream, Trailers};
 
 impl StaticResponse {
     #[instrument(skip_all, fields(payload.id = payload_id))]
     async fn make_response(
         &self,
+        handlebars: &Handlebars<'static>,
         payload_id: &str,
+        version: Version,
         request_body: &Option<&Bytes>,
     ) -> Response<Bytes> {
         let body_string = match request_body {
@@ -27,17 +31,23 @@ impl StaticResponse {
         let values = BTreeMap::from([
             ("quorra_payload_id", Value::from(payload_id)),
             ("request_body", body_string),
+            ("request_protocol", Value::from(protocol_name(version))),
         ]);
 
-        let handlebars = crate::HANDLEBARS.read().unwrap();
-        let body = match handlebars.render(&self.handlebar_template_id, &values) {
-            Ok(body) => body,
-            Err(e) => format!(
-                "Quorra encoundered an error rendering the response. Error {}",
-                e
-            ),
+        let body = match (&self.binary_body, &self.event_stream) {
+            (Some(body), _) => body.clone(),
+            (None, Some(_)) => Bytes::new(),
+            (None, None) => {
+                let body = match handlebars.render(&self.handlebar_template_id, &values) {
+                    Ok(body) => body,
+                    Err(e) => format!(
+                        "Quorra encoundered an error rendering the response. Error {}",
+                        e
+                    ),
+                };
+                Bytes::from(body)
+            }
         };
-        let body = Bytes::from(body);
 
         let mut response = Response::builder().status(self.status);
 
@@ -47,6 +57,14 @@ impl StaticResponse {
             }
         }
 
+        if !self.trailers.is_empty() {
+            response = response.extension(Trailers(self.trailers.clone()));
+        }
+
+        if let Some(event_stream) = &self.event_stream {
+            response = response.extension(BodyStream(event_stream.clone().start(values)));
+        }
+
         response.body(body).unwrap()
     }
 }
@@ -73,20 +91,41 @@ impl PayloadBackendConfig {
         &self,
         method: &Method,
         uri: &Uri,
+        version: Version,
         headers: &HeaderMap,
         body: &Option<&Bytes>,
     ) -> bool {
         self.matchers
             .iter()
-            .any(|x| x.request_matches(method, uri, headers, body))
+            .any(|x| x.request_matches(method, uri, version, headers, body))
+    }
+}
+
+/// The name of the protocol, for matchers and templates.
+fn protocol_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_11 => "HTTP/1.1",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "unknown",
     }
 }
 
 impl RequestMatcher {
-    fn request_matches(
+    fn matches_protocol(&self, version: Version) -> bool {
+        match &self.protocol {
+            None => true,
+            Some(matcher) => matcher.is_match(protocol_name(version)),
+        }
+    }
+
+    pub(crate) fn request_matches(
         &self,
         method: &Method,
         uri: &Uri,
+        version: Version,
         headers: &HeaderMap,
         body: &Option<&Bytes>,
     ) -> bool {
@@ -95,11 +134,21 @@ impl RequestMatcher {
         }
         debug!("Matched method");
 
+        if !self.matches_protocol(version) {
+            return false;
+        }
+        debug!("Matched protocol");
+
         if !self.path.is_match(uri.path()) {
             return false;
         }
         debug!("Matched uri");
 
+        if !self.matches_host(uri, headers) {
+            return false;
+        }
+        debug!("Matched host");
+
         if !self.matches_query(uri) {
             return false;
         }
@@ -114,11 +163,67 @@ impl RequestMatcher {
             return false;
         }
 
+        if !self.matches_body(body) {
+            return false;
+        }
+
         debug!("Matched Body");
 
         true
     }
 
+    /// The URI authority is set for HTTP/2 and absolute URIs, otherwise the
+    /// `Host` header is used. The port is ignored.
+    fn matches_host(&self, uri: &Uri, headers: &HeaderMap) -> bool {
+        let matcher = match &self.host {
+            None => return true,
+            Some(matcher) => matcher,
+        };
+
+        let authority = match uri.authority() {
+            Some(authority) => Some(authority.clone()),
+            None => headers
+                .get(HOST)
+                .and_then(|host| host.to_str().ok())
+                .and_then(|host| Authority::from_str(host).ok()),
+        };
+        match authority {
+            Some(authority) => matcher.is_match(authority.host()),
+            None => false,
+        }
+    }
+
+    fn matches_body(&self, body: &Option<&Bytes>) -> bool {
+        let matcher = match &self.body {
+            None => return true,
+            Some(matcher) => matcher,
+        };
+
+        let body_text = match body.map(|body| String::from_utf8(body.to_vec())) {
+            Some(Ok(body)) => body,
+            None => String::new(),
+            Some(Err(_e)) => {
+                debug!("Unable to extract string from body of request");
+                return false;
+            }
+        };
+
+        if let Some(regex) = &matcher.regex {
+            if !regex.is_match(&body_text) {
+                return false;
+            }
+        }
+
+        if let Some(expected) = &matcher.json {
+            match serde_json::from_str::<Value>(&body_text) {
+                Ok(actual) => return json_contains(expected, &actual),
+                Err(_) => return false,
+            }
+        }
+
+        true
+    }
+
     fn match_graphql_operation(&self, body: &Option<&Bytes>) -> bool {
         let matcher = match &self.graphql_operations {
             None => return true,
@@ -191,6 +296,21 @@ impl RequestMatcher {
     }
 }
 
+/// Checks that every field in `expected` is present in `actual` with the same value.
+pub(crate) fn json_contains(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            expected
+                .iter()
+                .all(|(key, expected)| match actual.get(key) {
+                    Some(actual) => json_contains(expected, actual),
+                    None => false,
+                })
+        }
+        (expected, actual) => expected == actual,
+    }
+}
+
 #[test]
 fn test_request_matcher_empty() {
     let matcher = RequestMatcher::new(
@@ -202,20 +322,75 @@ fn test_request_matcher_empty() {
     )
     .unwrap();
     let empty_uri = "/".parse::<Uri>().unwrap();
-    assert!(matcher.request_matches(&Method::OPTIONS, &empty_uri, &Default::default(), &None));
-    assert!(matcher.request_matches(&Method::GET, &empty_uri, &Default::default(), &None));
-    assert!(matcher.request_matches(&Method::PUT, &empty_uri, &Default::default(), &None));
-    assert!(matcher.request_matches(&Method::DELETE, &empty_uri, &Default::default(), &None));
-    assert!(matcher.request_matches(&Method::HEAD, &empty_uri, &Default::default(), &None));
-    assert!(matcher.request_matches(&Method::TRACE, &empty_uri, &Default::default(), &None));
+    assert!(matcher.request_matches(
+        &Method::OPTIONS,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
+    assert!(matcher.request_matches(
+        &Method::GET,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
+    assert!(matcher.request_matches(
+        &Method::PUT,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
+    assert!(matcher.request_matches(
+        &Method::DELETE,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
+    assert!(matcher.request_matches(
+        &Method::HEAD,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
+    assert!(matcher.request_matches(
+        &Method::TRACE,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
 
-    assert!(matcher.request_matches(&Method::POST, &empty_uri, &Default::default(), &None));
-    assert!(matcher.request_matches(&Method::CONNECT, &empty_uri, &Default::default(), &None));
-    assert!(matcher.request_matches(&Method::PATCH, &empty_uri, &Default::default(), &None));
+    assert!(matcher.request_matches(
+        &Method::POST,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
+    assert!(matcher.request_matches(
+        &Method::CONNECT,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
+    assert!(matcher.request_matches(
+        &Method::PATCH,
+        &empty_uri,
+        Version::HTTP_11,
+        &Default::default(),
+        &None
+    ));
 
     assert!(matcher.request_matches(
         &Method::GET,
         &"/foo/bar".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
@@ -234,12 +409,14 @@ fn test_request_matcher_method() {
     assert!(matcher.request_matches(
         &Method::GET,
         &"/".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
     assert!(!matcher.request_matches(
         &Method::PUT,
         &"/".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
@@ -258,18 +435,21 @@ fn test_request_matcher_path() {
     assert!(matcher.request_matches(
         &Method::GET,
         &"/foo/bar".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
     assert!(!matcher.request_matches(
         &Method::GET,
         &"/foo/barasdfa".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
     assert!(!matcher.request_matches(
         &Method::GET,
         &"/foo/bar/".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
@@ -288,29 +468,69 @@ fn test_request_matcher_query() {
     assert!(!matcher.request_matches(
         &Method::GET,
         &"/foo/bar".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
     assert!(matcher.request_matches(
         &Method::GET,
         &"/test?foo=bar".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
     assert!(!matcher.request_matches(
         &Method::GET,
         &"/test?foo=bar&foo=baz".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
     assert!(!matcher.request_matches(
         &Method::GET,
         &"/test?foo=baz".parse::<Uri>().unwrap(),
+        Version::HTTP_11,
         &Default::default(),
         &None
     ));
 }
 
+#[test]
+fn test_request_matcher_body() {
+    let mut matcher = RequestMatcher::new(
+        &Default::default(),
+        &".*".to_string(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    )
+    .unwrap();
+    matcher.body = Some(
+        BodyMatcher::new(&quorra_config::prelude::BodyStaticMatchConfig {
+            regex: None,
+            json: Some(r#"{"user": {"id": 1}}"#.to_string()),
+        })
+        .unwrap(),
+    );
+    let uri = "/".parse::<Uri>().unwrap();
+    let matches = |body: &'static str| {
+        matcher.request_matches(
+            &Method::POST,
+            &uri,
+            Version::HTTP_11,
+            &Default::default(),
+            &Some(&Bytes::from(body)),
+        )
+    };
+
+    assert!(matches(
+        r#"{"user": {"id": 1, "name": "bob"}, "extra": true}"#
+    ));
+    assert!(!matches(r#"{"user": {"id": 2}}"#));
+    assert!(!matches(r#"{"id": 1}"#));
+    assert!(!matches("not json"));
+}
+
 #[derive(Debug, Clone)]
 pub struct HttpStaticPlugin {
     pub config: PluginBackendConfig,
@@ -323,16 +543,17 @@ impl quorra_plugin::HttpPlugin for HttpStaticPlugin {
         &self,
         method: &Method,
         uri: &Uri,
+        version: Version,
         headers: &HeaderMap,
         body: &Option<&Bytes>,
     ) -> Option<Response<Bytes>> {
         for payload in &self.config.payloads {
-            if payload.matches(method, uri, headers, body) {
+            if payload.matches(method, uri, version, headers, body) {
                 return Some(
                     payload
                         .responses
                         .get_response()
-                        .make_response(&payload.id, body)
+                        .make_response(&self.config.handlebars, &payload.id, version, body)
                         .await,
                 );
             }
@@ -341,3 +562,62 @@ impl quorra_plugin::HttpPlugin for HttpStaticPlugin {
         None
     }
 }
+
+#[test]
+fn test_request_matcher_host() {
+    let mut matcher = RequestMatcher::new(
+        &Default::default(),
+        &".*".to_string(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    )
+    .unwrap();
+    matcher.host = Some(
+        regex::RegexBuilder::new(r"^(?:api\.example\.test)$")
+            .case_insensitive(true)
+            .build()
+            .unwrap(),
+    );
+    let matches = |uri: &str, host: Option<&'static str>| {
+        let mut headers = HeaderMap::new();
+        if let Some(host) = host {
+            headers.insert(HOST, http::HeaderValue::from_static(host));
+        }
+        matcher.request_matches(
+            &Method::GET,
+            &uri.parse::<Uri>().unwrap(),
+            Version::HTTP_11,
+            &headers,
+            &None,
+        )
+    };
+
+    assert!(matches("/", Some("api.example.test")));
+    assert!(matches("/", Some("API.example.test:3000")));
+    assert!(matches("http://api.example.test:8080/", None));
+    assert!(!matches("/", Some("www.example.test")));
+    assert!(!matches("/", None));
+    // The authority wins over the header.
+    assert!(!matches(
+        "http://www.example.test/",
+        Some("api.example.test")
+    ));
+}
+
+#[test]
+fn test_request_matcher_protocol() {
+    let mut matcher = RequestMatcher::new(
+        &Default::default(),
+        &".*".to_string(),
+        &Default::default(),
+        &Default::default(),
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(matcher.matches_protocol(Version::HTTP_11));
+
+    matcher.protocol = Some(regex::Regex::new("^(?:HTTP/2)$").unwrap());
+    assert!(matcher.matches_protocol(Version::HTTP_2));
+    assert!(!matcher.matches_protocol(Version::HTTP_11));
+}
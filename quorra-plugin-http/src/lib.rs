@@ -1,15 +1,18 @@
 use thiserror::Error;
 
 mod config;
+mod grpc;
 mod http_static;
+mod sse;
+mod websocket;
+
+pub use grpc::{GrpcPlugin, GrpcPluginBuilder};
+pub use websocket::{WebsocketPlugin, WebsocketPluginBuilder};
 
 use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext};
 use lazy_static::lazy_static;
 use quorra_config::prelude::StaticHttpConfig;
-use std::{
-    sync::atomic::{AtomicU64, Ordering},
-    sync::RwLock,
-};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tracing::debug;
 
 lazy_static! {
@@ -38,14 +41,14 @@ fn id_generator(
     Ok(())
 }
 
-lazy_static! {
-    pub(crate) static ref HANDLEBARS: RwLock<Box<Handlebars<'static>>> = {
-        let mut handlebars = Handlebars::new();
-        handlebars.register_escape_fn(handlebars::no_escape);
-        handlebars.register_helper("uuid", Box::new(uuid_generator));
-        handlebars.register_helper("id", Box::new(id_generator));
-        RwLock::new(Box::new(handlebars))
-    };
+/// A registry with the helpers every template can use. Each plugin keeps its
+/// own, so templates are only replaced along with the plugin using them.
+pub(crate) fn new_registry() -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.register_helper("uuid", Box::new(uuid_generator));
+    handlebars.register_helper("id", Box::new(id_generator));
+    handlebars
 }
 
 #[derive(Error, Debug)]
@@ -71,11 +74,37 @@ pub enum HttpPluginError {
     #[error("No respone configured for match")]
     NoResponsesProvided,
     #[error(transparent)]
-    TemplateError(#[from] handlebars::TemplateError),
+    TemplateError(Box<handlebars::TemplateError>),
+    #[error(transparent)]
+    Base64Error(#[from] base64::DecodeError),
+    #[error("invalid JSONPath: {0}")]
+    InvalidJsonPath(String),
+    #[error("unable to read descriptor {path}: {source}")]
+    DescriptorFile {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    DescriptorError(#[from] prost_reflect::DescriptorError),
+    #[error("no service `{0}` in the descriptor")]
+    UnknownService(String),
+    #[error("no method `{0}` in the service")]
+    UnknownMethod(String),
+    #[error("`{0}` streams requests, only unary and server-streaming methods are supported")]
+    UnsupportedStreaming(String),
+    #[error("`{0}` is unary and can respond with one message at most")]
+    TooManyMessages(String),
     #[error(transparent)]
     AnyhowError(#[from] anyhow::Error),
 }
 
+// Boxed, the template errors are larger than every other variant together.
+impl From<handlebars::TemplateError> for HttpPluginError {
+    fn from(e: handlebars::TemplateError) -> Self {
+        Self::TemplateError(Box::new(e))
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct HttpStaticPluginBuilder {
     configs: Vec<StaticHttpConfig<String>>,
@@ -90,6 +119,12 @@ impl HttpStaticPluginBuilder {
         self.configs.push(config.clone());
     }
 
+    /// Checks a payload the way `build` would, returning every problem found
+    /// instead of stopping at the first one.
+    pub fn validate(config: &StaticHttpConfig<String>) -> Vec<(String, HttpPluginError)> {
+        crate::config::internal::PayloadBackendConfig::validate(config)
+    }
+
     pub fn build(self) -> Result<http_static::HttpStaticPlugin, HttpPluginError> {
         debug!("{} responses loaded", self.configs.len());
         let plugin_config = crate::config::internal::PluginBackendConfig::try_from(&self.configs)?;
@@ -0,0 +1,161 @@
+use crate::HttpPluginError;
+use bytes::Bytes;
+use handlebars::Handlebars;
+use quorra_config::prelude::*;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+use tracing::debug;
+
+/// The events of an `sse` body, each field registered as a template.
+#[derive(Debug)]
+pub struct EventStream {
+    handlebars: Handlebars<'static>,
+    events: Vec<Event>,
+    times: u32,
+    retry: Option<u64>,
+    keep_open: bool,
+    close_after: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Event {
+    id_template: Option<String>,
+    event_template: Option<String>,
+    data_template: String,
+    delay: Duration,
+}
+
+impl EventStream {
+    /// Templates are named after the response, like its body.
+    pub fn try_from(response_id: &str, config: &SseBodyConfig) -> Result<Self, HttpPluginError> {
+        let mut handlebars = crate::new_registry();
+        let mut register = |name: String, template: &str| -> Result<String, HttpPluginError> {
+            handlebars.register_template_string(&name, template)?;
+            Ok(name)
+        };
+
+        let mut events = Vec::new();
+        for (index, event) in config.events.iter().enumerate() {
+            let prefix = format!("{}.events.{}", response_id, index);
+            events.push(Event {
+                id_template: match &event.id {
+                    Some(id) => Some(register(format!("{}.id", prefix), id)?),
+                    None => None,
+                },
+                event_template: match &event.event {
+                    Some(name) => Some(register(format!("{}.event", prefix), name)?),
+                    None => None,
+                },
+                data_template: register(format!("{}.data", prefix), &event.data)?,
+                delay: Duration::from_millis(event.delay),
+            });
+        }
+
+        Ok(Self {
+            handlebars,
+            events,
+            times: config.times,
+            retry: config.retry,
+            keep_open: config.keep_open,
+            close_after: config.close_after.map(Duration::from_millis),
+        })
+    }
+
+    /// Starts sending the events, which stops early once the receiver is dropped.
+    pub fn start(self: Arc<Self>, values: BTreeMap<&'static str, Value>) -> mpsc::Receiver<Bytes> {
+        let (sender, receiver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let stream = async {
+                if self.play(&sender, &values).await && self.keep_open {
+                    sender.closed().await;
+                }
+            };
+            match self.close_after {
+                Some(close_after) => {
+                    if timeout(close_after, stream).await.is_err() {
+                        debug!("Closing the event stream after {:?}", close_after);
+                    }
+                }
+                None => stream.await,
+            }
+        });
+        receiver
+    }
+
+    /// Returns `false` when the client went away.
+    async fn play(&self, sender: &mpsc::Sender<Bytes>, values: &BTreeMap<&str, Value>) -> bool {
+        if let Some(retry) = self.retry {
+            if sender
+                .send(Bytes::from(format!("retry: {}\n\n", retry)))
+                .await
+                .is_err()
+            {
+                return false;
+            }
+        }
+
+        let mut played = 0;
+        while self.times == 0 || played < self.times {
+            for event in &self.events {
+                sleep(event.delay).await;
+                if sender
+                    .send(event.render(&self.handlebars, values))
+                    .await
+                    .is_err()
+                {
+                    debug!("Client went away, stopping the event stream");
+                    return false;
+                }
+            }
+            played += 1;
+        }
+        true
+    }
+}
+
+impl Event {
+    fn render(&self, handlebars: &Handlebars<'static>, values: &BTreeMap<&str, Value>) -> Bytes {
+        let render = |name: &str| match handlebars.render(name, values) {
+            Ok(rendered) => rendered,
+            Err(e) => format!(
+                "Quorra encoundered an error rendering the event. Error {}",
+                e
+            ),
+        };
+
+        let id = self.id_template.as_deref().map(render);
+        let event = self.event_template.as_deref().map(render);
+        let data = render(&self.data_template);
+        Bytes::from(format_sse_event(id.as_deref(), event.as_deref(), &data))
+    }
+}
+
+#[tokio::test]
+async fn test_event_stream_plays_every_time() {
+    let config: SseBodyConfig = serde_yaml::from_str(
+        r#"
+events:
+  - id: "{{ quorra_payload_id }}"
+    data: first
+  - event: done
+    data: second
+times: 2
+retry: 500
+"#,
+    )
+    .unwrap();
+    let stream = Arc::new(EventStream::try_from("test_event_stream", &config).unwrap());
+    let values = BTreeMap::from([("quorra_payload_id", Value::from("sse"))]);
+
+    let mut receiver = stream.start(values);
+    let mut body = String::new();
+    while let Some(chunk) = receiver.recv().await {
+        body.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+    let once = "id: sse\ndata: first\n\nevent: done\ndata: second\n\n";
+    assert_eq!(format!("retry: 500\n\n{}{}", once, once), body);
+}
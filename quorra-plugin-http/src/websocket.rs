@@ -0,0 +1,530 @@
+use crate::{config::internal::RequestMatcher, HttpPluginError};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use handlebars::Handlebars;
+use http::{
+    header::{
+        CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+    },
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version,
+};
+use hyper::{upgrade::Upgraded, Body};
+use jsonpath_lib::Compiled;
+use quorra_config::prelude::*;
+use regex::Regex;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{sync::mpsc, task::JoinHandle, time::sleep};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::derive_accept_key,
+        protocol::{frame::coding::CloseCode, CloseFrame, Role},
+        Message,
+    },
+    WebSocketStream,
+};
+use tracing::{debug, info, instrument};
+
+#[derive(Debug, Default)]
+pub struct WebsocketPluginBuilder {
+    configs: Vec<WebsocketConfig>,
+}
+
+impl WebsocketPluginBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_config(&mut self, config: &WebsocketConfig) {
+        self.configs.push(config.clone());
+    }
+
+    /// Checks an endpoint the way `build` would, returning every problem found
+    /// instead of stopping at the first one.
+    pub fn validate(config: &WebsocketConfig) -> Vec<(String, HttpPluginError)> {
+        let mut errors = Vec::new();
+        for (index, matcher) in config.matches.iter().enumerate() {
+            if let Err(e) = RequestMatcher::from_matches_config(matcher) {
+                errors.push((format!("matches[{}]", index), e));
+            }
+        }
+        for (index, message) in config.on_connect.iter().enumerate() {
+            if let Err(e) = ScriptedMessage::try_from(message) {
+                errors.push((format!("on-connect[{}]", index), e));
+            }
+        }
+        for (index, reply) in config.replies.iter().enumerate() {
+            if let Err(e) = ReplyRule::try_from(reply) {
+                errors.push((format!("replies[{}]", index), e));
+            }
+        }
+        for (index, push) in config.pushes.iter().enumerate() {
+            if let Err(e) = Push::try_from(push) {
+                errors.push((format!("pushes[{}]", index), e));
+            }
+        }
+        errors
+    }
+
+    pub fn build(self) -> Result<WebsocketPlugin, HttpPluginError> {
+        debug!("{} websocket endpoints loaded", self.configs.len());
+        let mut endpoints = Vec::new();
+        for config in &self.configs {
+            endpoints.push(Arc::new(WebsocketEndpoint::try_from(config)?));
+        }
+        Ok(WebsocketPlugin { endpoints })
+    }
+}
+
+/// Answers upgrade requests, then plays the conversation of the first endpoint
+/// that matches.
+#[derive(Debug, Clone)]
+pub struct WebsocketPlugin {
+    endpoints: Vec<Arc<WebsocketEndpoint>>,
+}
+
+#[derive(Debug)]
+struct WebsocketEndpoint {
+    id: String,
+    /// Renders the text frames, which are inline templates.
+    handlebars: Arc<Handlebars<'static>>,
+    matchers: Vec<RequestMatcher>,
+    on_connect: Vec<ScriptedMessage>,
+    replies: Vec<ReplyRule>,
+    pushes: Vec<Push>,
+    close: Option<Close>,
+}
+
+#[derive(Debug, Clone)]
+enum Frame {
+    /// A template, rendered when it's sent.
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct ScriptedMessage {
+    frame: Frame,
+    delay: Duration,
+}
+
+#[derive(Debug)]
+struct ReplyRule {
+    regex: Option<Regex>,
+    json_path: Option<Compiled>,
+    equals: Option<String>,
+    send: Vec<ScriptedMessage>,
+    close: Option<Close>,
+}
+
+#[derive(Debug)]
+struct Push {
+    frame: Frame,
+    after: Duration,
+    every: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+struct Close {
+    after: Duration,
+    code: u16,
+    reason: String,
+}
+
+/// What the writer of a connection sends next.
+enum Outgoing {
+    Message(Message),
+    Close(CloseFrame<'static>),
+}
+
+impl TryFrom<&WebsocketConfig> for WebsocketEndpoint {
+    type Error = HttpPluginError;
+
+    fn try_from(config: &WebsocketConfig) -> Result<Self, Self::Error> {
+        let mut matchers = Vec::new();
+        for matcher in &config.matches {
+            matchers.push(RequestMatcher::from_matches_config(matcher)?);
+        }
+        Ok(Self {
+            id: config.id.clone(),
+            handlebars: Arc::new(crate::new_registry()),
+            matchers,
+            on_connect: config
+                .on_connect
+                .iter()
+                .map(ScriptedMessage::try_from)
+                .collect::<Result<_, _>>()?,
+            replies: config
+                .replies
+                .iter()
+                .map(ReplyRule::try_from)
+                .collect::<Result<_, _>>()?,
+            pushes: config
+                .pushes
+                .iter()
+                .map(Push::try_from)
+                .collect::<Result<_, _>>()?,
+            close: config.close.as_ref().map(Close::from),
+        })
+    }
+}
+
+impl TryFrom<&WebsocketFrameConfig> for Frame {
+    type Error = HttpPluginError;
+
+    fn try_from(config: &WebsocketFrameConfig) -> Result<Self, Self::Error> {
+        Ok(match config {
+            WebsocketFrameConfig::Text(text) => {
+                // Fails on broken templates now, rather than on the first send.
+                handlebars::Template::compile(text)?;
+                Frame::Text(text.clone())
+            }
+            WebsocketFrameConfig::Binary(data) => Frame::Binary(STANDARD.decode(data)?),
+        })
+    }
+}
+
+impl TryFrom<&WebsocketMessageConfig> for ScriptedMessage {
+    type Error = HttpPluginError;
+
+    fn try_from(config: &WebsocketMessageConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            frame: Frame::try_from(&config.frame)?,
+            delay: Duration::from_millis(config.delay),
+        })
+    }
+}
+
+impl TryFrom<&WebsocketReplyConfig> for ReplyRule {
+    type Error = HttpPluginError;
+
+    fn try_from(config: &WebsocketReplyConfig) -> Result<Self, Self::Error> {
+        let json_path = match &config.json_path {
+            None => None,
+            Some(path) => Some(Compiled::compile(path).map_err(HttpPluginError::InvalidJsonPath)?),
+        };
+        Ok(Self {
+            regex: config.regex.as_deref().map(Regex::new).transpose()?,
+            json_path,
+            equals: config.equals.clone(),
+            send: config
+                .send
+                .iter()
+                .map(ScriptedMessage::try_from)
+                .collect::<Result<_, _>>()?,
+            close: config.close.as_ref().map(Close::from),
+        })
+    }
+}
+
+impl TryFrom<&WebsocketPushConfig> for Push {
+    type Error = HttpPluginError;
+
+    fn try_from(config: &WebsocketPushConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            frame: Frame::try_from(&config.frame)?,
+            after: Duration::from_millis(config.after),
+            every: config.every.filter(|x| *x > 0).map(Duration::from_millis),
+        })
+    }
+}
+
+impl From<&WebsocketCloseConfig> for Close {
+    fn from(config: &WebsocketCloseConfig) -> Self {
+        Self {
+            after: Duration::from_millis(config.after),
+            code: config.code,
+            reason: config.reason.clone(),
+        }
+    }
+}
+
+impl ReplyRule {
+    /// Binary messages are only answered by rules without matchers.
+    fn matches(&self, message: &Message) -> bool {
+        let text = match message {
+            Message::Text(text) => text,
+            _ => return self.regex.is_none() && self.json_path.is_none(),
+        };
+
+        if let Some(regex) = &self.regex {
+            if !regex.is_match(text) {
+                return false;
+            }
+        }
+
+        if let Some(json_path) = &self.json_path {
+            let json: Value = match serde_json::from_str(text) {
+                Ok(json) => json,
+                Err(_) => return false,
+            };
+            let found = json_path.select(&json).unwrap_or_default();
+            let matched = match &self.equals {
+                None => !found.is_empty(),
+                Some(expected) => found.iter().any(|value| match value {
+                    Value::String(value) => value == expected,
+                    other => &other.to_string() == expected,
+                }),
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl Frame {
+    fn render(
+        &self,
+        handlebars: &Handlebars<'static>,
+        payload_id: &str,
+        message: Option<&str>,
+    ) -> Message {
+        match self {
+            Frame::Binary(data) => Message::Binary(data.clone()),
+            Frame::Text(template) => {
+                let values = BTreeMap::from([
+                    ("quorra_payload_id", Value::from(payload_id)),
+                    ("message", message.map(Value::from).unwrap_or(Value::Null)),
+                ]);
+                let text = match handlebars.render_template(template, &values) {
+                    Ok(text) => text,
+                    Err(e) => format!(
+                        "Quorra encoundered an error rendering the message. Error {}",
+                        e
+                    ),
+                };
+                Message::Text(text)
+            }
+        }
+    }
+}
+
+impl Close {
+    fn frame(&self) -> CloseFrame<'static> {
+        CloseFrame {
+            code: CloseCode::from(self.code),
+            reason: self.reason.clone().into(),
+        }
+    }
+}
+
+impl WebsocketEndpoint {
+    fn matches(&self, request: &Request<Body>) -> bool {
+        self.matchers.iter().any(|matcher| {
+            matcher.request_matches(
+                request.method(),
+                request.uri(),
+                request.version(),
+                request.headers(),
+                &None,
+            )
+        })
+    }
+
+    #[instrument(skip_all, fields(payload.id = self.id))]
+    async fn run(self: Arc<Self>, socket: WebSocketStream<Upgraded>) {
+        info!("WebSocket connected");
+        let (mut sink, mut incoming) = socket.split();
+        let (sender, mut outgoing) = mpsc::unbounded_channel();
+
+        let writer = tokio::spawn(async move {
+            while let Some(next) = outgoing.recv().await {
+                let result = match next {
+                    Outgoing::Message(message) => sink.send(message).await,
+                    Outgoing::Close(frame) => {
+                        let _ = sink.send(Message::Close(Some(frame))).await;
+                        break;
+                    }
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut timers: Vec<JoinHandle<()>> = Vec::new();
+        timers.push(self.send_all(&self.on_connect, None, None, &sender));
+        for push in &self.pushes {
+            timers.push(self.push(push, &sender));
+        }
+        if let Some(close) = &self.close {
+            timers.push(self.send_all(&[], None, Some(close.clone()), &sender));
+        }
+
+        while let Some(Ok(message)) = incoming.next().await {
+            if message.is_close() {
+                break;
+            }
+            if !(message.is_text() || message.is_binary()) {
+                continue;
+            }
+            debug!("Received a message");
+
+            if let Some(reply) = self.replies.iter().find(|reply| reply.matches(&message)) {
+                let text = message.to_text().ok();
+                timers.push(self.send_all(&reply.send, text, reply.close.clone(), &sender));
+            }
+        }
+
+        for timer in timers {
+            timer.abort();
+        }
+        drop(sender);
+        let _ = writer.await;
+        info!("WebSocket disconnected");
+    }
+
+    /// Sends the messages in order, each after its own delay, then closes when
+    /// asked to.
+    fn send_all(
+        &self,
+        messages: &[ScriptedMessage],
+        incoming: Option<&str>,
+        close: Option<Close>,
+        sender: &mpsc::UnboundedSender<Outgoing>,
+    ) -> JoinHandle<()> {
+        let rendered: Vec<(Duration, Message)> = messages
+            .iter()
+            .map(|message| {
+                let frame = message.frame.render(&self.handlebars, &self.id, incoming);
+                (message.delay, frame)
+            })
+            .collect();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            for (delay, message) in rendered {
+                sleep(delay).await;
+                if sender.send(Outgoing::Message(message)).is_err() {
+                    return;
+                }
+            }
+            if let Some(close) = close {
+                sleep(close.after).await;
+                let _ = sender.send(Outgoing::Close(close.frame()));
+            }
+        })
+    }
+
+    fn push(&self, push: &Push, sender: &mpsc::UnboundedSender<Outgoing>) -> JoinHandle<()> {
+        let (id, frame, after, every) =
+            (self.id.clone(), push.frame.clone(), push.after, push.every);
+        let handlebars = self.handlebars.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            sleep(after).await;
+            loop {
+                if sender
+                    .send(Outgoing::Message(frame.render(&handlebars, &id, None)))
+                    .is_err()
+                {
+                    return;
+                }
+                match every {
+                    Some(every) => sleep(every).await,
+                    None => return,
+                }
+            }
+        })
+    }
+}
+
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(UPGRADE)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.eq_ignore_ascii_case("websocket"))
+        .unwrap_or_default()
+        && headers.contains_key(SEC_WEBSOCKET_KEY)
+}
+
+#[async_trait]
+impl quorra_plugin::HttpPlugin for WebsocketPlugin {
+    /// Only upgrade requests are answered.
+    async fn respond_to_request(
+        &self,
+        _method: &Method,
+        _uri: &Uri,
+        _version: Version,
+        _headers: &HeaderMap,
+        _body: &Option<&Bytes>,
+    ) -> Option<Response<Bytes>> {
+        None
+    }
+
+    fn upgrade_request(&self, request: &mut Request<Body>) -> Option<Response<Body>> {
+        if !is_websocket_upgrade(request.headers()) {
+            return None;
+        }
+        let endpoint = self
+            .endpoints
+            .iter()
+            .find(|endpoint| endpoint.matches(request))?
+            .clone();
+
+        let accept = derive_accept_key(request.headers()[SEC_WEBSOCKET_KEY].as_bytes());
+        // Clients asking for subprotocols expect one of them back.
+        let protocol = request
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.split(',').next())
+            .map(|x| x.trim().to_string());
+
+        let on_upgrade = hyper::upgrade::on(request);
+        let payload_id = endpoint.id.clone();
+        tokio::spawn(async move {
+            match on_upgrade.await {
+                Ok(upgraded) => {
+                    let socket =
+                        WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                    endpoint.run(socket).await;
+                }
+                Err(e) => info!("Unable to upgrade the connection: {}", e),
+            }
+        });
+
+        let mut response = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(UPGRADE, HeaderValue::from_static("websocket"))
+            .header(CONNECTION, HeaderValue::from_static("Upgrade"))
+            .header(SEC_WEBSOCKET_ACCEPT, accept)
+            .header("x-quorra-payload-id", payload_id);
+        if let Some(protocol) = protocol {
+            response = response.header(SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
+        response.body(Body::empty()).ok()
+    }
+}
+
+#[test]
+fn test_reply_rule_matches() {
+    let rule = |regex: Option<&str>, json_path: Option<&str>, equals: Option<&str>| {
+        ReplyRule::try_from(&WebsocketReplyConfig {
+            regex: regex.map(|x| x.to_string()),
+            json_path: json_path.map(|x| x.to_string()),
+            equals: equals.map(|x| x.to_string()),
+            send: vec![],
+            close: None,
+        })
+        .unwrap()
+    };
+    let text = |text: &str| Message::Text(text.to_string());
+
+    assert!(rule(None, None, None).matches(&Message::Binary(vec![1])));
+    assert!(rule(Some("^ping$"), None, None).matches(&text("ping")));
+    assert!(!rule(Some("^ping$"), None, None).matches(&text("pong")));
+    assert!(!rule(Some("ping"), None, None).matches(&Message::Binary(vec![1])));
+
+    let subscribe = rule(None, Some("$.type"), Some("subscribe"));
+    assert!(subscribe.matches(&text(r#"{"type": "subscribe"}"#)));
+    assert!(!subscribe.matches(&text(r#"{"type": "unsubscribe"}"#)));
+    assert!(!subscribe.matches(&text("not json")));
+    assert!(rule(None, Some("$.id"), Some("7")).matches(&text(r#"{"id": 7}"#)));
+    assert!(rule(None, Some("$.channel"), None).matches(&text(r#"{"channel": "a"}"#)));
+    assert!(!rule(None, Some("$.channel"), None).matches(&text(r#"{"id": 1}"#)));
+}
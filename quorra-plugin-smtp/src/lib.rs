@@ -0,0 +1,55 @@
+use thiserror::Error;
+
+mod mailbox;
+mod payload;
+mod session;
+
+pub use mailbox::{CapturedMessage, CapturedPart, Mailbox};
+pub use payload::SmtpPlugin;
+pub use session::serve_connection;
+
+use payload::SmtpPayload;
+use quorra_config::prelude::SmtpConfig;
+use tracing::debug;
+
+#[derive(Error, Debug)]
+pub enum SmtpPluginError {
+    #[error(transparent)]
+    RegexError(#[from] regex::Error),
+    #[error("No response configured for match")]
+    NoResponsesProvided,
+    #[error("`{0}` can't be replied, expected a 2xx, 4xx or 5xx code")]
+    InvalidCode(u16),
+    #[error("`to` can't be matched on `mail`, the recipients come after it")]
+    RecipientOnMail,
+}
+
+#[derive(Debug, Default)]
+pub struct SmtpPluginBuilder {
+    configs: Vec<SmtpConfig>,
+}
+
+impl SmtpPluginBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_config(&mut self, config: &SmtpConfig) {
+        self.configs.push(config.clone());
+    }
+
+    /// Checks a payload the way `build` would, returning every problem found
+    /// instead of stopping at the first one.
+    pub fn validate(config: &SmtpConfig) -> Vec<(String, SmtpPluginError)> {
+        payload::validate(config)
+    }
+
+    pub fn build(self) -> Result<SmtpPlugin, SmtpPluginError> {
+        debug!("{} SMTP payloads loaded", self.configs.len());
+        let mut payloads = Vec::new();
+        for config in &self.configs {
+            payloads.push(SmtpPayload::try_from(config)?);
+        }
+        Ok(SmtpPlugin { payloads })
+    }
+}
@@ -0,0 +1,287 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mail_parser::{MessageParser, MimeHeaders, PartType};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+/// The most messages kept, the oldest ones are dropped first.
+const MAX_MESSAGES: usize = 10_000;
+/// The most memory the messages kept may use, counting both their raw and
+/// decoded content. The oldest ones are dropped first.
+const MAX_MAILBOX_BYTES: usize = 256 * 1024 * 1024;
+
+/// The messages accepted by the `smtp` listeners, kept in memory until they
+/// are cleared. Clones share the messages.
+#[derive(Debug, Clone, Default)]
+pub struct Mailbox {
+    inner: Arc<Mutex<MailboxInner>>,
+}
+
+#[derive(Debug)]
+struct MailboxInner {
+    last_id: u64,
+    messages: VecDeque<CapturedMessage>,
+    /// The size of the messages kept, see `CapturedMessage::size`.
+    bytes: usize,
+    max_messages: usize,
+    max_bytes: usize,
+}
+
+impl Default for MailboxInner {
+    fn default() -> Self {
+        Self {
+            last_id: 0,
+            messages: VecDeque::new(),
+            bytes: 0,
+            max_messages: MAX_MESSAGES,
+            max_bytes: MAX_MAILBOX_BYTES,
+        }
+    }
+}
+
+/// A message as the admin endpoint returns it.
+#[derive(Serialize, Debug, Clone)]
+pub struct CapturedMessage {
+    /// Counts up from 1, across the listeners.
+    pub id: u64,
+    /// The listener the message was sent to, `None` being the default listener.
+    pub listener: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub received_at: u64,
+    /// The sender of the envelope, from `MAIL FROM`.
+    pub from: String,
+    /// The recipients of the envelope, from `RCPT TO`, so including `Bcc`.
+    pub recipients: Vec<String>,
+    pub subject: Option<String>,
+    /// Every header in order, unfolded but not decoded.
+    pub headers: Vec<CapturedHeader>,
+    /// The parts with a body, leaving out the multipart containers.
+    pub parts: Vec<CapturedPart>,
+    /// The message as it was sent.
+    pub raw: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CapturedHeader {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CapturedPart {
+    pub content_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// `text` when the body is decoded text, `base64` for any other body.
+    pub encoding: &'static str,
+    pub body: String,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses and keeps a message, returning its id.
+    pub(crate) fn store(
+        &self,
+        listener: Option<&str>,
+        from: &str,
+        recipients: &[String],
+        data: &[u8],
+    ) -> u64 {
+        let mut message = CapturedMessage::parse(data);
+        message.listener = listener.map(|x| x.to_string());
+        message.from = from.to_string();
+        message.recipients = recipients.to_vec();
+
+        let size = message.size();
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_id += 1;
+        message.id = inner.last_id;
+        while inner.messages.len() >= inner.max_messages
+            || (!inner.messages.is_empty() && inner.bytes + size > inner.max_bytes)
+        {
+            debug!("Dropping the oldest captured message");
+            if let Some(dropped) = inner.messages.pop_front() {
+                inner.bytes -= dropped.size();
+            }
+        }
+        inner.bytes += size;
+        inner.messages.push_back(message);
+        inner.last_id
+    }
+
+    /// Every message kept, oldest first.
+    pub fn messages(&self) -> Vec<CapturedMessage> {
+        self.inner
+            .lock()
+            .unwrap()
+            .messages
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets every message, returning how many there were. Ids keep counting up.
+    pub fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let count = inner.messages.len();
+        inner.messages.clear();
+        inner.bytes = 0;
+        count
+    }
+}
+
+impl CapturedMessage {
+    /// Roughly the memory used by the message, its raw content and every
+    /// decoded part and header.
+    fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|header| header.name.len() + header.value.len())
+            .sum();
+        let parts: usize = self
+            .parts
+            .iter()
+            .map(|part| part.content_type.len() + part.body.len())
+            .sum();
+        self.raw.len() + headers + parts
+    }
+
+    /// Messages that can't be parsed are still kept, with their raw content.
+    fn parse(data: &[u8]) -> Self {
+        let mut captured = Self {
+            id: 0,
+            listener: None,
+            received_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis() as u64)
+                .unwrap_or_default(),
+            from: String::new(),
+            recipients: Vec::new(),
+            subject: None,
+            headers: Vec::new(),
+            parts: Vec::new(),
+            raw: String::from_utf8_lossy(data).into_owned(),
+        };
+        let message = match MessageParser::default().parse(data) {
+            Some(message) => message,
+            None => return captured,
+        };
+
+        captured.subject = message.subject().map(|x| x.to_string());
+        captured.headers = message
+            .headers_raw()
+            .map(|(name, value)| CapturedHeader {
+                name: name.to_string(),
+                value: value
+                    .replace("\r\n", "")
+                    .replace('\n', "")
+                    .trim()
+                    .to_string(),
+            })
+            .collect();
+
+        for part in &message.parts {
+            let (default_type, encoding, body) = match &part.body {
+                PartType::Multipart(_) => continue,
+                PartType::Text(text) => ("text/plain", "text", text.to_string()),
+                PartType::Html(html) => ("text/html", "text", html.to_string()),
+                PartType::Binary(data) | PartType::InlineBinary(data) => {
+                    ("application/octet-stream", "base64", STANDARD.encode(data))
+                }
+                PartType::Message(nested) => (
+                    "message/rfc822",
+                    "text",
+                    String::from_utf8_lossy(nested.raw_message()).into_owned(),
+                ),
+            };
+            let content_type = match part.content_type() {
+                Some(content_type) => match &content_type.c_subtype {
+                    Some(subtype) => format!("{}/{}", content_type.c_type, subtype),
+                    None => content_type.c_type.to_string(),
+                },
+                None => default_type.to_string(),
+            };
+            captured.parts.push(CapturedPart {
+                content_type,
+                filename: part.attachment_name().map(|x| x.to_string()),
+                encoding,
+                body,
+            });
+        }
+        captured
+    }
+}
+
+#[test]
+fn test_parse_multipart_message() {
+    let data = "From: App <app@example.test>\r\n\
+        To: user@example.test\r\n\
+        Subject: =?UTF-8?Q?Welcome_=F0=9F=91=8B?=\r\n\
+        X-Campaign: onboarding,\r\n \tday-1\r\n\
+        Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        \r\n\
+        Hello there\r\n\
+        --b\r\n\
+        Content-Type: application/pdf\r\n\
+        Content-Disposition: attachment; filename=\"terms.pdf\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        JVBERg==\r\n\
+        --b--\r\n";
+    let message = CapturedMessage::parse(data.as_bytes());
+
+    assert_eq!(Some("Welcome 👋"), message.subject.as_deref());
+    let campaign = message
+        .headers
+        .iter()
+        .find(|header| header.name == "X-Campaign")
+        .unwrap();
+    assert_eq!("onboarding, \tday-1", campaign.value);
+
+    assert_eq!(2, message.parts.len());
+    assert_eq!("text/plain", message.parts[0].content_type);
+    assert_eq!("Hello there", message.parts[0].body);
+    assert_eq!("application/pdf", message.parts[1].content_type);
+    assert_eq!(Some("terms.pdf"), message.parts[1].filename.as_deref());
+    assert_eq!(
+        ("base64", "JVBERg=="),
+        (message.parts[1].encoding, message.parts[1].body.as_str())
+    );
+}
+
+#[test]
+fn test_mailbox_drops_oldest_messages_over_size() {
+    let mailbox = Mailbox::new();
+    let message = |body: &str| format!("Subject: test\r\n\r\n{}\r\n", body);
+    let size = CapturedMessage::parse(message("a").as_bytes()).size();
+    mailbox.inner.lock().unwrap().max_bytes = size * 2;
+
+    for body in ["a", "b", "c"] {
+        mailbox.store(None, "app@example.test", &[], message(body).as_bytes());
+    }
+    let ids: Vec<u64> = mailbox.messages().iter().map(|x| x.id).collect();
+    assert_eq!(vec![2, 3], ids);
+
+    // A message larger than the limit is still kept, on its own.
+    mailbox.store(
+        None,
+        "app@example.test",
+        &[],
+        message(&"x".repeat(size * 2)).as_bytes(),
+    );
+    let ids: Vec<u64> = mailbox.messages().iter().map(|x| x.id).collect();
+    assert_eq!(vec![4], ids);
+    assert_eq!(1, mailbox.clear());
+    assert_eq!(0, mailbox.inner.lock().unwrap().bytes);
+}
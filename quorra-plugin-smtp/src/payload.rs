@@ -0,0 +1,202 @@
+use crate::SmtpPluginError;
+use quorra_config::prelude::*;
+use rand::{seq::SliceRandom, thread_rng};
+use regex::Regex;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::info;
+
+/// Answers the commands of an `smtp` listener with the first payload that
+/// matches.
+#[derive(Debug)]
+pub struct SmtpPlugin {
+    pub(crate) payloads: Vec<SmtpPayload>,
+}
+
+#[derive(Debug)]
+pub(crate) struct SmtpPayload {
+    id: String,
+    on: SmtpCommand,
+    matchers: Vec<EnvelopeMatcher>,
+    pointer: AtomicUsize,
+    responses: Vec<Arc<SmtpResponse>>,
+}
+
+#[derive(Debug)]
+struct EnvelopeMatcher {
+    from: Option<Regex>,
+    to: Option<Regex>,
+}
+
+#[derive(Debug)]
+struct SmtpResponse {
+    id: String,
+    code: u16,
+    message: Option<String>,
+    delay: Duration,
+}
+
+/// The reply to a command.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Reply {
+    pub(crate) code: u16,
+    pub(crate) message: String,
+    pub(crate) delay: Duration,
+}
+
+pub(crate) fn validate(config: &SmtpConfig) -> Vec<(String, SmtpPluginError)> {
+    let mut errors = Vec::new();
+    for (index, matcher) in config.matches.iter().enumerate() {
+        if let Err(e) = EnvelopeMatcher::try_from(config.on, matcher) {
+            errors.push((format!("matches[{}]", index), e));
+        }
+    }
+    for (index, response) in config.responses.iter().enumerate() {
+        if let Err(e) = SmtpResponse::try_from(response) {
+            errors.push((format!("responses[{}]", index), e));
+        }
+    }
+    if never_picked(&config.responses) {
+        errors.push((
+            "responses".to_string(),
+            SmtpPluginError::NoResponsesProvided,
+        ));
+    }
+    errors
+}
+
+impl SmtpPayload {
+    pub(crate) fn try_from(config: &SmtpConfig) -> Result<Self, SmtpPluginError> {
+        let mut matchers = Vec::new();
+        for matcher in &config.matches {
+            matchers.push(EnvelopeMatcher::try_from(config.on, matcher)?);
+        }
+
+        let mut responses = Vec::new();
+        for response_config in &config.responses {
+            let response = Arc::new(SmtpResponse::try_from(response_config)?);
+            for _ in 0..response_config.weight {
+                responses.push(response.clone());
+            }
+        }
+        responses.shuffle(&mut thread_rng());
+        if responses.is_empty() {
+            return Err(SmtpPluginError::NoResponsesProvided);
+        }
+
+        Ok(Self {
+            id: config.id.clone(),
+            on: config.on,
+            matchers,
+            pointer: AtomicUsize::new(0),
+            responses,
+        })
+    }
+
+    fn get_response(&self) -> &SmtpResponse {
+        let value = match self
+            .pointer
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+                Some((value + 1) % self.responses.len())
+            }) {
+            Ok(value) => value,
+            Err(value) => value,
+        };
+        &self.responses[value]
+    }
+
+    fn matches(&self, from: &str, to: &[String]) -> bool {
+        self.matchers.is_empty() || self.matchers.iter().any(|x| x.matches(from, to))
+    }
+}
+
+impl EnvelopeMatcher {
+    fn try_from(on: SmtpCommand, config: &SmtpMatchesConfig) -> Result<Self, SmtpPluginError> {
+        if on == SmtpCommand::Mail && config.to.is_some() {
+            return Err(SmtpPluginError::RecipientOnMail);
+        }
+        Ok(Self {
+            from: config.from.as_deref().map(Regex::new).transpose()?,
+            to: config.to.as_deref().map(Regex::new).transpose()?,
+        })
+    }
+
+    fn matches(&self, from: &str, to: &[String]) -> bool {
+        if let Some(regex) = &self.from {
+            if !regex.is_match(from) {
+                return false;
+            }
+        }
+        if let Some(regex) = &self.to {
+            if !to.iter().any(|to| regex.is_match(to)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl SmtpResponse {
+    fn try_from(config: &SmtpResponseConfig) -> Result<Self, SmtpPluginError> {
+        // `3xx` replies ask for more from the client, which wouldn't follow.
+        if !matches!(config.code, 200..=299 | 400..=599) {
+            return Err(SmtpPluginError::InvalidCode(config.code));
+        }
+        Ok(Self {
+            id: config.id.clone(),
+            code: config.code,
+            message: config.message.clone(),
+            delay: Duration::from_millis(config.delay),
+        })
+    }
+}
+
+impl SmtpPlugin {
+    /// The reply of the first payload that matches the command, if any. `to` is
+    /// the recipient being added on `rcpt`.
+    pub(crate) fn reply(&self, on: SmtpCommand, from: &str, to: &[String]) -> Option<Reply> {
+        let payload = self
+            .payloads
+            .iter()
+            .find(|payload| payload.on == on && payload.matches(from, to))?;
+        let response = payload.get_response();
+        info!(
+            payload.id = payload.id,
+            response.id = response.id,
+            "Answering an SMTP command"
+        );
+
+        let message = response.message.clone().unwrap_or_else(|| {
+            match response.code {
+                200..=299 => "OK",
+                421 => "Service not available, closing the connection",
+                400..=499 => "Try again later",
+                _ => "Rejected",
+            }
+            .to_string()
+        });
+        Some(Reply {
+            code: response.code,
+            message,
+            delay: response.delay,
+        })
+    }
+}
+
+#[test]
+fn test_validate_reply_codes() {
+    let config: SmtpConfig = serde_json::from_str(
+        r#"{"on": "rcpt", "responses": [{"code": 250}, {"code": 354}, {"code": 421}, {"code": 600}]}"#,
+    )
+    .unwrap();
+    let parts: Vec<String> = validate(&config)
+        .into_iter()
+        .map(|(part, _)| part)
+        .collect();
+    assert_eq!(vec!["responses[1]", "responses[3]"], parts);
+}
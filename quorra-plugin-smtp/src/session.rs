@@ -0,0 +1,362 @@
+use crate::{payload::Reply, Mailbox, SmtpPlugin};
+use quorra_config::prelude::SmtpCommand;
+use std::{io, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::RwLock,
+    time::sleep,
+};
+use tracing::{debug, info};
+
+/// The largest message accepted, larger ones get a `552`.
+const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+/// The longest command line, with its newline, from RFC 5321. Longer ones get
+/// a `500`. Longer lines of a message are read in pieces of this size.
+const MAX_LINE_LENGTH: u64 = 1000;
+
+struct Session<S> {
+    stream: BufReader<S>,
+    plugin: Arc<RwLock<SmtpPlugin>>,
+    mailbox: Mailbox,
+    listener: Option<String>,
+    /// Set by `MAIL FROM`, until the message is sent or the session is reset.
+    from: Option<String>,
+    recipients: Vec<String>,
+    closing: bool,
+}
+
+/// Plays the server side of an SMTP session, keeping the messages it accepts
+/// in the mailbox. Any credentials are accepted. The plugin is locked for
+/// every command, so that open connections pick up reloads.
+pub async fn serve_connection<S>(
+    stream: S,
+    plugin: Arc<RwLock<SmtpPlugin>>,
+    mailbox: Mailbox,
+    listener: Option<String>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    info!("SMTP connection opened");
+    let mut session = Session {
+        stream: BufReader::new(stream),
+        plugin,
+        mailbox,
+        listener,
+        from: None,
+        recipients: Vec::new(),
+        closing: false,
+    };
+    if let Err(e) = session.run().await {
+        debug!("SMTP connection failed: {}", e);
+    }
+    info!("SMTP connection closed");
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    async fn run(&mut self) -> io::Result<()> {
+        self.send(220, "quorra ESMTP Quorra").await?;
+        while !self.closing {
+            match self.read_command().await? {
+                Some(line) => self.command(&line).await?,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads up to the next newline, but no more than `MAX_LINE_LENGTH` bytes.
+    /// Returns 0 once the client is gone.
+    async fn read_piece(&mut self, piece: &mut Vec<u8>) -> io::Result<usize> {
+        piece.clear();
+        (&mut self.stream)
+            .take(MAX_LINE_LENGTH)
+            .read_until(b'\n', piece)
+            .await
+    }
+
+    /// Reads a command line, answering `500` to the ones that are too long.
+    /// `None` once the client is gone.
+    async fn read_command(&mut self) -> io::Result<Option<String>> {
+        let mut line = Vec::new();
+        loop {
+            if self.read_piece(&mut line).await? == 0 {
+                return Ok(None);
+            }
+            if line.ends_with(b"\n") {
+                return Ok(Some(String::from_utf8_lossy(&line).trim_end().to_string()));
+            }
+            // The rest of the line is dropped as it comes.
+            while !line.ends_with(b"\n") {
+                if self.read_piece(&mut line).await? == 0 {
+                    return Ok(None);
+                }
+            }
+            self.send(500, "Line too long").await?;
+        }
+    }
+
+    async fn command(&mut self, line: &str) -> io::Result<()> {
+        let (verb, argument) = match line.split_once(' ') {
+            Some((verb, argument)) => (verb, argument.trim()),
+            None => (line, ""),
+        };
+        match verb.to_ascii_uppercase().as_str() {
+            "HELO" => self.send(250, "quorra").await,
+            "EHLO" => {
+                self.stream
+                    .write_all(
+                        b"250-quorra\r\n250-8BITMIME\r\n250-SMTPUTF8\r\n250 AUTH PLAIN LOGIN\r\n",
+                    )
+                    .await
+            }
+            "AUTH" => self.authenticate(argument).await,
+            "MAIL" => self.mail(argument).await,
+            "RCPT" => self.rcpt(argument).await,
+            "DATA" => self.data().await,
+            "RSET" => {
+                self.reset();
+                self.send(250, "OK").await
+            }
+            "NOOP" => self.send(250, "OK").await,
+            "VRFY" => self.send(252, "Cannot VRFY user").await,
+            "QUIT" => {
+                self.closing = true;
+                self.send(221, "Bye").await
+            }
+            _ => self.send(502, "Command not implemented").await,
+        }
+    }
+
+    /// Prompts for as many lines as the mechanism needs, then accepts them.
+    async fn authenticate(&mut self, argument: &str) -> io::Result<()> {
+        let mut words = argument.split_whitespace();
+        let mechanism = words.next().unwrap_or_default().to_ascii_uppercase();
+        let prompts: &[&str] = match (mechanism.as_str(), words.next()) {
+            ("PLAIN", Some(_)) => &[],
+            ("PLAIN", None) => &[""],
+            ("LOGIN", Some(_)) => &["UGFzc3dvcmQ6"],
+            ("LOGIN", None) => &["VXNlcm5hbWU6", "UGFzc3dvcmQ6"],
+            _ => return self.send(504, "Unrecognized authentication type").await,
+        };
+        for prompt in prompts {
+            self.send(334, prompt).await?;
+            if self.read_command().await?.is_none() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        self.send(235, "Authentication successful").await
+    }
+
+    async fn mail(&mut self, argument: &str) -> io::Result<()> {
+        let from = match parse_path(argument, "FROM:") {
+            Some(from) => from,
+            None => return self.send(501, "Syntax: MAIL FROM:<address>").await,
+        };
+        self.reset();
+        match self.injected(SmtpCommand::Mail, &from, &[]).await {
+            Some(reply) if reply.code >= 400 => self.reject(reply).await,
+            reply => {
+                self.from = Some(from);
+                self.accept(reply, "OK").await
+            }
+        }
+    }
+
+    async fn rcpt(&mut self, argument: &str) -> io::Result<()> {
+        let from = match &self.from {
+            Some(from) => from.clone(),
+            None => return self.send(503, "Send MAIL first").await,
+        };
+        let to = match parse_path(argument, "TO:").filter(|to| !to.is_empty()) {
+            Some(to) => to,
+            None => return self.send(501, "Syntax: RCPT TO:<address>").await,
+        };
+        match self
+            .injected(SmtpCommand::Rcpt, &from, std::slice::from_ref(&to))
+            .await
+        {
+            Some(reply) if reply.code >= 400 => self.reject(reply).await,
+            reply => {
+                self.recipients.push(to);
+                self.accept(reply, "OK").await
+            }
+        }
+    }
+
+    async fn data(&mut self) -> io::Result<()> {
+        let from = match &self.from {
+            Some(from) if !self.recipients.is_empty() => from.clone(),
+            Some(_) => return self.send(503, "Send RCPT first").await,
+            None => return self.send(503, "Send MAIL first").await,
+        };
+        self.send(354, "End data with <CR><LF>.<CR><LF>").await?;
+
+        let mut data = Vec::new();
+        let mut too_large = false;
+        let mut piece = Vec::new();
+        let mut at_line_start = true;
+        loop {
+            if self.read_piece(&mut piece).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line_start = at_line_start;
+            at_line_start = piece.ends_with(b"\n");
+            if line_start && (piece == b".\r\n" || piece == b".\n") {
+                break;
+            }
+            // Lines starting with a dot get another one in front.
+            let piece = match line_start {
+                true => piece.strip_prefix(b".").unwrap_or(&piece[..]),
+                false => &piece[..],
+            };
+            if data.len() + piece.len() > MAX_MESSAGE_SIZE {
+                too_large = true;
+            } else {
+                data.extend_from_slice(piece);
+            }
+        }
+
+        let recipients = std::mem::take(&mut self.recipients);
+        self.reset();
+        if too_large {
+            return self.send(552, "Message too large").await;
+        }
+        match self.injected(SmtpCommand::Data, &from, &recipients).await {
+            Some(reply) if reply.code >= 400 => self.reject(reply).await,
+            reply => {
+                let id = self
+                    .mailbox
+                    .store(self.listener.as_deref(), &from, &recipients, &data);
+                info!(message.id = id, "Captured a message");
+                self.accept(reply, &format!("OK: queued as {}", id)).await
+            }
+        }
+    }
+
+    /// The reply of the payload matching the command, once its delay is over.
+    async fn injected(&mut self, on: SmtpCommand, from: &str, to: &[String]) -> Option<Reply> {
+        let reply = self.plugin.read().await.reply(on, from, to);
+        if let Some(reply) = &reply {
+            sleep(reply.delay).await;
+        }
+        reply
+    }
+
+    /// Sends the reply of the payload that accepted the command, or `250`.
+    async fn accept(&mut self, reply: Option<Reply>, message: &str) -> io::Result<()> {
+        match reply {
+            Some(reply) => self.send(reply.code, &reply.message).await,
+            None => self.send(250, message).await,
+        }
+    }
+
+    /// Sends the failure of a payload. `421` closes the connection, like a
+    /// server shutting down.
+    async fn reject(&mut self, reply: Reply) -> io::Result<()> {
+        self.closing = reply.code == 421;
+        self.send(reply.code, &reply.message).await
+    }
+
+    fn reset(&mut self) {
+        self.from = None;
+        self.recipients.clear();
+    }
+
+    async fn send(&mut self, code: u16, message: &str) -> io::Result<()> {
+        self.stream
+            .write_all(format!("{} {}\r\n", code, message).as_bytes())
+            .await
+    }
+}
+
+/// Reads the address of `FROM:<address> SIZE=100`, which is empty for bounces.
+fn parse_path(argument: &str, prefix: &str) -> Option<String> {
+    let head = argument.get(..prefix.len())?;
+    if !head.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let path = argument[prefix.len()..].trim_start();
+    match path.strip_prefix('<') {
+        Some(path) => path.find('>').map(|end| path[..end].to_string()),
+        None => path.split_whitespace().next().map(|x| x.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_session_captures_messages() {
+    use tokio::io::AsyncReadExt;
+
+    let config: quorra_config::prelude::SmtpConfig = serde_json::from_str(
+        r#"{"on": "rcpt", "matches": [{"to": "@blocked\\.test$"}], "responses": [{"code": 550}]}"#,
+    )
+    .unwrap();
+    let mut builder = crate::SmtpPluginBuilder::new();
+    builder.load_config(&config);
+    let plugin = Arc::new(RwLock::new(builder.build().unwrap()));
+    let mailbox = Mailbox::new();
+
+    let (mut client, server) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(serve_connection(
+        server,
+        plugin,
+        mailbox.clone(),
+        Some("mail".to_string()),
+    ));
+    client
+        .write_all(
+            b"EHLO client\r\nMAIL FROM:<app@example.test> SIZE=100\r\n\
+            RCPT TO:<user@blocked.test>\r\nRCPT TO:<user@example.test>\r\nDATA\r\n\
+            Subject: Hi\r\n\r\n..dotted\r\n.\r\nQUIT\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut replies = String::new();
+    client.read_to_string(&mut replies).await.unwrap();
+    connection.await.unwrap();
+    let codes: Vec<&str> = replies.lines().map(|line| &line[..4]).collect();
+    assert_eq!(
+        vec![
+            "220 ", "250-", "250-", "250-", "250 ", "250 ", "550 ", "250 ", "354 ", "250 ", "221 "
+        ],
+        codes
+    );
+
+    let messages = mailbox.messages();
+    assert_eq!(1, messages.len());
+    assert_eq!(Some("mail"), messages[0].listener.as_deref());
+    assert_eq!("app@example.test", messages[0].from);
+    assert_eq!(vec!["user@example.test"], messages[0].recipients);
+    assert_eq!("Subject: Hi\r\n\r\n.dotted\r\n", messages[0].raw);
+}
+
+#[tokio::test]
+async fn test_session_limits_line_length() {
+    use tokio::io::AsyncReadExt;
+
+    let plugin = Arc::new(RwLock::new(
+        crate::SmtpPluginBuilder::new().build().unwrap(),
+    ));
+    let mailbox = Mailbox::new();
+    let (mut client, server) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(serve_connection(server, plugin, mailbox.clone(), None));
+
+    let long = "x".repeat(2500);
+    let session = format!(
+        "NOOP {long}\r\nMAIL FROM:<app@example.test>\r\nRCPT TO:<user@example.test>\r\n\
+        DATA\r\n{long}\r\n.\r\nQUIT\r\n",
+        long = long
+    );
+    let writer = tokio::spawn(async move {
+        client.write_all(session.as_bytes()).await.unwrap();
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).await.unwrap();
+        replies
+    });
+    connection.await.unwrap();
+    let replies = writer.await.unwrap();
+
+    let codes: Vec<&str> = replies.lines().map(|line| &line[..3]).collect();
+    assert_eq!(vec!["220", "500", "250", "250", "354", "250", "221"], codes);
+    assert_eq!(format!("{}\r\n", long), mailbox.messages()[0].raw);
+}
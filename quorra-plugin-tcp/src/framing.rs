@@ -0,0 +1,116 @@
+use crate::TcpPluginError;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use quorra_config::prelude::TcpFramingConfig;
+
+/// The largest frame read, so that a client that never sends the delimiter
+/// can't use up the memory.
+pub(crate) const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) enum Framing {
+    Delimiter(Vec<u8>),
+    /// The number of bytes of the length.
+    LengthPrefix(usize),
+}
+
+impl TryFrom<&TcpFramingConfig> for Framing {
+    type Error = TcpPluginError;
+
+    fn try_from(config: &TcpFramingConfig) -> Result<Self, Self::Error> {
+        match config {
+            TcpFramingConfig::Delimiter(delimiter) if delimiter.is_empty() => {
+                Err(TcpPluginError::EmptyDelimiter)
+            }
+            TcpFramingConfig::Delimiter(delimiter) => {
+                Ok(Framing::Delimiter(delimiter.as_bytes().to_vec()))
+            }
+            TcpFramingConfig::LengthPrefix(size @ (1 | 2 | 4)) => {
+                Ok(Framing::LengthPrefix(*size as usize))
+            }
+            TcpFramingConfig::LengthPrefix(size) => Err(TcpPluginError::InvalidLengthPrefix(*size)),
+        }
+    }
+}
+
+impl Framing {
+    /// Takes the next complete frame off the front of the buffer.
+    pub(crate) fn decode(&self, buffer: &mut BytesMut) -> Result<Option<Bytes>, TcpPluginError> {
+        match self {
+            Framing::Delimiter(delimiter) => {
+                match buffer
+                    .windows(delimiter.len())
+                    .position(|window| window == delimiter.as_slice())
+                {
+                    Some(end) => {
+                        let frame = buffer.split_to(end).freeze();
+                        buffer.advance(delimiter.len());
+                        Ok(Some(frame))
+                    }
+                    None if buffer.len() > MAX_FRAME_SIZE => {
+                        Err(TcpPluginError::FrameTooLarge(buffer.len()))
+                    }
+                    None => Ok(None),
+                }
+            }
+            Framing::LengthPrefix(size) => {
+                if buffer.len() < *size {
+                    return Ok(None);
+                }
+                let length = buffer[..*size]
+                    .iter()
+                    .fold(0, |length, byte| length << 8 | *byte as usize);
+                if length > MAX_FRAME_SIZE {
+                    return Err(TcpPluginError::FrameTooLarge(length));
+                }
+                if buffer.len() < size + length {
+                    return Ok(None);
+                }
+                buffer.advance(*size);
+                Ok(Some(buffer.split_to(length).freeze()))
+            }
+        }
+    }
+
+    /// Frames a reply the way frames are read.
+    pub(crate) fn encode(&self, data: &[u8]) -> Result<Bytes, TcpPluginError> {
+        let mut frame = BytesMut::with_capacity(data.len() + 4);
+        match self {
+            Framing::Delimiter(delimiter) => {
+                frame.put_slice(data);
+                frame.put_slice(delimiter);
+            }
+            Framing::LengthPrefix(size) => {
+                let length = u32::try_from(data.len())
+                    .ok()
+                    .filter(|length| *size == 4 || *length < 1 << (8 * size))
+                    .ok_or(TcpPluginError::ReplyTooLarge(data.len(), *size))?;
+                frame.put_slice(&length.to_be_bytes()[4 - size..]);
+                frame.put_slice(data);
+            }
+        }
+        Ok(frame.freeze())
+    }
+}
+
+#[test]
+fn test_length_prefix_round_trip() {
+    let framing = Framing::try_from(&TcpFramingConfig::LengthPrefix(2)).unwrap();
+    let mut buffer = BytesMut::from(&framing.encode(b"ping").unwrap()[..]);
+    assert_eq!(&[0, 4], &buffer[..2]);
+
+    buffer.put_slice(&[0, 4, b'p']);
+    assert_eq!(
+        Some(Bytes::from("ping")),
+        framing.decode(&mut buffer).unwrap()
+    );
+    assert_eq!(None, framing.decode(&mut buffer).unwrap());
+    buffer.put_slice(b"ong");
+    assert_eq!(
+        Some(Bytes::from("pong")),
+        framing.decode(&mut buffer).unwrap()
+    );
+    assert!(buffer.is_empty());
+
+    let framing = Framing::try_from(&TcpFramingConfig::LengthPrefix(1)).unwrap();
+    assert!(framing.encode(&[0; 256]).is_err());
+}
@@ -0,0 +1,85 @@
+use thiserror::Error;
+
+mod framing;
+mod payload;
+
+pub use payload::{serve_connection, TcpPlugin};
+
+use framing::Framing;
+use handlebars::Handlebars;
+use payload::TcpPayload;
+use quorra_config::prelude::{TcpConfig, TcpFramingConfig};
+use tracing::debug;
+
+#[derive(Error, Debug)]
+pub enum TcpPluginError {
+    #[error(transparent)]
+    RegexError(#[from] regex::Error),
+    #[error(transparent)]
+    TemplateError(Box<handlebars::TemplateError>),
+    #[error("invalid hex `{0}`, expected pairs of hex digits")]
+    InvalidHex(String),
+    #[error("No response configured for match")]
+    NoResponsesProvided,
+    #[error("the frame delimiter can't be empty")]
+    EmptyDelimiter,
+    #[error("a length prefix of {0} bytes, expected 1, 2 or 4")]
+    InvalidLengthPrefix(u8),
+    #[error("a frame of {0} bytes is larger than the limit")]
+    FrameTooLarge(usize),
+    #[error("a reply of {0} bytes doesn't fit a length prefix of {1} bytes")]
+    ReplyTooLarge(usize, usize),
+}
+
+// Boxed, the template errors are larger than every other variant together.
+impl From<handlebars::TemplateError> for TcpPluginError {
+    fn from(e: handlebars::TemplateError) -> Self {
+        Self::TemplateError(Box::new(e))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TcpPluginBuilder {
+    framing: TcpFramingConfig,
+    configs: Vec<TcpConfig>,
+}
+
+impl TcpPluginBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How the listener splits what it reads into frames, a line at a time unless set.
+    pub fn with_framing(mut self, framing: TcpFramingConfig) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub fn load_config(&mut self, config: &TcpConfig) {
+        self.configs.push(config.clone());
+    }
+
+    /// Checks a payload the way `build` would, returning every problem found
+    /// instead of stopping at the first one.
+    pub fn validate(config: &TcpConfig) -> Vec<(String, TcpPluginError)> {
+        payload::validate(config)
+    }
+
+    pub fn build(self) -> Result<TcpPlugin, TcpPluginError> {
+        debug!("{} TCP payloads loaded", self.configs.len());
+        let framing = Framing::try_from(&self.framing)?;
+
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        let mut payloads = Vec::new();
+        for config in &self.configs {
+            payloads.push(TcpPayload::try_from(config, &mut handlebars)?);
+        }
+
+        Ok(TcpPlugin {
+            framing,
+            handlebars,
+            payloads,
+        })
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{framing::Framing, TcpPluginError};
+use bytes::{Bytes, BytesMut};
+use handlebars::Handlebars;
+use quorra_config::prelude::*;
+use rand::{seq::SliceRandom, thread_rng};
+use regex::bytes::Regex;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::RwLock,
+    time::sleep,
+};
+use tracing::{debug, info, warn};
+
+/// Answers the frames of a `tcp` listener with the first payload that matches.
+#[derive(Debug)]
+pub struct TcpPlugin {
+    pub(crate) framing: Framing,
+    pub(crate) handlebars: Handlebars<'static>,
+    pub(crate) payloads: Vec<TcpPayload>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TcpPayload {
+    id: String,
+    matchers: Vec<FrameMatcher>,
+    pointer: AtomicUsize,
+    responses: Vec<Arc<TcpResponse>>,
+}
+
+#[derive(Debug)]
+struct FrameMatcher {
+    regex: Option<Regex>,
+    /// Searched for in the frame, like the regex. `None` matches any byte.
+    hex: Option<Vec<Option<u8>>>,
+}
+
+#[derive(Debug)]
+struct TcpResponse {
+    id: String,
+    messages: Vec<ScriptedMessage>,
+    close: bool,
+    delay: Duration,
+}
+
+#[derive(Debug)]
+struct ScriptedMessage {
+    data: MessageData,
+    delay: Duration,
+}
+
+#[derive(Debug)]
+enum MessageData {
+    /// The name of the template.
+    Template(String),
+    Bytes(Vec<u8>),
+}
+
+/// The reply to a frame, rendered and framed.
+struct Answer {
+    delay: Duration,
+    messages: Vec<(Duration, Bytes)>,
+    close: bool,
+}
+
+pub(crate) fn validate(config: &TcpConfig) -> Vec<(String, TcpPluginError)> {
+    let mut errors = Vec::new();
+    for (index, matcher) in config.matches.iter().enumerate() {
+        if let Err(e) = FrameMatcher::try_from(matcher) {
+            errors.push((format!("matches[{}]", index), e));
+        }
+    }
+    let mut handlebars = Handlebars::new();
+    for (index, response) in config.responses.iter().enumerate() {
+        if let Err(e) = TcpResponse::try_from(&config.id, response, &mut handlebars) {
+            errors.push((format!("responses[{}]", index), e));
+        }
+    }
+    if never_picked(&config.responses) {
+        errors.push(("responses".to_string(), TcpPluginError::NoResponsesProvided));
+    }
+    errors
+}
+
+impl TcpPayload {
+    pub(crate) fn try_from(
+        config: &TcpConfig,
+        handlebars: &mut Handlebars<'static>,
+    ) -> Result<Self, TcpPluginError> {
+        let mut matchers = Vec::new();
+        for matcher in &config.matches {
+            matchers.push(FrameMatcher::try_from(matcher)?);
+        }
+
+        let mut responses = Vec::new();
+        for response_config in &config.responses {
+            let response = Arc::new(TcpResponse::try_from(
+                &config.id,
+                response_config,
+                handlebars,
+            )?);
+            for _ in 0..response_config.weight {
+                responses.push(response.clone());
+            }
+        }
+        responses.shuffle(&mut thread_rng());
+        if responses.is_empty() {
+            return Err(TcpPluginError::NoResponsesProvided);
+        }
+
+        Ok(Self {
+            id: config.id.clone(),
+            matchers,
+            pointer: AtomicUsize::new(0),
+            responses,
+        })
+    }
+
+    fn get_response(&self) -> &TcpResponse {
+        let value = match self
+            .pointer
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+                Some((value + 1) % self.responses.len())
+            }) {
+            Ok(value) => value,
+            Err(value) => value,
+        };
+        &self.responses[value]
+    }
+
+    /// The captures of the first matcher that matches the frame.
+    fn matches(&self, frame: &[u8]) -> Option<Map<String, Value>> {
+        if self.matchers.is_empty() {
+            return Some(Map::new());
+        }
+        self.matchers
+            .iter()
+            .find_map(|matcher| matcher.matches(frame))
+    }
+}
+
+impl TryFrom<&TcpMatchesConfig> for FrameMatcher {
+    type Error = TcpPluginError;
+
+    fn try_from(config: &TcpMatchesConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            regex: config.regex.as_deref().map(Regex::new).transpose()?,
+            hex: config
+                .hex
+                .as_deref()
+                .map(|hex| parse_hex(hex, true))
+                .transpose()?,
+        })
+    }
+}
+
+impl FrameMatcher {
+    /// Returns every group of the regex, by index and by name.
+    fn matches(&self, frame: &[u8]) -> Option<Map<String, Value>> {
+        if let Some(hex) = &self.hex {
+            let found = hex.is_empty()
+                || frame.windows(hex.len()).any(|window| {
+                    hex.iter()
+                        .zip(window)
+                        .all(|(expected, actual)| expected.is_none() || *expected == Some(*actual))
+                });
+            if !found {
+                return None;
+            }
+        }
+
+        let mut captures = Map::new();
+        if let Some(regex) = &self.regex {
+            let found = regex.captures(frame)?;
+            for (index, name) in regex.capture_names().enumerate() {
+                if let Some(group) = found.get(index) {
+                    let value = Value::from(String::from_utf8_lossy(group.as_bytes()));
+                    if let Some(name) = name {
+                        captures.insert(name.to_string(), value.clone());
+                    }
+                    captures.insert(index.to_string(), value);
+                }
+            }
+        }
+        Some(captures)
+    }
+}
+
+impl TcpResponse {
+    fn try_from(
+        payload_id: &str,
+        config: &TcpResponseConfig,
+        handlebars: &mut Handlebars<'static>,
+    ) -> Result<Self, TcpPluginError> {
+        let mut messages = Vec::new();
+        for (index, message) in config.send.iter().enumerate() {
+            let data = match &message.data {
+                TcpDataConfig::Text(text) => {
+                    let name = format!("{}.{}.send.{}", payload_id, config.id, index);
+                    handlebars.register_template_string(&name, text)?;
+                    MessageData::Template(name)
+                }
+                TcpDataConfig::Hex(hex) => MessageData::Bytes(
+                    parse_hex(hex, false)?
+                        .into_iter()
+                        .map(|byte| byte.unwrap_or_default())
+                        .collect(),
+                ),
+            };
+            messages.push(ScriptedMessage {
+                data,
+                delay: Duration::from_millis(message.delay),
+            });
+        }
+
+        Ok(Self {
+            id: config.id.clone(),
+            messages,
+            close: config.close,
+            delay: Duration::from_millis(config.delay),
+        })
+    }
+}
+
+/// Reads hex like `0a ff 02`, ignoring whitespace. `??` reads as `None` when
+/// `wildcards` is set.
+fn parse_hex(hex: &str, wildcards: bool) -> Result<Vec<Option<u8>>, TcpPluginError> {
+    let invalid = || TcpPluginError::InvalidHex(hex.to_string());
+    let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(invalid());
+    }
+
+    pairs
+        .map(|pair| {
+            if wildcards && pair == ['?', '?'] {
+                return Ok(None);
+            }
+            match (pair[0].to_digit(16), pair[1].to_digit(16)) {
+                (Some(high), Some(low)) => Ok(Some((high * 16 + low) as u8)),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+impl TcpPlugin {
+    /// Answers every complete frame in the buffer, leaving the rest for later.
+    fn answer_all(&self, buffer: &mut BytesMut) -> Result<Vec<Answer>, TcpPluginError> {
+        let mut answers = Vec::new();
+        while let Some(frame) = self.framing.decode(buffer)? {
+            match self.answer(&frame) {
+                Some(answer) => answers.push(answer),
+                None => debug!("No payload matches the frame, ignoring it"),
+            }
+        }
+        Ok(answers)
+    }
+
+    fn answer(&self, frame: &[u8]) -> Option<Answer> {
+        let (payload, captures) = self
+            .payloads
+            .iter()
+            .find_map(|payload| payload.matches(frame).map(|captures| (payload, captures)))?;
+        let response = payload.get_response();
+        info!(
+            payload.id = payload.id,
+            response.id = response.id,
+            "Answering a frame"
+        );
+
+        let values = BTreeMap::from([
+            ("quorra_payload_id", Value::from(payload.id.as_str())),
+            ("frame", Value::from(String::from_utf8_lossy(frame))),
+            ("captures", Value::Object(captures)),
+        ]);
+        let mut messages = Vec::new();
+        for message in &response.messages {
+            let data = match &message.data {
+                MessageData::Bytes(data) => data.clone(),
+                MessageData::Template(name) => match self.handlebars.render(name, &values) {
+                    Ok(rendered) => rendered.into_bytes(),
+                    Err(e) => format!(
+                        "Quorra encountered an error rendering the message. Error {}",
+                        e
+                    )
+                    .into_bytes(),
+                },
+            };
+            match self.framing.encode(&data) {
+                Ok(framed) => messages.push((message.delay, framed)),
+                Err(e) => warn!("Skipping a message of response {}: {}", response.id, e),
+            }
+        }
+
+        Some(Answer {
+            delay: response.delay,
+            messages,
+            close: response.close,
+        })
+    }
+}
+
+impl Answer {
+    /// Returns `false` once the connection is closed.
+    async fn send<S: AsyncWrite + Unpin>(self, stream: &mut S) -> bool {
+        sleep(self.delay).await;
+        for (delay, message) in self.messages {
+            sleep(delay).await;
+            if stream.write_all(&message).await.is_err() {
+                return false;
+            }
+        }
+        if self.close {
+            let _ = stream.shutdown().await;
+            return false;
+        }
+        stream.flush().await.is_ok()
+    }
+}
+
+/// Reads frames off the stream and answers them, one after the other, until
+/// the client hangs up or a response closes the connection. The plugin is
+/// locked for every read, so that open connections pick up reloads.
+pub async fn serve_connection<S>(mut stream: S, plugin: Arc<RwLock<TcpPlugin>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    info!("TCP connection opened");
+    let mut buffer = BytesMut::with_capacity(4096);
+    'connection: loop {
+        match stream.read_buf(&mut buffer).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Unable to read from the connection: {}", e);
+                break;
+            }
+        }
+
+        let answers = match plugin.read().await.answer_all(&mut buffer) {
+            Ok(answers) => answers,
+            Err(e) => {
+                warn!("Closing the connection: {}", e);
+                break;
+            }
+        };
+        for answer in answers {
+            if !answer.send(&mut stream).await {
+                break 'connection;
+            }
+        }
+    }
+
+    if !buffer.is_empty() {
+        debug!("Dropping {} bytes of an incomplete frame", buffer.len());
+    }
+    info!("TCP connection closed");
+}
+
+#[test]
+fn test_frame_matcher() {
+    let config: TcpMatchesConfig =
+        serde_json::from_str(r#"{"regex": "^GET (?P<key>\\w+)", "hex": "47 ?? 54"}"#).unwrap();
+    let matcher = FrameMatcher::try_from(&config).unwrap();
+
+    let captures = matcher.matches(b"GET user:1").unwrap();
+    assert_eq!(Some(&Value::from("user")), captures.get("key"));
+    assert_eq!(Some(&Value::from("user")), captures.get("1"));
+    assert_eq!(Some(&Value::from("GET user")), captures.get("0"));
+    assert!(matcher.matches(b"SET user:1 x").is_none());
+    assert!(matcher.matches(b"GE").is_none());
+
+    let config: TcpMatchesConfig = serde_json::from_str(r#"{"hex": "?? 3a"}"#).unwrap();
+    let matcher = FrameMatcher::try_from(&config).unwrap();
+    assert!(matcher.matches(b"user:1").is_some());
+    assert!(matcher.matches(b":1").is_none());
+
+    assert!(parse_hex("0a f", false).is_err());
+    assert!(parse_hex("??", false).is_err());
+    assert_eq!(vec![Some(0x0a), None], parse_hex("0A ??", true).unwrap());
+}
+
+#[tokio::test]
+async fn test_serve_connection() {
+    let config: TcpConfig = serde_json::from_str(
+        r#"{
+            "id": "echo",
+            "matches": [{"regex": "^PING (.*)"}],
+            "responses": [{"send": [{"text": "PONG {{ captures.[1] }}"}, {"hex": "6f6b"}]}]
+        }"#,
+    )
+    .unwrap();
+    let mut builder = crate::TcpPluginBuilder::new();
+    builder.load_config(&config);
+    let plugin = Arc::new(RwLock::new(builder.build().unwrap()));
+
+    let (mut client, server) = tokio::io::duplex(1024);
+    let connection = tokio::spawn(serve_connection(server, plugin));
+    client.write_all(b"HELLO\nPING 42\n").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut reply = String::new();
+    client.read_to_string(&mut reply).await.unwrap();
+    assert_eq!("PONG 42\nok\n", reply);
+    connection.await.unwrap();
+}
@@ -1,6 +1,7 @@
 use crate::HttpPlugin;
 use bytes::Bytes;
-use http::{HeaderMap, Method, Response, Uri};
+use http::{HeaderMap, Method, Request, Response, Uri, Version};
+use hyper::Body;
 use std::sync::Arc;
 use thiserror::Error;
 use tracing::{error, instrument};
@@ -23,11 +24,16 @@ impl HttpBackend {
         }
     }
 
+    pub fn handle_upgrade(&self, request: &mut Request<Body>) -> Option<Response<Body>> {
+        self.http_plugin.upgrade_request(request)
+    }
+
     #[instrument(skip_all, fields(http.uri = %uri, http.method = %method))]
     pub async fn handle_request(
         &self,
         method: &Method,
         uri: &Uri,
+        version: Version,
         headers: &HeaderMap,
         body: &Bytes,
     ) -> Result<Option<Response<Bytes>>, HttpBackend> {
@@ -35,7 +41,7 @@ impl HttpBackend {
 
         if let Some(response) = self
             .http_plugin
-            .respond_to_request(method, uri, headers, &body)
+            .respond_to_request(method, uri, version, headers, &body)
             .await
         {
             return Ok(Some(response));
@@ -55,6 +61,7 @@ async fn test_no_match_found() {
         .handle_request(
             &Method::GET,
             &"/".parse::<Uri>().unwrap(),
+            Version::HTTP_11,
             &Default::default(),
             &Bytes::new(),
         )
@@ -74,6 +81,7 @@ async fn test_match_found() {
         .handle_request(
             &Method::GET,
             &"/".parse::<Uri>().unwrap(),
+            Version::HTTP_11,
             &Default::default(),
             &Bytes::new(),
         )
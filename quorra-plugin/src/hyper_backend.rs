@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
-use crate::HttpPlugin;
-use http::StatusCode;
-use hyper::{Body, Request, Response};
+use crate::{BodyStream, HttpPlugin, Trailers};
+use bytes::{Bytes, BytesMut};
+use http::{header::UPGRADE, HeaderMap, StatusCode};
+use hyper::{body::HttpBody, Body, Request, Response};
 use tracing::{debug, info, instrument};
 
 use crate::http_backend::HttpBackend;
@@ -10,6 +11,26 @@ use crate::http_backend::HttpBackend;
 #[derive(Clone)]
 pub struct HyperService {
     backends: Arc<Vec<HttpBackend>>,
+    fallback: Arc<FallbackResponse>,
+    max_body_size: Option<usize>,
+}
+
+/// The response sent when no plugin responds to the request.
+#[derive(Debug, Clone)]
+pub struct FallbackResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Default for FallbackResponse {
+    fn default() -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            headers: Default::default(),
+            body: Default::default(),
+        }
+    }
 }
 
 impl HyperService {
@@ -20,19 +41,50 @@ impl HyperService {
         }
         Self {
             backends: Arc::new(backends),
+            fallback: Default::default(),
+            max_body_size: None,
         }
     }
 
+    pub fn with_fallback(mut self, fallback: FallbackResponse) -> Self {
+        self.fallback = Arc::new(fallback);
+        self
+    }
+
+    /// Requests with a larger body are answered with a `413`, without being
+    /// read in full.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = Some(max_body_size);
+        self
+    }
+
     #[instrument(skip_all, fields(http.uri = %req.uri(), http.method = %req.method()))]
     pub async fn process_plugins(
         &self,
-        req: Request<Body>,
+        mut req: Request<Body>,
     ) -> Result<Response<Body>, anyhow::Error> {
+        if req.headers().contains_key(UPGRADE) {
+            for backend in self.backends.iter() {
+                if let Some(response) = backend.handle_upgrade(&mut req) {
+                    return Ok(response);
+                }
+            }
+        }
+
         let headers = req.headers().clone();
         let uri = req.uri().clone();
         let method = req.method().clone();
-        let body = match hyper::body::to_bytes(req.into_body()).await {
-            Ok(body) => body,
+        let version = req.version();
+        let body = match read_body(req.into_body(), self.max_body_size).await {
+            Ok(Some(body)) => body,
+            Ok(None) => {
+                info!("Request body is larger than {:?} bytes", self.max_body_size);
+
+                return Ok(Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .header("x-quorra-source", "internal")
+                    .body(Body::empty())?);
+            }
             Err(e) => {
                 info!("Unable to extract body from HTTP request. Error: {}", e);
 
@@ -46,10 +98,13 @@ impl HyperService {
         debug!("Incoming request");
 
         for backend in self.backends.iter() {
-            match backend.handle_request(&method, &uri, &headers, &body).await {
+            match backend
+                .handle_request(&method, &uri, version, &headers, &body)
+                .await
+            {
                 Ok(None) => continue,
                 Ok(Some(response)) => {
-                    return Ok(response.map(Body::from));
+                    return Ok(into_hyper_response(response));
                 }
                 Err(e) => {
                     info!(
@@ -63,10 +118,71 @@ impl HyperService {
 
         debug!("No backend was configured to process request");
 
-        Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())?)
+        let mut response = Response::builder()
+            .status(self.fallback.status)
+            .body(Body::from(self.fallback.body.clone()))?;
+        *response.headers_mut() = self.fallback.headers.clone();
+        Ok(response)
+    }
+}
+
+/// Streamed bodies and trailers need a body that is sent as it goes, the
+/// trailers once the body is done.
+fn into_hyper_response(response: Response<Bytes>) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    let stream = parts.extensions.remove::<BodyStream>();
+    let trailers = parts.extensions.remove::<Trailers>();
+    if stream.is_none() && trailers.is_none() {
+        return Response::from_parts(parts, Body::from(body));
+    }
+
+    let (mut sender, streamed) = Body::channel();
+    tokio::spawn(async move {
+        match stream {
+            Some(BodyStream(mut receiver)) => {
+                while let Some(chunk) = receiver.recv().await {
+                    if sender.send_data(chunk).await.is_err() {
+                        debug!("Client went away during a streamed body");
+                        return;
+                    }
+                }
+            }
+            None => {
+                if sender.send_data(body).await.is_err() {
+                    return;
+                }
+            }
+        }
+        if let Some(Trailers(trailers)) = trailers {
+            let _ = sender.send_trailers(trailers).await;
+        }
+    });
+    Response::from_parts(parts, streamed)
+}
+
+/// Reads the whole body, or returns `None` once it goes over `max_body_size`.
+async fn read_body(
+    mut body: Body,
+    max_body_size: Option<usize>,
+) -> Result<Option<Bytes>, hyper::Error> {
+    let max_body_size = match max_body_size {
+        Some(max_body_size) => max_body_size,
+        None => return hyper::body::to_bytes(body).await.map(Some),
+    };
+
+    if body.size_hint().lower() > max_body_size as u64 {
+        return Ok(None);
     }
+
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > max_body_size {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes.freeze()))
 }
 
 #[tokio::test]
@@ -85,3 +201,61 @@ async fn test_will_match_first() {
         .unwrap();
     assert_eq!(&String::from_utf8(body.to_vec()).unwrap(), "const ``");
 }
+
+#[tokio::test]
+#[allow(clippy::box_default)]
+async fn test_fallback_and_body_limit() {
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", "text/plain".parse().unwrap());
+    let service = HyperService::new(vec![Arc::new(Box::new(
+        crate::test_models::EmptyReponse::default(),
+    ))])
+    .with_fallback(FallbackResponse {
+        status: StatusCode::IM_A_TEAPOT,
+        headers,
+        body: Bytes::from("no mock"),
+    })
+    .with_max_body_size(4);
+
+    let req = Request::builder().body(Body::from("1234")).unwrap();
+    let response = service.process_plugins(req).await.unwrap();
+    assert_eq!(StatusCode::IM_A_TEAPOT, response.status());
+    assert_eq!("text/plain", response.headers()["content-type"]);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!("no mock", body);
+
+    let req = Request::builder().body(Body::from("12345")).unwrap();
+    let response = service.process_plugins(req).await.unwrap();
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+}
+
+#[tokio::test]
+async fn test_trailers_follow_the_body() {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", "0".parse().unwrap());
+    let mut response = Response::new(Bytes::from("done"));
+    response.extensions_mut().insert(Trailers(trailers));
+
+    let mut body = into_hyper_response(response).into_body();
+    assert_eq!("done", body.data().await.unwrap().unwrap());
+    assert!(body.data().await.is_none());
+    let trailers = body.trailers().await.unwrap().unwrap();
+    assert_eq!("0", trailers["grpc-status"]);
+}
+
+#[tokio::test]
+async fn test_body_stream_replaces_the_body() {
+    let (sender, receiver) = tokio::sync::mpsc::channel(1);
+    let mut response = Response::new(Bytes::new());
+    response.extensions_mut().insert(BodyStream(receiver));
+    tokio::spawn(async move {
+        for chunk in ["data: 1\n\n", "data: 2\n\n"] {
+            sender.send(Bytes::from(chunk)).await.unwrap();
+        }
+    });
+
+    let body = hyper::body::to_bytes(into_hyper_response(response).into_body())
+        .await
+        .unwrap();
+    assert_eq!("data: 1\n\ndata: 2\n\n", body);
+}
@@ -1,8 +1,10 @@
 use async_trait::async_trait;
 use bytes::Bytes;
-use http::{HeaderMap, Method, Response, Uri};
-pub use hyper_backend::HyperService;
+use http::{HeaderMap, Method, Request, Response, Uri, Version};
+use hyper::Body;
+pub use hyper_backend::{FallbackResponse, HyperService};
 use std::{fmt::Debug, sync::Arc};
+use tokio::sync::mpsc;
 
 mod http_backend;
 mod hyper_backend;
@@ -15,11 +17,29 @@ pub trait HttpPlugin: Debug + Sync + Send {
         &self,
         method: &Method,
         uri: &Uri,
+        version: Version,
         headers: &HeaderMap,
         body: &Option<&Bytes>,
     ) -> Option<Response<Bytes>>;
+
+    /// Called before the body is read, for requests asking to upgrade the
+    /// connection. A plugin taking over the connection answers with the `101`,
+    /// and waits for the upgrade with `hyper::upgrade::on`.
+    fn upgrade_request(&self, _request: &mut Request<Body>) -> Option<Response<Body>> {
+        None
+    }
 }
 
+/// Headers to send after the body, set as an extension of the plugin's response.
+#[derive(Debug, Clone)]
+pub struct Trailers(pub HeaderMap);
+
+/// A body sent chunk by chunk as it's produced, set as an extension of the
+/// plugin's response in place of its body. The stream ends when the sender is
+/// dropped, and sending fails once the client is gone.
+#[derive(Debug)]
+pub struct BodyStream(pub mpsc::Receiver<Bytes>);
+
 pub async fn make_hyper_backend(plugins: Vec<Arc<Box<dyn HttpPlugin>>>) -> HyperService {
     HyperService::new(plugins)
 }
@@ -1,5 +1,5 @@
 use bytes::Bytes;
-use http::{header::HeaderMap, Method, Response, StatusCode, Uri};
+use http::{header::HeaderMap, Method, Response, StatusCode, Uri, Version};
 
 #[derive(Debug, Default)]
 pub struct EmptyReponse {}
@@ -10,6 +10,7 @@ impl crate::HttpPlugin for EmptyReponse {
         &self,
         _method: &Method,
         _uri: &Uri,
+        _version: Version,
         _headers: &HeaderMap,
         _body: &Option<&Bytes>,
     ) -> Option<Response<Bytes>> {
@@ -28,6 +29,7 @@ impl crate::HttpPlugin for ConstantResponse {
         &self,
         _method: &Method,
         _uri: &Uri,
+        _version: Version,
         _headers: &HeaderMap,
         _body: &Option<&Bytes>,
     ) -> Option<Response<Bytes>> {
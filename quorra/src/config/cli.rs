@@ -20,5 +20,15 @@ pub enum SubCommands {
     /// Run the primary web server mocking responses
     Server(crate::server::ServerCommandConfig),
     /// Convert a har file into usable config
-    ConvertHar(crate::convert_har::ConvertHarCommandConfig),
+    ConvertHar(Box<crate::convert_har::ConvertHarCommandConfig>),
+    /// Convert the saved examples of a Postman collection into usable config
+    ConvertPostman(crate::convert_postman::ConvertPostmanCommandConfig),
+    /// Convert WireMock mappings into usable config
+    ConvertWiremock(crate::convert_wiremock::ConvertWiremockCommandConfig),
+    /// Export the loaded config as WireMock mappings
+    ExportWiremock(crate::convert_wiremock::ExportWiremockCommandConfig),
+    /// Export the loaded config as an OpenAPI document
+    ExportOpenapi(crate::export_openapi::ExportOpenApiCommandConfig),
+    /// Load every config file and report all the problems found
+    Validate(crate::validate::ValidateCommandConfig),
 }
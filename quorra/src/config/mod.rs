@@ -1,8 +1,14 @@
-use quorra_config::prelude::{ParsedUserConfig, ResponseConfig};
-use quorra_plugin::HttpPlugin;
-use quorra_plugin_http::HttpStaticPluginBuilder;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use quorra_config::prelude::{
+    ConfigError, ConfigLocation, FallbackResponseConfig, HttpServerConfig, ListenerConfig,
+    ResponseConfig,
+};
+use quorra_plugin::{FallbackResponse, HttpPlugin, HyperService};
+use quorra_plugin_http::{GrpcPluginBuilder, HttpStaticPluginBuilder, WebsocketPluginBuilder};
+use quorra_plugin_smtp::{SmtpPlugin, SmtpPluginBuilder};
+use quorra_plugin_tcp::{TcpPlugin, TcpPluginBuilder};
 
-use std::sync::Arc;
+use std::{fmt::Display, path::Path, sync::Arc};
 
 mod cli;
 mod logging;
@@ -10,17 +16,118 @@ mod logging;
 pub use cli::{Opts, SubCommands};
 pub use logging::*;
 
+/// Checks a payload the way its plugin builds it, returning every problem
+/// found along with the part of the payload it was found in.
+pub fn validate_payload(file: &Path, config: &ResponseConfig<String>) -> Vec<ConfigError> {
+    fn described<E: Display>(errors: Vec<(String, E)>) -> Vec<(String, String)> {
+        errors
+            .into_iter()
+            .map(|(part, e)| (part, e.to_string()))
+            .collect()
+    }
+
+    let problems = match config {
+        ResponseConfig::StaticHttp(http) => described(HttpStaticPluginBuilder::validate(http)),
+        ResponseConfig::Websocket(websocket) => {
+            described(WebsocketPluginBuilder::validate(websocket))
+        }
+        ResponseConfig::Grpc(grpc) => described(GrpcPluginBuilder::validate(grpc)),
+        ResponseConfig::Tcp(tcp) => described(TcpPluginBuilder::validate(tcp)),
+        ResponseConfig::Smtp(smtp) => described(SmtpPluginBuilder::validate(smtp)),
+    };
+    problems
+        .into_iter()
+        .map(|(part, message)| ConfigError::Invalid {
+            location: Box::new(ConfigLocation {
+                key_path: Some(part),
+                ..ConfigLocation::new(file)
+            }),
+            message: format!("payload {}: {}", config.id(), message),
+        })
+        .collect()
+}
+
 pub async fn build_backends(
-    container: &ParsedUserConfig,
+    responses: &[ResponseConfig<String>],
 ) -> Result<Vec<Arc<Box<dyn HttpPlugin>>>, anyhow::Error> {
     let mut http_static_builder = HttpStaticPluginBuilder::new();
-    for response_config in &container.responses {
+    let mut websocket_builder = WebsocketPluginBuilder::new();
+    let mut grpc_builder = GrpcPluginBuilder::new();
+    for response_config in responses {
         match response_config {
             ResponseConfig::StaticHttp(http) => http_static_builder.load_config(http),
+            ResponseConfig::Websocket(websocket) => websocket_builder.load_config(websocket),
+            ResponseConfig::Grpc(grpc) => grpc_builder.load_config(grpc),
+            // Only answered by their own listeners, `validate` warns about them.
+            ResponseConfig::Tcp(_) | ResponseConfig::Smtp(_) => {}
+        }
+    }
+
+    Ok(vec![
+        Arc::new(Box::new(http_static_builder.build()?)),
+        Arc::new(Box::new(websocket_builder.build()?)),
+        Arc::new(Box::new(grpc_builder.build()?)),
+    ])
+}
+
+/// Builds the service answering the requests of a listener, with the settings
+/// of the `[http]` section that can change on reload.
+pub async fn build_service(
+    http_config: &HttpServerConfig,
+    responses: &[ResponseConfig<String>],
+) -> Result<HyperService, anyhow::Error> {
+    let http_plugins = build_backends(responses).await?;
+
+    let mut service =
+        HyperService::new(http_plugins).with_fallback(build_fallback(&http_config.fallback)?);
+    if let Some(max_body_size) = http_config.max_body_size {
+        service = service.with_max_body_size(max_body_size);
+    }
+    Ok(service)
+}
+
+/// Builds the plugin answering the connections of a `tcp` listener. Only the
+/// `tcp` payloads are used.
+pub fn build_tcp_plugin(
+    listener: &ListenerConfig,
+    responses: &[ResponseConfig<String>],
+) -> Result<TcpPlugin, anyhow::Error> {
+    let mut builder =
+        TcpPluginBuilder::new().with_framing(listener.framing.clone().unwrap_or_default());
+    for response_config in responses {
+        if let ResponseConfig::Tcp(tcp) = response_config {
+            builder.load_config(tcp);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Builds the plugin injecting failures into the sessions of an `smtp`
+/// listener. Only the `smtp` payloads are used.
+pub fn build_smtp_plugin(
+    responses: &[ResponseConfig<String>],
+) -> Result<SmtpPlugin, anyhow::Error> {
+    let mut builder = SmtpPluginBuilder::new();
+    for response_config in responses {
+        if let ResponseConfig::Smtp(smtp) = response_config {
+            builder.load_config(smtp);
         }
     }
+    Ok(builder.build()?)
+}
 
-    let boxed = Box::new(http_static_builder.build()?);
+fn build_fallback(config: &FallbackResponseConfig) -> Result<FallbackResponse, anyhow::Error> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &config.headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
 
-    Ok(vec![Arc::new(boxed)])
+    Ok(FallbackResponse {
+        status: StatusCode::from_u16(config.status)?,
+        headers,
+        body: config.body.clone().into(),
+    })
 }
@@ -1,23 +1,174 @@
 use clap::Parser;
-use quorra_har::HarConvertor;
-use std::path::PathBuf;
+use quorra_har::{HarConvertOptions, HarConvertor, ScrubRule, Scrubber};
+use regex::Regex;
+use std::{ops::RangeInclusive, path::PathBuf};
+use tracing::{info, warn};
+
+/// Where the generated scrub salt is kept, in the destination directory.
+const SALT_FILE: &str = ".quorra-scrub-salt";
 
 #[derive(Parser, Debug)]
 pub struct ConvertHarCommandConfig {
-    #[clap(long("har"))]
-    /// Input HAR file
-    pub har_file: PathBuf,
+    #[clap(long("har"), required = true)]
+    /// Input HAR file, `-` reads from stdin. Can be used multiple times
+    pub har_files: Vec<PathBuf>,
 
     #[clap(long("destination"))]
     /// Where the converted files should be created
     pub dest_directory: PathBuf,
+
+    #[clap(long("match-body"))]
+    /// Match requests on the recorded body. GraphQL requests match on the operation name and variables
+    pub match_body: bool,
+
+    #[clap(long("match-header"))]
+    /// Request header to match on when it was recorded. Can be used multiple times
+    pub match_headers: Vec<String>,
+
+    #[clap(long("generalize-paths"))]
+    /// Replace numeric, UUID and hash path segments with a regex, grouping the recorded variants
+    pub generalize_paths: bool,
+
+    #[clap(long("host"))]
+    /// Only convert requests to this host, `*.example.com` matches subdomains. Can be used multiple times
+    pub include_hosts: Vec<String>,
+
+    #[clap(long("url-filter"))]
+    /// Only convert requests whose full URL matches this regex
+    pub url_filter: Option<Regex>,
+
+    #[clap(long("mime-type"))]
+    /// Only convert responses whose MIME type starts with this value. Can be used multiple times
+    pub mime_types: Vec<String>,
+
+    #[clap(long("status"), value_parser = parse_status_range)]
+    /// Only convert responses with a status in this range, e.g. `200-299` or `200`
+    pub status_range: Option<RangeInclusive<u16>>,
+
+    #[clap(long("split-by-host"))]
+    /// Write every host into its own directory with a `Host` matcher, and generate a root config.toml
+    pub split_by_host: bool,
+
+    #[clap(long("keep-header"))]
+    /// Response header to keep besides Content-Type and Access-Control-Allow-Origin, `*` keeps all. Can be used multiple times
+    pub keep_headers: Vec<String>,
+
+    #[clap(long("drop-header"))]
+    /// Response header to always drop. Can be used multiple times
+    pub drop_headers: Vec<String>,
+
+    #[clap(long("keep-cookies"))]
+    /// Convert recorded response cookies into Set-Cookie headers
+    pub keep_cookies: bool,
+
+    #[clap(long("keep-timing"))]
+    /// Delay responses by the recorded server wait time
+    pub keep_timing: bool,
+
+    #[clap(long("scrub"))]
+    /// Replace JWTs, emails and credit card numbers with consistent fake values
+    pub scrub: bool,
+
+    #[clap(long("scrub-regex"))]
+    /// Replace matches of this regex, or only its first capture group. Can be used multiple times
+    pub scrub_regexes: Vec<Regex>,
+
+    #[clap(long("scrub-json-path"))]
+    /// Replace values selected by this JSONPath in JSON bodies. Can be used multiple times
+    pub scrub_json_paths: Vec<String>,
+
+    #[clap(long("scrub-salt"), env = "QUORRA_SCRUB_SALT")]
+    /// Secret mixed into the fake values, so they can't be reversed by guessing. When not set,
+    /// a random salt is generated and saved to `.quorra-scrub-salt` in the destination, and
+    /// reused by the next runs into it
+    pub scrub_salt: Option<String>,
+
+    #[clap(long("merge"))]
+    /// Update the payloads already in the destination instead of adding new files next to them
+    pub merge: bool,
 }
 
 impl ConvertHarCommandConfig {
     pub async fn run_convert(&self) -> Result<(), anyhow::Error> {
         std::fs::create_dir_all(&self.dest_directory)?;
-        let convertor = HarConvertor::new(&self.har_file);
-        convertor.convert(&self.dest_directory).await?;
+        let options = HarConvertOptions {
+            match_body: self.match_body,
+            match_headers: self.match_headers.clone(),
+            generalize_paths: self.generalize_paths,
+            include_hosts: self.include_hosts.clone(),
+            url_filter: self.url_filter.clone(),
+            mime_types: self.mime_types.clone(),
+            status_range: self.status_range.clone(),
+            split_by_host: self.split_by_host,
+            keep_headers: self.keep_headers.clone(),
+            drop_headers: self.drop_headers.clone(),
+            keep_cookies: self.keep_cookies,
+            keep_timing: self.keep_timing,
+            scrubber: self.make_scrubber()?,
+            merge: self.merge,
+        };
+        let convertor = HarConvertor::new(&self.har_files, options);
+        let report = convertor.convert(&self.dest_directory).await?;
+        if self.merge {
+            if report.is_empty() {
+                info!("Nothing changed in {}", self.dest_directory.display());
+            }
+            for change in &report.changes {
+                info!("{}", change);
+            }
+        }
         Ok(())
     }
+
+    fn make_scrubber(&self) -> Result<Scrubber, anyhow::Error> {
+        let mut rules = Vec::new();
+        if self.scrub {
+            rules.append(&mut Scrubber::builtin_rules());
+        }
+        for regex in &self.scrub_regexes {
+            rules.push(ScrubRule::Regex(regex.clone()));
+        }
+        for path in &self.scrub_json_paths {
+            rules.push(ScrubRule::JsonPath(path.clone()));
+        }
+        let salt = match &self.scrub_salt {
+            Some(salt) => {
+                if salt.is_empty() && !rules.is_empty() {
+                    warn!("The scrub salt is empty, fake values can be matched to guessed real values");
+                }
+                salt.clone()
+            }
+            None if rules.is_empty() => String::new(),
+            None => self.saved_salt()?,
+        };
+        Ok(Scrubber::new(rules, &salt))
+    }
+
+    /// The salt generated by an earlier run into the same destination, so that
+    /// re-recording gives the same fake values. Made up and saved the first time.
+    fn saved_salt(&self) -> Result<String, anyhow::Error> {
+        let path = self.dest_directory.join(SALT_FILE);
+        if let Ok(salt) = std::fs::read_to_string(&path) {
+            if !salt.trim().is_empty() {
+                return Ok(salt.trim().to_string());
+            }
+        }
+        let salt = crate::unique_id();
+        info!(
+            "Saving the generated scrub salt to {}, keep it out of version control",
+            path.display()
+        );
+        std::fs::write(&path, &salt)?;
+        Ok(salt)
+    }
+}
+
+fn parse_status_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = value.split_once('-').unwrap_or((value, value));
+    let start: u16 = start.trim().parse().map_err(|e| format!("{}", e))?;
+    let end: u16 = end.trim().parse().map_err(|e| format!("{}", e))?;
+    if start > end {
+        return Err(format!("{} is larger than {}", start, end));
+    }
+    Ok(start..=end)
 }
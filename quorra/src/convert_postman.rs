@@ -0,0 +1,23 @@
+use clap::Parser;
+use quorra_har::PostmanConvertor;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ConvertPostmanCommandConfig {
+    #[clap(long("collection"))]
+    /// Input Postman collection (v2.1) file
+    pub collection_file: PathBuf,
+
+    #[clap(long("destination"))]
+    /// Where the converted files should be created
+    pub dest_directory: PathBuf,
+}
+
+impl ConvertPostmanCommandConfig {
+    pub async fn run_convert(&self) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(&self.dest_directory)?;
+        let convertor = PostmanConvertor::new(&self.collection_file);
+        convertor.convert(&self.dest_directory).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use crate::report::log_report;
+use clap::Parser;
+use quorra_har::{WiremockConvertor, WiremockExporter};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ConvertWiremockCommandConfig {
+    #[clap(long("mappings"))]
+    /// Directory containing WireMock mapping files
+    pub mappings_directory: PathBuf,
+
+    #[clap(long("files"))]
+    /// Directory `bodyFileName` is resolved against. Defaults to `__files` next to the mappings
+    pub files_directory: Option<PathBuf>,
+
+    #[clap(long("destination"))]
+    /// Where the converted files should be created
+    pub dest_directory: PathBuf,
+}
+
+impl ConvertWiremockCommandConfig {
+    pub async fn run_convert(&self) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(&self.dest_directory)?;
+        let convertor =
+            WiremockConvertor::new(&self.mappings_directory, self.files_directory.as_deref());
+        let report = convertor.convert(&self.dest_directory).await?;
+        log_report(&report);
+        Ok(())
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportWiremockCommandConfig {
+    #[clap(long = "config", short, env = "CONFIG_PATH")]
+    /// Location of root config file
+    pub config_file: PathBuf,
+
+    #[clap(long("destination"))]
+    /// Where the WireMock `mappings` directory should be created
+    pub dest_directory: PathBuf,
+}
+
+impl ExportWiremockCommandConfig {
+    pub async fn run_export(&self) -> Result<(), anyhow::Error> {
+        let exporter = WiremockExporter::new(&self.config_file);
+        let report = exporter.export(&self.dest_directory).await?;
+        log_report(&report);
+        Ok(())
+    }
+}
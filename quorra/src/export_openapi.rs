@@ -0,0 +1,35 @@
+use crate::report::log_report;
+use clap::Parser;
+use quorra_har::OpenApiExporter;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ExportOpenApiCommandConfig {
+    #[clap(long = "config", short, env = "CONFIG_PATH")]
+    /// Location of root config file
+    pub config_file: PathBuf,
+
+    #[clap(long("output"))]
+    /// File to write, YAML when it ends in `.yaml` or `.yml`. Prints JSON to stdout when not set
+    pub output: Option<PathBuf>,
+
+    #[clap(long("title"), default_value = "Quorra mocks")]
+    /// Title of the generated document
+    pub title: String,
+}
+
+impl ExportOpenApiCommandConfig {
+    pub async fn run_export(&self) -> Result<(), anyhow::Error> {
+        let exporter = OpenApiExporter::new(&self.config_file, &self.title);
+        let report = match &self.output {
+            Some(output) => exporter.export(output).await?,
+            None => {
+                let (document, report) = exporter.document()?;
+                println!("{}", serde_json::to_string_pretty(&document)?);
+                report
+            }
+        };
+        log_report(&report);
+        Ok(())
+    }
+}
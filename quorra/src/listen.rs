@@ -0,0 +1,344 @@
+use anyhow::{anyhow, bail};
+use futures::{
+    future::Future,
+    ready,
+    stream::{poll_fn, Stream},
+};
+use std::{
+    fmt::Display,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::{sleep, Sleep},
+};
+use tracing::{debug, warn};
+
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    io::{FromRawFd, IntoRawFd, RawFd},
+};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// How long to wait before accepting again, when running out of file descriptors.
+pub const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// The first file descriptor passed by socket activation.
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Where a listener accepts connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    /// Like `127.0.0.1:3000`. Port 0 picks a free port.
+    Tcp(SocketAddr),
+    /// `unix:/path.sock`
+    Unix(PathBuf),
+    /// `fd:0` or `fd:name`, a socket passed with `LISTEN_FDS`. Numbers count from
+    /// the first socket passed, names come from `LISTEN_FDNAMES`.
+    Inherited(String),
+}
+
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        if let Some(name) = address.strip_prefix("fd:") {
+            return Ok(Self::Inherited(name.to_string()));
+        }
+        address
+            .parse()
+            .map(Self::Tcp)
+            .map_err(|e| anyhow!("Invalid listen address `{}`: {}", address, e))
+    }
+}
+
+/// A socket that is already listening.
+pub enum BoundListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// A listener that hands out its connections when polled.
+pub trait PollAccept {
+    type Conn;
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Conn>>;
+}
+
+impl PollAccept for TcpListener {
+    type Conn = TcpStream;
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<TcpStream>> {
+        TcpListener::poll_accept(self, cx).map_ok(|(stream, _)| stream)
+    }
+}
+
+#[cfg(unix)]
+impl PollAccept for UnixListener {
+    type Conn = UnixStream;
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<UnixStream>> {
+        UnixListener::poll_accept(self, cx).map_ok(|(stream, _)| stream)
+    }
+}
+
+/// The connections of a listener. A failed accept, like when running out of
+/// file descriptors, is logged and retried after a while instead of ending the
+/// stream, the way hyper's `AddrIncoming` does.
+pub fn incoming<L: PollAccept>(listener: L) -> impl Stream<Item = io::Result<L::Conn>> {
+    let mut delay: Option<Pin<Box<Sleep>>> = None;
+    poll_fn(move |cx| loop {
+        if let Some(sleeping) = &mut delay {
+            ready!(sleeping.as_mut().poll(cx));
+            delay = None;
+        }
+        match ready!(listener.poll_accept(cx)) {
+            Ok(stream) => return Poll::Ready(Some(Ok(stream))),
+            Err(e) => {
+                warn!("Unable to accept a connection: {}", e);
+                delay = Some(Box::pin(sleep(ACCEPT_ERROR_DELAY)));
+            }
+        }
+    })
+}
+
+impl Display for BoundListener {
+    /// The address clients can connect to, with the port picked for port 0.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoundListener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "unknown"),
+            },
+            #[cfg(unix)]
+            BoundListener::Unix(_, path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The sockets passed by the service manager, each of which can be used once.
+#[derive(Default)]
+pub struct SocketActivation {
+    #[cfg(unix)]
+    fds: Vec<(Option<RawFd>, Option<String>)>,
+}
+
+impl SocketActivation {
+    /// Reads `LISTEN_FDS`, which only applies when `LISTEN_PID` is this process.
+    #[cfg(unix)]
+    pub fn from_env() -> Self {
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse::<RawFd>().ok())
+            .filter(|_| for_us)
+            .unwrap_or_default();
+        let names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+            .map(|names| names.split(':').map(|x| x.to_string()).collect())
+            .unwrap_or_default();
+
+        debug!("Inherited {} sockets", count);
+        let fds = (0..count)
+            .map(|index| {
+                let name = names.get(index as usize).cloned();
+                (Some(LISTEN_FDS_START + index), name)
+            })
+            .collect();
+        Self { fds }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_env() -> Self {
+        Self::default()
+    }
+
+    /// Binds the address, or takes the inherited socket. Must be called from
+    /// within the runtime.
+    pub fn bind(&mut self, address: &ListenAddress) -> Result<BoundListener, anyhow::Error> {
+        match address {
+            ListenAddress::Tcp(addr) => Ok(BoundListener::Tcp(tcp_listener(
+                std::net::TcpListener::bind(addr)?,
+            )?)),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)
+                    .map_err(|e| anyhow!("Unable to bind {}: {}", path.display(), e))?;
+                Ok(BoundListener::Unix(listener, path.clone()))
+            }
+            #[cfg(unix)]
+            ListenAddress::Inherited(name) => self.take(name),
+            #[cfg(not(unix))]
+            other => bail!("{:?} is only supported on Unix", other),
+        }
+    }
+
+    #[cfg(unix)]
+    fn take(&mut self, name: &str) -> Result<BoundListener, anyhow::Error> {
+        let index = match name.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => self
+                .fds
+                .iter()
+                .position(|(_, x)| x.as_deref() == Some(name))
+                .ok_or_else(|| anyhow!("No socket named `{}` in LISTEN_FDNAMES", name))?,
+        };
+        let fd = match self.fds.get_mut(index) {
+            Some((fd, _)) => fd
+                .take()
+                .ok_or_else(|| anyhow!("Socket `fd:{}` is used more than once", name))?,
+            None => bail!(
+                "Socket `fd:{}` was not passed, LISTEN_FDS has {} sockets",
+                name,
+                self.fds.len()
+            ),
+        };
+
+        // A datagram socket would take every datagram as a connection.
+        let socket_type =
+            socket_type(fd).map_err(|e| anyhow!("Socket `fd:{}` can't be used: {}", name, e))?;
+        if socket_type != libc::SOCK_STREAM {
+            bail!("Socket `fd:{}` is not a stream socket", name);
+        }
+
+        // The socket can be either kind, only a TCP socket has an IP address.
+        // Safety: the service manager hands the descriptor over to this process,
+        // and it is only taken once.
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        if tcp.local_addr().is_ok() {
+            return Ok(BoundListener::Tcp(tcp_listener(tcp)?));
+        }
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+        let path = unix
+            .local_addr()?
+            .as_pathname()
+            .map(|path| path.to_path_buf())
+            .unwrap_or_default();
+        unix.set_nonblocking(true)?;
+        Ok(BoundListener::Unix(UnixListener::from_std(unix)?, path))
+    }
+}
+
+fn tcp_listener(listener: std::net::TcpListener) -> Result<TcpListener, anyhow::Error> {
+    listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(listener)?)
+}
+
+/// The `SO_TYPE` of the socket, like `SOCK_STREAM`.
+#[cfg(unix)]
+fn socket_type(fd: RawFd) -> io::Result<libc::c_int> {
+    let mut socket_type: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // Safety: the option is an int, written to one of the size given.
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut socket_type as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket_type)
+}
+
+/// A socket left behind by an earlier run would fail the bind, it's removed
+/// when nothing accepts connections on it anymore. Anything that isn't a
+/// socket is left alone.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> Result<(), anyhow::Error> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        _ => return Ok(()),
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => bail!(
+            "Unable to bind {}: address in use, another process is listening on it",
+            path.display()
+        ),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            debug!("Removing stale socket {}", path.display());
+            std::fs::remove_file(path)?;
+            Ok(())
+        }
+        Err(e) => bail!("Unable to bind {}: {}", path.display(), e),
+    }
+}
+
+/// Writes every bound address, so that a harness binding port 0 can find them.
+/// The file is written in one go, so it's never read half written.
+pub fn write_address_file(
+    path: &std::path::Path,
+    addresses: &[(Option<String>, String)],
+) -> Result<(), anyhow::Error> {
+    let mut contents = String::new();
+    for (listener, address) in addresses {
+        let listener = listener.as_deref().unwrap_or("default");
+        contents.push_str(&format!("{} {}\n", listener, address));
+    }
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+#[test]
+fn test_parse_listen_address() {
+    assert_eq!(
+        ListenAddress::Tcp("127.0.0.1:0".parse().unwrap()),
+        "127.0.0.1:0".parse().unwrap()
+    );
+    assert_eq!(
+        ListenAddress::Unix(PathBuf::from("/tmp/quorra.sock")),
+        "unix:/tmp/quorra.sock".parse().unwrap()
+    );
+    assert_eq!(
+        ListenAddress::Inherited("web".to_string()),
+        "fd:web".parse().unwrap()
+    );
+    assert!("localhost".parse::<ListenAddress>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remove_stale_socket_keeps_live_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("quorra.sock");
+    let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    assert!(remove_stale_socket(&path).is_err());
+    assert!(path.exists());
+
+    drop(listener);
+    remove_stale_socket(&path).unwrap();
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_inherited_socket_must_be_stream() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut activation = SocketActivation {
+        fds: vec![(Some(socket.into_raw_fd()), None)],
+    };
+    let error = activation.take("0").err().unwrap();
+    assert!(error.to_string().contains("not a stream socket"));
+}
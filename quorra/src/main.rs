@@ -1,16 +1,21 @@
 use clap::Parser;
-use quorra_config::ConfigContainer;
-use std::{path::Path, process::ExitCode, time::Duration};
-use tokio::sync::RwLock;
-use tracing::{debug, error, warn};
+use quorra_config::prelude::ConfigError;
+use std::process::ExitCode;
+use tracing::error;
 
 mod config;
 mod convert_har;
+mod convert_postman;
+mod convert_wiremock;
+mod export_openapi;
+mod listen;
+mod reload;
+mod report;
 mod server;
+mod tls;
+mod validate;
 
 use config::Opts;
-use quorra_plugin::HyperService;
-use std::sync::Arc;
 
 pub fn unique_id() -> String {
     uuid::Uuid::new_v4().to_string()
@@ -27,11 +32,19 @@ async fn main() -> ExitCode {
     let result = match opt.command {
         config::SubCommands::Server(server_opts) => server_opts.run_server().await,
         config::SubCommands::ConvertHar(options) => options.run_convert().await,
+        config::SubCommands::ConvertPostman(options) => options.run_convert().await,
+        config::SubCommands::ConvertWiremock(options) => options.run_convert().await,
+        config::SubCommands::ExportWiremock(options) => options.run_export().await,
+        config::SubCommands::ExportOpenapi(options) => options.run_export().await,
+        config::SubCommands::Validate(options) => options.run_validate().await,
     };
 
     let exit_code = match result {
         Err(e) => {
             error!("Exiting because of {}", e);
+            if let Some(snippet) = e.downcast_ref::<ConfigError>().and_then(|e| e.snippet()) {
+                eprint!("{}", snippet);
+            }
             1
         }
         Ok(_) => 0,
@@ -39,36 +52,3 @@ async fn main() -> ExitCode {
 
     ExitCode::from(exit_code)
 }
-
-fn watch_for_changes(root_config: &Path, shared_config: Arc<RwLock<HyperService>>) {
-    let root_config = root_config.to_path_buf();
-    let config_container = ConfigContainer::new(&root_config);
-    tokio::spawn(async move {
-        loop {
-            tokio::time::sleep(Duration::from_secs(5)).await;
-
-            debug!("Reloading configuration");
-
-            let loaded_config = match config_container.load_config() {
-                Ok(loaded_config) => loaded_config,
-                Err(e) => {
-                    warn!("Unable to load config: {:?}", e);
-                    continue;
-                }
-            };
-
-            let http_plugins = match crate::config::build_backends(&loaded_config).await {
-                Ok(loaded_config) => loaded_config,
-                Err(e) => {
-                    warn!("Unable to convert config: {:?}", e);
-                    continue;
-                }
-            };
-            debug!("Found {} http plugins", http_plugins.len());
-            let service = HyperService::new(http_plugins);
-            {
-                *shared_config.write().await = service;
-            }
-        }
-    });
-}
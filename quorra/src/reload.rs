@@ -0,0 +1,751 @@
+use crate::config::{build_service, build_smtp_plugin, build_tcp_plugin, validate_payload};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use quorra_config::prelude::*;
+use quorra_plugin::HyperService;
+use quorra_plugin_smtp::SmtpPlugin;
+use quorra_plugin_tcp::TcpPlugin;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tracing::{debug, info, warn};
+
+/// How long the files have to stay quiet before a reload, so that a burst of
+/// edits only reloads once.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+/// Used when the platform can't notify about file changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Loads the config one response file at a time, remembering the last good
+/// version of every file. When a file breaks, the last good version is served
+/// in its place so that the other files can still be reloaded.
+pub struct ConfigReloader {
+    container: ConfigContainer,
+    root_fingerprint: Option<u64>,
+    last_good: BTreeMap<PathBuf, LoadedFile>,
+    /// The error last reported for every broken file, to only log changes.
+    broken: BTreeMap<PathBuf, String>,
+    /// The files in the last config, in order, with their listener and host,
+    /// and the fingerprint of the version served.
+    served: Vec<(ListedFile, u64)>,
+    /// The listeners whose service failed to build on the last reload, rebuilt
+    /// on the next one even when nothing changed.
+    unapplied: BTreeSet<Option<String>>,
+}
+
+struct LoadedFile {
+    /// Hash of every source of the file, when it was loaded.
+    fingerprint: u64,
+    sources: Vec<PathBuf>,
+    config: ResponseConfig<String>,
+}
+
+pub struct ReloadedConfig {
+    pub config: ParsedUserConfig,
+    /// The listeners, `None` being the default one, that serve a file that
+    /// changed since the last reload, or that gained or lost a file. Every
+    /// listener when the root config changed. The others have nothing to rebuild.
+    /// Also the listeners that failed to build on the last reload.
+    pub changed: BTreeSet<Option<String>>,
+    /// Files that could not be loaded, whether or not an older version was kept.
+    pub errors: Vec<ConfigError>,
+}
+
+impl ConfigReloader {
+    pub fn new(root_config: &Path) -> Self {
+        Self {
+            container: ConfigContainer::new(root_config),
+            root_fingerprint: None,
+            last_good: Default::default(),
+            broken: Default::default(),
+            served: Default::default(),
+            unapplied: Default::default(),
+        }
+    }
+
+    /// Fails only when the root config cannot be used, otherwise every broken
+    /// file is replaced by its last good version, or left out when there is none.
+    /// Files are only parsed again when their content changed, or when they are
+    /// broken.
+    pub fn reload(&mut self) -> Result<ReloadedConfig, ConfigError> {
+        // Taken before the root config is read, a write landing in between
+        // makes the next reload read it again.
+        let root_fingerprint = fingerprint(&[self.container.root_config_file().to_path_buf()]);
+        let (mut config, files) = match self.container.list_response_files() {
+            Ok(found) => found,
+            Err(e) => {
+                warn!(
+                    monotonic_counter.config_reload_failures = 1_u64,
+                    "Unable to load config: {}", e
+                );
+                return Err(e);
+            }
+        };
+
+        let mut last_good = BTreeMap::new();
+        let mut broken = BTreeMap::new();
+        let mut errors = Vec::new();
+        // A file can be used by more than one listener, it's only loaded once.
+        for ListedFile { path: file, .. } in &files {
+            if last_good.contains_key(file) || broken.contains_key(file) {
+                continue;
+            }
+
+            let (loaded, error) = self.reload_file(file);
+            if let Some(loaded) = loaded {
+                last_good.insert(file.clone(), loaded);
+            }
+            if let Some(e) = error {
+                broken.insert(file.clone(), e.to_string());
+                errors.push(e);
+            }
+        }
+
+        let change = broken.len() as i64 - self.broken.len() as i64;
+        if change != 0 {
+            info!(
+                counter.config_broken_files = change,
+                "Broken config files: {}",
+                broken.len()
+            );
+        }
+
+        let served: Vec<(ListedFile, u64)> = files
+            .into_iter()
+            .filter_map(|file| {
+                let fingerprint = last_good.get(&file.path)?.fingerprint;
+                Some((file, fingerprint))
+            })
+            .collect();
+        let mut changed = std::mem::take(&mut self.unapplied);
+        if self.root_fingerprint != Some(root_fingerprint) {
+            changed.insert(None);
+            changed.extend(config.root.listeners.keys().cloned().map(Some));
+        }
+        let listeners: BTreeSet<&Option<String>> = served
+            .iter()
+            .chain(&self.served)
+            .map(|(file, _)| &file.listener)
+            .collect();
+        for listener in listeners {
+            let files_of = |served: &[(ListedFile, u64)]| -> Vec<(ListedFile, u64)> {
+                served
+                    .iter()
+                    .filter(|(file, _)| &file.listener == listener)
+                    .cloned()
+                    .collect()
+            };
+            if files_of(&served) != files_of(&self.served) {
+                changed.insert(listener.clone());
+            }
+        }
+        for (file, _) in &served {
+            let response = last_good[&file.path].config.clone();
+            config.push_response(file, response);
+        }
+
+        self.root_fingerprint = Some(root_fingerprint);
+        self.last_good = last_good;
+        self.broken = broken;
+        self.served = served;
+        Ok(ReloadedConfig {
+            config,
+            changed,
+            errors,
+        })
+    }
+
+    /// Has the next reload rebuild the listeners, when their new config could
+    /// not be applied.
+    fn retry_later(&mut self, listeners: BTreeSet<Option<String>>) {
+        self.unapplied.extend(listeners);
+    }
+
+    /// The directories of every file the config was loaded from, including the
+    /// broken ones.
+    fn source_dirs(&self) -> BTreeSet<PathBuf> {
+        self.last_good
+            .values()
+            .flat_map(|loaded| &loaded.sources)
+            .chain(self.broken.keys())
+            .filter_map(|source| source.parent())
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    /// Returns the version of the file to serve, if there is one, and the error
+    /// when the file is broken.
+    fn reload_file(&mut self, file: &Path) -> (Option<LoadedFile>, Option<ConfigError>) {
+        if let Some(previous) = self.last_good.remove(file) {
+            if !self.broken.contains_key(file)
+                && fingerprint(&previous.sources) == previous.fingerprint
+            {
+                return (Some(previous), None);
+            }
+            self.last_good.insert(file.to_path_buf(), previous);
+        }
+
+        debug!("Loading changed config file {:?}", file);
+        let loaded = self
+            .container
+            .load_response_file(file)
+            .and_then(|loaded| check_plugin(file, loaded));
+        match loaded {
+            Ok(loaded) => {
+                if self.broken.contains_key(file) {
+                    info!(file = %file.display(), "Config file was fixed and has been reloaded");
+                }
+                let loaded = LoadedFile {
+                    fingerprint: loaded.fingerprint,
+                    sources: loaded.sources,
+                    config: loaded.config,
+                };
+                (Some(loaded), None)
+            }
+            Err(e) => {
+                let kept = self.last_good.remove(file);
+                if self.broken.get(file) != Some(&e.to_string()) {
+                    match kept {
+                        Some(_) => {
+                            warn!(file = %file.display(), "Keeping the last good version of the file: {}", e)
+                        }
+                        None => {
+                            warn!(file = %file.display(), "Skipping the file, it has never loaded: {}", e)
+                        }
+                    }
+                }
+                (kept, Some(e))
+            }
+        }
+    }
+}
+
+/// A file that parses can still be refused by its plugin, which would fail the
+/// whole build, so it is checked on its own first.
+fn check_plugin(file: &Path, loaded: ResponseFile) -> Result<ResponseFile, ConfigError> {
+    match validate_payload(file, &loaded.config).into_iter().next() {
+        None => Ok(loaded),
+        Some(e) => Err(e),
+    }
+}
+
+enum ReloadTrigger {
+    FilesChanged,
+    Signal,
+    Request(oneshot::Sender<ReloadStatus>),
+}
+
+/// The outcome of a reload, returned to whoever asked for it.
+#[derive(Serialize, Debug, Default)]
+pub struct ReloadStatus {
+    /// True when the server is now using a new config.
+    pub changed: bool,
+    pub errors: Vec<String>,
+}
+
+/// Asks the watcher to reload right away.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    sender: mpsc::UnboundedSender<ReloadTrigger>,
+}
+
+impl ReloadHandle {
+    /// Returns once the reload is done, and the new config is in use. `None`
+    /// when the watcher has stopped.
+    pub async fn reload(&self) -> Option<ReloadStatus> {
+        let (reply, status) = oneshot::channel();
+        self.sender.send(ReloadTrigger::Request(reply)).ok()?;
+        status.await.ok()
+    }
+}
+
+/// What a listener answers with, replaced on reload.
+#[derive(Clone)]
+pub enum ListenerService {
+    Http(Arc<RwLock<HyperService>>),
+    Tcp(Arc<RwLock<TcpPlugin>>),
+    Smtp(Arc<RwLock<SmtpPlugin>>),
+}
+
+/// The service of every listener, `None` being the default listener.
+pub type ListenerServices = BTreeMap<Option<String>, ListenerService>;
+
+/// Reloads the config when a file under the root config's directory or next to
+/// a loaded file changes, on `SIGHUP`, or when asked through the returned handle.
+pub fn watch_for_changes(mut reloader: ConfigReloader, services: ListenerServices) -> ReloadHandle {
+    let (sender, mut triggers) = mpsc::unbounded_channel();
+    let watcher = watch_files(&reloader, sender.clone());
+    watch_signals(sender.clone());
+
+    tokio::spawn(async move {
+        // Dropping the watcher stops the notifications.
+        let mut watcher = watcher;
+        if let Some(watcher) = &mut watcher {
+            watcher.watch_sources(&reloader);
+        }
+        while let Some(trigger) = triggers.recv().await {
+            let mut replies = Vec::new();
+            match trigger {
+                ReloadTrigger::Request(reply) => replies.push(reply),
+                ReloadTrigger::Signal => info!("Reloading configuration after SIGHUP"),
+                ReloadTrigger::FilesChanged => loop {
+                    match tokio::time::timeout(DEBOUNCE, triggers.recv()).await {
+                        Ok(Some(ReloadTrigger::Request(reply))) => {
+                            replies.push(reply);
+                            break;
+                        }
+                        Ok(Some(_)) => continue,
+                        Ok(None) | Err(_) => break,
+                    }
+                },
+            }
+
+            let status = apply_reload(&mut reloader, &services).await;
+            if let Some(watcher) = &mut watcher {
+                watcher.watch_sources(&reloader);
+            }
+            for reply in replies {
+                let _ = reply.send(ReloadStatus {
+                    changed: status.changed,
+                    errors: status.errors.clone(),
+                });
+            }
+        }
+    });
+
+    ReloadHandle { sender }
+}
+
+async fn apply_reload(reloader: &mut ConfigReloader, services: &ListenerServices) -> ReloadStatus {
+    debug!("Reloading configuration");
+
+    // Broken files are logged by the reloader, which keeps their last good version.
+    let reloaded = match reloader.reload() {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            return ReloadStatus {
+                changed: false,
+                errors: vec![e.to_string()],
+            }
+        }
+    };
+
+    let mut status = ReloadStatus {
+        changed: false,
+        errors: reloaded.errors.iter().map(|e| e.to_string()).collect(),
+    };
+    if reloaded.changed.is_empty() {
+        debug!("No config files changed");
+        return status;
+    }
+
+    if let Err(e) = apply_config(&reloaded, services).await {
+        warn!("Unable to convert config: {:?}", e);
+        status.errors.push(e.to_string());
+        // Nothing was replaced, so every changed listener is still outdated.
+        reloader.retry_later(reloaded.changed);
+        return status;
+    }
+    status.changed = true;
+    status
+}
+
+/// Replaces the service of every changed listener, or none of them when one
+/// fails to build.
+async fn apply_config(
+    reloaded: &ReloadedConfig,
+    services: &ListenerServices,
+) -> Result<(), anyhow::Error> {
+    let config = &reloaded.config;
+    for name in config.listeners.keys() {
+        if !services.contains_key(&Some(name.clone())) {
+            warn!("Listener {} is new, restart the server to start it", name);
+        }
+    }
+
+    // Every service is built before any is replaced, so that the listeners
+    // never serve different versions of the config. Listeners whose files are
+    // all unchanged keep their service.
+    let mut built = Vec::new();
+    let mut built_tcp = Vec::new();
+    let mut built_smtp = Vec::new();
+    for (listener, shared_service) in services {
+        if !reloaded.changed.contains(listener) {
+            continue;
+        }
+        let responses = match listener {
+            None => &config.responses,
+            Some(name) => match config.listeners.get(name) {
+                Some(responses) => responses,
+                None => {
+                    warn!(
+                        "Listener {} was removed, it keeps its last config until a restart",
+                        name
+                    );
+                    continue;
+                }
+            },
+        };
+        let listener_config = listener
+            .as_ref()
+            .and_then(|name| config.root.listeners.get(name));
+        let protocol = listener_config
+            .map(|listener| listener.protocol)
+            .unwrap_or_default();
+
+        let result = match (shared_service, listener_config) {
+            (ListenerService::Http(shared_service), _) if protocol == ListenerProtocol::Http => {
+                build_service(&config.root.http, responses)
+                    .await
+                    .map(|service| built.push((shared_service, service)))
+            }
+            (ListenerService::Tcp(shared_plugin), Some(listener_config))
+                if protocol == ListenerProtocol::Tcp =>
+            {
+                build_tcp_plugin(listener_config, responses)
+                    .map(|plugin| built_tcp.push((shared_plugin, plugin)))
+            }
+            (ListenerService::Smtp(shared_plugin), _) if protocol == ListenerProtocol::Smtp => {
+                build_smtp_plugin(responses).map(|plugin| built_smtp.push((shared_plugin, plugin)))
+            }
+            _ => {
+                warn!(
+                    "Listener {} changed its protocol, restart the server to use it",
+                    listener.as_deref().unwrap_or_default()
+                );
+                continue;
+            }
+        };
+        result?;
+    }
+
+    for (shared_service, service) in built {
+        *shared_service.write().await = service;
+    }
+    for (shared_plugin, plugin) in built_tcp {
+        *shared_plugin.write().await = plugin;
+    }
+    for (shared_plugin, plugin) in built_smtp {
+        *shared_plugin.write().await = plugin;
+    }
+    Ok(())
+}
+
+/// Notifies about changes under the root config's directory, and in the
+/// directories of the files loaded from outside of it, like with `../shared/*.yaml`.
+struct FileWatcher {
+    watcher: RecommendedWatcher,
+    root: PathBuf,
+    /// The directories outside of the root that are watched, or failed to be.
+    watched: BTreeSet<PathBuf>,
+}
+
+impl FileWatcher {
+    /// Starts watching the directories of new files outside of the root. New
+    /// files in other directories are only found on the next reload.
+    fn watch_sources(&mut self, reloader: &ConfigReloader) {
+        for dir in reloader.source_dirs() {
+            // Globs like `../shared/*.yaml` give paths under the root.
+            let dir = dir.canonicalize().unwrap_or(dir);
+            if dir.starts_with(&self.root) || !self.watched.insert(dir.clone()) {
+                continue;
+            }
+            match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(()) => debug!("Watching {} for changes", dir.display()),
+                Err(e) => warn!(
+                    "Unable to watch {} for changes, reload to pick them up: {}",
+                    dir.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+/// Watches the directory of the root config, which is where the response
+/// globs start. Falls back to polling when notifications are not available.
+fn watch_files(
+    reloader: &ConfigReloader,
+    sender: mpsc::UnboundedSender<ReloadTrigger>,
+) -> Option<FileWatcher> {
+    let root_config = reloader.container.root_config_file();
+    let config_dir = match root_config.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let event_sender = sender.clone();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            if !event.kind.is_access() {
+                let _ = event_sender.send(ReloadTrigger::FilesChanged);
+            }
+        }
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(&config_dir, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+
+    match watcher {
+        Ok(watcher) => Some(FileWatcher {
+            watcher,
+            // The files are found from the canonical directory.
+            root: config_dir.canonicalize().unwrap_or(config_dir),
+            watched: BTreeSet::new(),
+        }),
+        Err(e) => {
+            warn!(
+                "Unable to watch {} for changes, checking every {} seconds instead: {}",
+                config_dir.display(),
+                POLL_INTERVAL.as_secs(),
+                e
+            );
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    if sender.send(ReloadTrigger::FilesChanged).is_err() {
+                        break;
+                    }
+                }
+            });
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+fn watch_signals(sender: mpsc::UnboundedSender<ReloadTrigger>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Unable to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if sender.send(ReloadTrigger::Signal).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn watch_signals(_sender: mpsc::UnboundedSender<ReloadTrigger>) {}
+
+/// Makes an empty directory holding the root config, as `config.toml`. The
+/// directory is removed once dropped.
+#[cfg(test)]
+pub(crate) fn test_config_dir(root_config: &str) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.toml"), root_config).unwrap();
+    dir
+}
+
+/// Serves the default listener from the config in `dir`, reloaded through the
+/// returned handle.
+#[cfg(test)]
+async fn watch_test_config(dir: &Path) -> (Arc<RwLock<HyperService>>, ReloadHandle) {
+    let mut reloader = ConfigReloader::new(&dir.join("config.toml"));
+    let config = reloader.reload().unwrap().config;
+    let service = build_service(&config.root.http, &config.responses)
+        .await
+        .unwrap();
+    let service = Arc::new(RwLock::new(service));
+    let services = BTreeMap::from([(None, ListenerService::Http(service.clone()))]);
+    (service, watch_for_changes(reloader, services))
+}
+
+#[test]
+fn test_reload_keeps_last_good_file() {
+    let dir = test_config_dir("[responses]\npaths = [\"*.yaml\"]\n");
+    let payload = |id: &str, status: &str| {
+        format!(
+            "type: static-http\nid: {}\nmatches: []\nresponses:\n  - status: {}\n",
+            id, status
+        )
+    };
+    let ids = |reloaded: &ReloadedConfig| -> Vec<String> {
+        reloaded
+            .config
+            .responses
+            .iter()
+            .map(|response| response.id().to_string())
+            .collect()
+    };
+
+    std::fs::write(dir.path().join("a.yaml"), payload("a", "200")).unwrap();
+    std::fs::write(dir.path().join("b.yaml"), payload("b", "200")).unwrap();
+    let mut reloader = ConfigReloader::new(&dir.path().join("config.toml"));
+    let reloaded = reloader.reload().unwrap();
+    assert_eq!(vec!["a", "b"], ids(&reloaded));
+    assert_eq!(BTreeSet::from([None]), reloaded.changed);
+    assert!(reloaded.errors.is_empty());
+    assert!(reloader.reload().unwrap().changed.is_empty());
+
+    // `a` breaks while `b` changes, and a new broken file has nothing to keep.
+    std::fs::write(dir.path().join("a.yaml"), payload("a-broken", "abc")).unwrap();
+    std::fs::write(dir.path().join("b.yaml"), payload("b2", "200")).unwrap();
+    std::fs::write(dir.path().join("c.yaml"), payload("c", "abc")).unwrap();
+    let reloaded = reloader.reload().unwrap();
+    assert_eq!(vec!["a", "b2"], ids(&reloaded));
+    assert_eq!(2, reloaded.errors.len());
+    let reloaded = reloader.reload().unwrap();
+    assert!(reloaded.changed.is_empty());
+    assert_eq!(2, reloaded.errors.len());
+
+    std::fs::write(dir.path().join("a.yaml"), payload("a2", "200")).unwrap();
+    std::fs::remove_file(dir.path().join("c.yaml")).unwrap();
+    let reloaded = reloader.reload().unwrap();
+    assert_eq!(vec!["a2", "b2"], ids(&reloaded));
+    assert!(reloaded.errors.is_empty());
+}
+
+#[test]
+fn test_reload_splits_files_by_listener() {
+    let dir = test_config_dir("[listeners.payments]\naddress = \"127.0.0.1:4001\"\nresponses.paths = [\"payments/*.yaml\", \"shared.yaml\"]\n\n[listeners.users]\naddress = [\"127.0.0.1:4002\"]\nresponses.paths = [\"shared.yaml\"]\n",
+    );
+    std::fs::create_dir_all(dir.path().join("payments")).unwrap();
+    let payload = |id: &str| {
+        format!(
+            "type: static-http\nid: {}\nmatches: []\nresponses:\n  - status: 200\n",
+            id
+        )
+    };
+    std::fs::write(dir.path().join("payments/pay.yaml"), payload("pay")).unwrap();
+    std::fs::write(dir.path().join("shared.yaml"), payload("shared")).unwrap();
+
+    let mut reloader = ConfigReloader::new(&dir.path().join("config.toml"));
+    let reloaded = reloader.reload().unwrap();
+    let config = reloaded.config;
+    let ids = |responses: &[ResponseConfig<String>]| -> Vec<String> {
+        responses
+            .iter()
+            .map(|response| response.id().to_string())
+            .collect()
+    };
+
+    assert!(!config.root.has_default_listener());
+    assert!(config.responses.is_empty());
+    assert_eq!(vec!["pay", "shared"], ids(&config.listeners["payments"]));
+    assert_eq!(vec!["shared"], ids(&config.listeners["users"]));
+
+    // Only the listeners serving a changed file have to be rebuilt.
+    std::fs::write(dir.path().join("payments/pay.yaml"), payload("pay2")).unwrap();
+    let changed = reloader.reload().unwrap().changed;
+    assert_eq!(BTreeSet::from([Some("payments".to_string())]), changed);
+    std::fs::write(dir.path().join("shared.yaml"), payload("shared2")).unwrap();
+    let changed = reloader.reload().unwrap().changed;
+    assert_eq!(
+        BTreeSet::from([Some("payments".to_string()), Some("users".to_string())]),
+        changed
+    );
+}
+
+#[tokio::test]
+async fn test_reload_handle_applies_changes() {
+    use hyper::{Body, Request, StatusCode};
+
+    let dir = test_config_dir("[responses]\npaths = [\"*.yaml\"]\n");
+    let payload = |status: &str| {
+        format!(
+            "type: static-http\nmatches:\n  - path: /a\nresponses:\n  - status: {}\n",
+            status
+        )
+    };
+    std::fs::write(dir.path().join("a.yaml"), payload("201")).unwrap();
+
+    let (service, handle) = watch_test_config(dir.path()).await;
+    let status = || async {
+        let request = Request::get("/a").body(Body::empty()).unwrap();
+        let response = service.read().await.process_plugins(request).await;
+        response.unwrap().status()
+    };
+    assert_eq!(StatusCode::CREATED, status().await);
+
+    // The reload is done by the time the handle returns, whether or not the
+    // watcher noticed the change first.
+    std::fs::write(dir.path().join("a.yaml"), payload("202")).unwrap();
+    let reloaded = handle.reload().await.unwrap();
+    assert!(reloaded.changed && reloaded.errors.is_empty());
+    assert_eq!(StatusCode::ACCEPTED, status().await);
+
+    std::fs::write(dir.path().join("a.yaml"), payload("abc")).unwrap();
+    let reloaded = handle.reload().await.unwrap();
+    assert!(!reloaded.changed);
+    assert_eq!(1, reloaded.errors.len());
+    assert_eq!(StatusCode::ACCEPTED, status().await);
+}
+
+#[tokio::test]
+async fn test_reload_keeps_body_of_kept_response() {
+    use hyper::{body::to_bytes, Body, Request};
+
+    let dir = test_config_dir("[responses]\npaths = [\"*.yaml\"]\n");
+    let payload = |body: &str, header: &str| {
+        format!(
+            "type: static-http\nmatches:\n  - path: /a\nresponses:\n  - id: reused\n    status: 200\n    headers:\n      \"{}\": \"1\"\n    body:\n      type: raw\n      data: {}\n",
+            header, body
+        )
+    };
+    std::fs::write(dir.path().join("a.yaml"), payload("good", "x-good")).unwrap();
+
+    let (service, handle) = watch_test_config(dir.path()).await;
+    let body = || async {
+        let request = Request::get("/a").body(Body::empty()).unwrap();
+        let response = service.read().await.process_plugins(request).await;
+        to_bytes(response.unwrap().into_body()).await.unwrap()
+    };
+    assert_eq!("good", body().await);
+
+    // Checking the broken file doesn't replace the template of the response
+    // that is kept.
+    std::fs::write(dir.path().join("a.yaml"), payload("broken", "bad header")).unwrap();
+    let reloaded = handle.reload().await.unwrap();
+    assert!(!reloaded.changed);
+    assert_eq!(1, reloaded.errors.len());
+    assert_eq!("good", body().await);
+}
+
+#[tokio::test]
+async fn test_reload_retries_config_that_failed_to_build() {
+    use hyper::{Body, Request, StatusCode};
+
+    let root_config = |fallback: u16| {
+        format!(
+            "[http.fallback]\nstatus = {}\n\n[responses]\npaths = [\"*.yaml\"]\n",
+            fallback
+        )
+    };
+    let dir = test_config_dir(&root_config(404));
+    let (service, handle) = watch_test_config(dir.path()).await;
+    let status = || async {
+        let request = Request::get("/a").body(Body::empty()).unwrap();
+        let response = service.read().await.process_plugins(request).await;
+        response.unwrap().status()
+    };
+    assert_eq!(StatusCode::NOT_FOUND, status().await);
+
+    // The fallback only fails once the service is built, and is still reported
+    // when nothing changed since.
+    std::fs::write(dir.path().join("config.toml"), root_config(1000)).unwrap();
+    for _ in 0..2 {
+        let reloaded = handle.reload().await.unwrap();
+        assert!(!reloaded.changed);
+        assert_eq!(1, reloaded.errors.len());
+        assert_eq!(StatusCode::NOT_FOUND, status().await);
+    }
+
+    std::fs::write(dir.path().join("config.toml"), root_config(410)).unwrap();
+    let reloaded = handle.reload().await.unwrap();
+    assert!(reloaded.changed && reloaded.errors.is_empty());
+    assert_eq!(StatusCode::GONE, status().await);
+}
@@ -0,0 +1,18 @@
+use quorra_har::ConversionReport;
+use tracing::{info, warn};
+
+/// Logs what a conversion or export could not carry over.
+pub fn log_report(report: &ConversionReport) {
+    if report.is_empty() {
+        info!("Everything was converted");
+        return;
+    }
+
+    for entry in &report.entries {
+        warn!("{}", entry);
+    }
+    warn!(
+        "{} features could not be converted, review the generated files",
+        report.entries.len()
+    );
+}
@@ -1,90 +1,527 @@
+use anyhow::{anyhow, bail};
 use clap::Parser;
-use std::path::PathBuf;
+use futures::future::{try_join_all, BoxFuture, FutureExt, TryFutureExt};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tracing::{info, info_span, warn, Instrument};
 
+use crate::listen::{
+    incoming, write_address_file, BoundListener, ListenAddress, SocketActivation,
+    ACCEPT_ERROR_DELAY,
+};
+use crate::reload::{
+    watch_for_changes, ConfigReloader, ListenerService, ListenerServices, ReloadHandle,
+};
 use http::{
     header::{HeaderName, AUTHORIZATION},
-    HeaderValue, Request,
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode,
+};
+use hyper::{
+    server::{accept::from_stream, conn::AddrIncoming, Builder, Server},
+    service::service_fn,
+    Body,
 };
-use hyper::{server::Server, service::service_fn};
-use quorra_config::ConfigContainer;
+use quorra_config::prelude::{HttpServerConfig, ListenerProtocol, TlsConfig, QUORRA_HEADERS};
 use quorra_plugin::HyperService;
-use std::{iter::once, net::SocketAddr, sync::Arc};
+use quorra_plugin_smtp::{Mailbox, SmtpPlugin};
+use quorra_plugin_tcp::TcpPlugin;
+use std::{iter::once, sync::Arc};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsAcceptor;
 use tower::{make::Shared, ServiceBuilder};
 use tower_http::{
-    compression::CompressionLayer, propagate_header::PropagateHeaderLayer,
-    sensitive_headers::SetSensitiveRequestHeadersLayer, set_header::SetResponseHeaderLayer,
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
+    propagate_header::PropagateHeaderLayer,
+    sensitive_headers::SetSensitiveRequestHeadersLayer,
+    set_header::SetResponseHeaderLayer,
+    timeout::TimeoutLayer,
     trace::TraceLayer,
 };
 
+/// Reloads the config right away, so that tests can use a file they just wrote
+/// without waiting for the file watcher.
+const RELOAD_PATH: &str = "/__quorra/reload";
+
+/// Lists the messages captured by the `smtp` listeners on `GET`, and forgets
+/// them on `DELETE`.
+const SMTP_MESSAGES_PATH: &str = "/__quorra/smtp/messages";
+
 #[derive(Parser, Debug)]
 pub struct ServerCommandConfig {
     #[clap(long = "config", short, env = "CONFIG_PATH")]
     /// Location of root config file
     pub config_file: PathBuf,
 
-    #[clap(
-        long = "listen",
-        short,
-        env = "SERVER_LISTEN",
-        default_value("127.0.0.1:3000")
-    )]
-    pub listen_address: String,
+    #[clap(long = "listen", short, env = "SERVER_LISTEN", value_delimiter = ',')]
+    /// Addresses to listen on, replacing `http.address` from the root config.
+    /// Defaults to 127.0.0.1:3000. Also takes `unix:/path.sock`, and `fd:0` or
+    /// `fd:name` for sockets passed with LISTEN_FDS.
+    pub listen_address: Vec<String>,
+
+    #[clap(long = "address-file", env = "SERVER_ADDRESS_FILE")]
+    /// Write the bound address of every listener to this file, once they are
+    /// all listening. Useful when listening on port 0.
+    pub address_file: Option<PathBuf>,
 }
 
 impl ServerCommandConfig {
     pub async fn run_server(&self) -> Result<(), anyhow::Error> {
-        let config_container = ConfigContainer::new(&self.config_file);
-        let user_config = config_container.load_config()?;
-        let http_plugins = crate::config::build_backends(&user_config).await?;
-        debug!("Found {} http plugins", http_plugins.len());
-
-        let addr: SocketAddr = self.listen_address.parse()?;
-        let service = HyperService::new(http_plugins);
-        let hyper_backend = Arc::new(RwLock::new(service));
-
-        crate::watch_for_changes(&self.config_file, hyper_backend.clone());
-
-        let real_service = service_fn(move |req: Request<hyper::Body>| {
-            let hyper_backend = hyper_backend.clone();
-            async move { hyper_backend.read().await.process_plugins(req).await }
-        });
-
-        let service = ServiceBuilder::new()
-            .layer(SetSensitiveRequestHeadersLayer::new(once(AUTHORIZATION)))
-            // High level logging of requests and responses
-            .layer(TraceLayer::new_for_http())
-            .layer(CompressionLayer::new())
-            .layer(SetResponseHeaderLayer::overriding(
-                HeaderName::from_static("x-quorra"),
-                HeaderValue::from_static("true"),
-            ))
-            .layer(SetResponseHeaderLayer::if_not_present(
-                HeaderName::from_static("x-quorra-plugin-id"),
-                HeaderValue::from_static("null"),
-            ))
-            .layer(SetResponseHeaderLayer::if_not_present(
-                HeaderName::from_static("x-quorra-payload-id"),
-                HeaderValue::from_static("null"),
-            ))
-            .layer(SetResponseHeaderLayer::if_not_present(
-                HeaderName::from_static("x-quorra-response-id"),
-                HeaderValue::from_static("null"),
-            ))
-            // Propagate `X-Request-Id`s from requests to responses
-            .layer(PropagateHeaderLayer::new(HeaderName::from_static(
-                "x-request-id",
-            )))
-            .service(real_service);
-
-        info!("Starting server on {}", addr);
-
-        Server::bind(&addr)
-            .serve(Shared::new(service))
-            .await
-            .expect("server error");
+        let mut reloader = ConfigReloader::new(&self.config_file);
+        let reloaded = reloader.reload()?;
+        // There is nothing to fall back to on startup, so every file has to load.
+        if let Some(e) = reloaded.errors.into_iter().next() {
+            return Err(e.into());
+        }
+
+        // The command line and the environment take precedence over the root config.
+        let config = &reloaded.config;
+        let mut http_config = config.root.http.clone();
+        if !self.listen_address.is_empty() {
+            http_config.address = self.listen_address.clone();
+        }
+        let quorra_headers = Arc::new(quorra_headers(&http_config)?);
+        let config_dir = self.config_file.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut listeners = Vec::new();
+        if config.root.has_default_listener() {
+            listeners.push((
+                None,
+                http_config.address.clone(),
+                &config.responses,
+                http_config.tls.as_ref(),
+            ));
+        }
+        for (name, listener) in &config.root.listeners {
+            if listener.tls.is_some() && listener.protocol != ListenerProtocol::Http {
+                bail!(
+                    "Listener {} has `tls`, which only applies to HTTP listeners",
+                    name
+                );
+            }
+            listeners.push((
+                Some(name.clone()),
+                listener.address.clone(),
+                &config.listeners[name],
+                listener.tls.as_ref(),
+            ));
+        }
+
+        // Listeners with the same TLS settings share their certificates, and
+        // their local CA.
+        let mut acceptors: Vec<(&TlsConfig, TlsAcceptor)> = Vec::new();
+        let mut listener_tls = BTreeMap::new();
+        for (name, _, _, tls) in &listeners {
+            let tls = match tls {
+                Some(tls) => *tls,
+                None => continue,
+            };
+            let acceptor = match acceptors.iter().find(|(config, _)| *config == tls) {
+                Some((_, acceptor)) => acceptor.clone(),
+                None => {
+                    let acceptor = crate::tls::build_acceptor(tls, http_config.http2, config_dir)
+                        .map_err(|e| match name {
+                        None => anyhow!("Invalid `http.tls`: {}", e),
+                        Some(name) => {
+                            anyhow!("Invalid `tls` of listener {}: {}", name, e)
+                        }
+                    })?;
+                    acceptors.push((tls, acceptor.clone()));
+                    acceptor
+                }
+            };
+            listener_tls.insert(name.clone(), acceptor);
+        }
+
+        let mut services = ListenerServices::new();
+        for (name, _, responses, _) in &listeners {
+            let listener = name.as_ref().map(|name| &config.root.listeners[name]);
+            let service = match listener.map(|listener| (listener, listener.protocol)) {
+                Some((listener, ListenerProtocol::Tcp)) => ListenerService::Tcp(Arc::new(
+                    RwLock::new(crate::config::build_tcp_plugin(listener, responses)?),
+                )),
+                Some((_, ListenerProtocol::Smtp)) => ListenerService::Smtp(Arc::new(RwLock::new(
+                    crate::config::build_smtp_plugin(responses)?,
+                ))),
+                _ => ListenerService::Http(Arc::new(RwLock::new(
+                    crate::config::build_service(&http_config, responses).await?,
+                ))),
+            };
+            services.insert(name.clone(), service);
+        }
+
+        let reload_handle = watch_for_changes(reloader, services.clone());
+        // Shared by the `smtp` listeners, and read through the HTTP ones.
+        let mailbox = Mailbox::new();
+
+        let mut activation = SocketActivation::from_env();
+        let mut servers = Vec::new();
+        let mut bound_addresses = Vec::new();
+        for (name, addresses, _, _) in listeners {
+            for address in addresses {
+                let listener = activation.bind(&address.parse::<ListenAddress>()?)?;
+                match &name {
+                    None => info!("Starting server on {}", listener),
+                    Some(name) => info!("Starting listener {} on {}", name, listener),
+                }
+                bound_addresses.push((name.clone(), listener.to_string()));
+                match &services[&name] {
+                    ListenerService::Http(hyper_backend) => servers.push(
+                        serve(
+                            listener,
+                            &http_config,
+                            listener_tls.get(&name).cloned(),
+                            quorra_headers.clone(),
+                            hyper_backend.clone(),
+                            reload_handle.clone(),
+                            mailbox.clone(),
+                        )?
+                        .err_into()
+                        .boxed(),
+                    ),
+                    ListenerService::Tcp(plugin) => servers.push(
+                        serve_connections(listener, ConnectionService::Tcp(plugin.clone())).boxed(),
+                    ),
+                    ListenerService::Smtp(plugin) => servers.push(
+                        serve_connections(
+                            listener,
+                            ConnectionService::Smtp(plugin.clone(), mailbox.clone(), name.clone()),
+                        )
+                        .boxed(),
+                    ),
+                }
+            }
+        }
 
+        if let Some(address_file) = &self.address_file {
+            write_address_file(address_file, &bound_addresses)?;
+        }
+
+        try_join_all(servers).await?;
         Ok(())
     }
 }
+
+/// Serves a listener's requests on one socket, over TLS when `tls` is set.
+fn serve(
+    listener: BoundListener,
+    http_config: &HttpServerConfig,
+    tls: Option<TlsAcceptor>,
+    quorra_headers: Arc<Vec<String>>,
+    hyper_backend: Arc<RwLock<HyperService>>,
+    reload_handle: ReloadHandle,
+    mailbox: Mailbox,
+) -> Result<BoxFuture<'static, hyper::Result<()>>, anyhow::Error> {
+    let real_service = service_fn(move |req: Request<hyper::Body>| {
+        let hyper_backend = hyper_backend.clone();
+        let reload_handle = reload_handle.clone();
+        let mailbox = mailbox.clone();
+        async move {
+            match (req.method(), req.uri().path()) {
+                (&Method::POST, RELOAD_PATH) => return reload_response(&reload_handle).await,
+                (&Method::GET, SMTP_MESSAGES_PATH) => return smtp_messages_response(&mailbox),
+                (&Method::DELETE, SMTP_MESSAGES_PATH) => return smtp_clear_response(&mailbox),
+                _ => {}
+            }
+            hyper_backend.read().await.process_plugins(req).await
+        }
+    });
+
+    let compression = http_config.compression;
+    let service = ServiceBuilder::new()
+        .map_response(move |mut response: Response<_>| {
+            remove_quorra_headers(&quorra_headers, response.headers_mut());
+            response
+        })
+        .layer(SetSensitiveRequestHeadersLayer::new(once(AUTHORIZATION)))
+        // High level logging of requests and responses
+        .layer(TraceLayer::new_for_http())
+        .layer(
+            CompressionLayer::new()
+                .gzip(compression)
+                .deflate(compression)
+                .br(compression)
+                // The encoder holds on to small chunks, which would stall the events.
+                .compress_when(
+                    DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream")),
+                ),
+        )
+        .layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static("x-quorra"),
+            HeaderValue::from_static("true"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            HeaderName::from_static("x-quorra-plugin-id"),
+            HeaderValue::from_static("null"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            HeaderName::from_static("x-quorra-payload-id"),
+            HeaderValue::from_static("null"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            HeaderName::from_static("x-quorra-response-id"),
+            HeaderValue::from_static("null"),
+        ))
+        // Propagate `X-Request-Id`s from requests to responses
+        .layer(PropagateHeaderLayer::new(HeaderName::from_static(
+            "x-request-id",
+        )))
+        .option_layer(
+            http_config
+                .request_timeout
+                .map(|timeout| TimeoutLayer::new(Duration::from_millis(timeout))),
+        )
+        .service(real_service);
+
+    let make_service = Shared::new(service);
+    Ok(match (listener, tls) {
+        (BoundListener::Tcp(listener), None) => configure(
+            Server::builder(AddrIncoming::from_listener(listener)?),
+            http_config,
+        )
+        .serve(make_service)
+        .boxed(),
+        (BoundListener::Tcp(listener), Some(acceptor)) => configure(
+            Server::builder(crate::tls::accept(incoming(listener), acceptor)),
+            http_config,
+        )
+        .serve(make_service)
+        .boxed(),
+        #[cfg(unix)]
+        (BoundListener::Unix(listener, _), None) => configure(
+            Server::builder(from_stream(incoming(listener))),
+            http_config,
+        )
+        .serve(make_service)
+        .boxed(),
+        #[cfg(unix)]
+        (BoundListener::Unix(listener, _), Some(acceptor)) => configure(
+            Server::builder(crate::tls::accept(incoming(listener), acceptor)),
+            http_config,
+        )
+        .serve(make_service)
+        .boxed(),
+    })
+}
+
+/// What answers the connections of a listener that doesn't speak HTTP.
+enum ConnectionService {
+    Tcp(Arc<RwLock<TcpPlugin>>),
+    /// With the name of the listener, kept with the messages.
+    Smtp(Arc<RwLock<SmtpPlugin>>, Mailbox, Option<String>),
+}
+
+impl ConnectionService {
+    fn spawn<S>(&self, stream: S, peer: Option<SocketAddr>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        match self {
+            Self::Tcp(plugin) => {
+                let span = match peer {
+                    Some(peer) => info_span!("tcp_connection", peer = %peer),
+                    None => info_span!("tcp_connection"),
+                };
+                let connection = quorra_plugin_tcp::serve_connection(stream, plugin.clone());
+                tokio::spawn(connection.instrument(span));
+            }
+            Self::Smtp(plugin, mailbox, name) => {
+                let span = match peer {
+                    Some(peer) => info_span!("smtp_connection", peer = %peer),
+                    None => info_span!("smtp_connection"),
+                };
+                let connection = quorra_plugin_smtp::serve_connection(
+                    stream,
+                    plugin.clone(),
+                    mailbox.clone(),
+                    name.clone(),
+                );
+                tokio::spawn(connection.instrument(span));
+            }
+        }
+    }
+}
+
+/// Answers the connections of a `tcp` or `smtp` listener, each on its own
+/// task. There is no TLS on them.
+async fn serve_connections(
+    listener: BoundListener,
+    service: ConnectionService,
+) -> Result<(), anyhow::Error> {
+    loop {
+        let accepted = match &listener {
+            BoundListener::Tcp(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, peer)| service.spawn(stream, Some(peer))),
+            #[cfg(unix)]
+            BoundListener::Unix(listener, _) => listener
+                .accept()
+                .await
+                .map(|(stream, _)| service.spawn(stream, None)),
+        };
+        // Like running out of file descriptors, which can pass.
+        if let Err(e) = accepted {
+            warn!("Unable to accept a connection: {}", e);
+            tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+        }
+    }
+}
+
+fn configure<I>(mut builder: Builder<I>, http_config: &HttpServerConfig) -> Builder<I> {
+    // Otherwise hyper answers HTTP/2 with prior knowledge on its own.
+    if !http_config.http2 {
+        builder = builder.http1_only(true);
+    }
+    if let Some(timeout) = http_config.header_read_timeout {
+        builder = builder.http1_header_read_timeout(Duration::from_millis(timeout));
+    }
+    builder
+}
+
+fn quorra_headers(http_config: &HttpServerConfig) -> Result<Vec<String>, anyhow::Error> {
+    let mut enabled = Vec::new();
+    for header in &http_config.quorra_headers {
+        let header = header.to_lowercase();
+        if !QUORRA_HEADERS.contains(&header.as_str()) {
+            bail!(
+                "Unknown header `{}` in `http.quorra-headers`, expected one of {:?}",
+                header,
+                QUORRA_HEADERS
+            );
+        }
+        enabled.push(header);
+    }
+    Ok(enabled)
+}
+
+/// Only the `x-quorra-*` headers enabled in the root config are sent.
+fn remove_quorra_headers(enabled: &[String], headers: &mut HeaderMap) {
+    for header in QUORRA_HEADERS {
+        if !enabled.iter().any(|x| x == header) {
+            headers.remove(header);
+        }
+    }
+}
+
+async fn reload_response(reload_handle: &ReloadHandle) -> Result<Response<Body>, anyhow::Error> {
+    let status = match reload_handle.reload().await {
+        Some(status) => status,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Body::empty())?)
+        }
+    };
+
+    let code = if status.errors.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    Ok(Response::builder()
+        .status(code)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&status)?))?)
+}
+
+fn smtp_messages_response(mailbox: &Mailbox) -> Result<Response<Body>, anyhow::Error> {
+    Ok(Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&mailbox.messages())?))?)
+}
+
+fn smtp_clear_response(mailbox: &Mailbox) -> Result<Response<Body>, anyhow::Error> {
+    let cleared = mailbox.clear();
+    Ok(Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(
+            &serde_json::json!({ "cleared": cleared }),
+        )?))?)
+}
+
+#[tokio::test]
+async fn test_smtp_messages_endpoint() {
+    use hyper::{body::to_bytes, Client};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = crate::reload::test_config_dir("[responses]\npaths = []\n");
+    let mut reloader = ConfigReloader::new(&dir.path().join("config.toml"));
+    let config = reloader.reload().unwrap().config;
+    let service = crate::config::build_service(&config.root.http, &config.responses)
+        .await
+        .unwrap();
+    let hyper_backend = Arc::new(RwLock::new(service));
+    let reload_handle = watch_for_changes(reloader, BTreeMap::new());
+
+    let mailbox = Mailbox::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = serve(
+        BoundListener::Tcp(listener),
+        &config.root.http,
+        None,
+        Arc::new(vec![]),
+        hyper_backend,
+        reload_handle,
+        mailbox.clone(),
+    )
+    .unwrap();
+    tokio::spawn(server);
+
+    let plugin = Arc::new(RwLock::new(
+        quorra_plugin_smtp::SmtpPluginBuilder::new()
+            .build()
+            .unwrap(),
+    ));
+    let (mut client, stream) = tokio::io::duplex(4096);
+    let connection = tokio::spawn(quorra_plugin_smtp::serve_connection(
+        stream,
+        plugin,
+        mailbox,
+        Some("mail".to_string()),
+    ));
+    client
+        .write_all(
+            b"HELO client\r\nMAIL FROM:<app@example.test>\r\nRCPT TO:<user@example.test>\r\n\
+            DATA\r\nSubject: Hi\r\n\r\nHello\r\n.\r\nQUIT\r\n",
+        )
+        .await
+        .unwrap();
+    client.read_to_end(&mut Vec::new()).await.unwrap();
+    connection.await.unwrap();
+
+    let uri = format!("http://{}{}", address, SMTP_MESSAGES_PATH);
+    let client = Client::new();
+    let json = |request: Request<Body>| {
+        let response = client.request(request);
+        async move {
+            let response = response.await.unwrap();
+            assert_eq!(StatusCode::OK, response.status());
+            let body = to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    let messages = json(Request::get(&uri).body(Body::empty()).unwrap()).await;
+    let messages = messages.as_array().unwrap();
+    assert_eq!(1, messages.len());
+    assert_eq!("mail", messages[0]["listener"]);
+    assert_eq!("app@example.test", messages[0]["from"]);
+    assert_eq!(
+        serde_json::json!(["user@example.test"]),
+        messages[0]["recipients"]
+    );
+
+    let cleared = json(Request::delete(&uri).body(Body::empty()).unwrap()).await;
+    assert_eq!(serde_json::json!({ "cleared": 1 }), cleared);
+    let messages = json(Request::get(&uri).body(Body::empty()).unwrap()).await;
+    assert_eq!(serde_json::json!([]), messages);
+}
@@ -0,0 +1,253 @@
+use anyhow::{anyhow, bail};
+use futures::{
+    stream::{poll_fn, Stream},
+    StreamExt,
+};
+use hyper::server::accept::{from_stream, Accept};
+use quorra_config::prelude::TlsConfig;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa, SanType,
+};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{any_supported_type, CertifiedKey},
+    PrivateKey, ServerConfig,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader},
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::{debug, info, warn};
+
+use crate::listen::ACCEPT_ERROR_DELAY;
+
+/// Used when the client doesn't send a server name, like when connecting to an IP.
+const DEFAULT_SERVER_NAME: &str = "localhost";
+
+/// How long a client may take to finish the handshake, before its connection
+/// is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many certificates the local CA keeps. Clients pick the server name, so
+/// the ones issued so far are forgotten past this.
+const MAX_ISSUED_CERTIFICATES: usize = 256;
+
+/// Builds the TLS settings of a listener. Relative paths start at
+/// `base_dir`, the directory of the root config. HTTP/2 is offered to clients
+/// when `http2` is set.
+pub fn build_acceptor(
+    tls: &TlsConfig,
+    http2: bool,
+    base_dir: &Path,
+) -> Result<TlsAcceptor, anyhow::Error> {
+    let builder = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth();
+
+    let mut config = match (&tls.cert, &tls.key, &tls.local_ca_cert) {
+        (Some(cert), Some(key), None) => {
+            let certs = load_certs(&base_dir.join(cert))?;
+            let key = load_key(&base_dir.join(key))?;
+            builder.with_single_cert(certs, key)?
+        }
+        (None, None, Some(ca_path)) => {
+            let resolver = LocalCa::new()?;
+            let ca_path = base_dir.join(ca_path);
+            std::fs::write(&ca_path, resolver.ca.serialize_pem()?)?;
+            info!("Wrote the local CA certificate to {}", ca_path.display());
+            builder.with_cert_resolver(Arc::new(resolver))
+        }
+        _ => bail!("needs either `cert` and `key`, or `local-ca-cert`"),
+    };
+    if http2 {
+        config.alpn_protocols.push(b"h2".to_vec());
+    }
+    config.alpn_protocols.push(b"http/1.1".to_vec());
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Hands connections to hyper once the handshake is done. Handshakes run on
+/// their own, so a slow client doesn't hold up the others, and are given up
+/// after `HANDSHAKE_TIMEOUT`.
+pub fn accept<S>(
+    mut incoming: impl Stream<Item = io::Result<S>> + Send + Unpin + 'static,
+    acceptor: TlsAcceptor,
+) -> impl Accept<Conn = TlsStream<S>, Error = io::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        // Stops once the server is gone.
+        while !sender.is_closed() {
+            let stream = match incoming.next().await {
+                Some(Ok(stream)) => stream,
+                Some(Err(e)) => {
+                    warn!("Unable to accept a connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+                None => break,
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(stream));
+                    }
+                    Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                    Err(_) => debug!("TLS handshake timed out"),
+                }
+            });
+        }
+    });
+
+    from_stream(poll_fn(move |cx| receiver.poll_recv(cx)))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<rustls::Certificate>, anyhow::Error> {
+    let mut reader = BufReader::new(open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        bail!("No certificate found in {}", path.display());
+    }
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, anyhow::Error> {
+    let mut reader = BufReader::new(open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+    bail!("No private key found in {}", path.display())
+}
+
+fn open(path: &Path) -> Result<File, anyhow::Error> {
+    File::open(path).map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))
+}
+
+/// A CA made up on startup, issuing a certificate for each server name the
+/// first time a client asks for it. At most `MAX_ISSUED_CERTIFICATES` are kept.
+struct LocalCa {
+    ca: Certificate,
+    ca_der: Vec<u8>,
+    issued: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl LocalCa {
+    fn new() -> Result<Self, anyhow::Error> {
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Quorra Local CA");
+        let ca = Certificate::from_params(params)?;
+        let ca_der = ca.serialize_der()?;
+        Ok(Self {
+            ca,
+            ca_der,
+            issued: Default::default(),
+        })
+    }
+
+    fn issue(&self, server_name: &str) -> Result<CertifiedKey, anyhow::Error> {
+        debug!("Issuing a certificate for {}", server_name);
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, server_name);
+        params.subject_alt_names = vec![SanType::DnsName(server_name.to_string())];
+        if server_name == DEFAULT_SERVER_NAME {
+            for ip in ["127.0.0.1", "::1"] {
+                params
+                    .subject_alt_names
+                    .push(SanType::IpAddress(ip.parse::<IpAddr>()?));
+            }
+        }
+
+        let leaf = Certificate::from_params(params)?;
+        let chain = vec![
+            rustls::Certificate(leaf.serialize_der_with_signer(&self.ca)?),
+            rustls::Certificate(self.ca_der.clone()),
+        ];
+        let key = any_supported_type(&PrivateKey(leaf.serialize_private_key_der()))?;
+        Ok(CertifiedKey::new(chain, key))
+    }
+
+    /// The certificate issued for `server_name`, issuing it if needed.
+    fn certified(&self, server_name: String) -> Option<Arc<CertifiedKey>> {
+        let mut issued = self.issued.lock().ok()?;
+        if let Some(certified) = issued.get(&server_name) {
+            return Some(certified.clone());
+        }
+        match self.issue(&server_name) {
+            Ok(certified) => {
+                if issued.len() >= MAX_ISSUED_CERTIFICATES {
+                    debug!("Forgetting the {} certificates issued so far", issued.len());
+                    issued.clear();
+                }
+                let certified = Arc::new(certified);
+                issued.insert(server_name, certified.clone());
+                Some(certified)
+            }
+            Err(e) => {
+                warn!("Unable to issue a certificate for {}: {}", server_name, e);
+                None
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for LocalCa {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello
+            .server_name()
+            .unwrap_or(DEFAULT_SERVER_NAME)
+            .to_lowercase();
+        self.certified(server_name)
+    }
+}
+
+#[test]
+fn test_local_ca_issues_signed_certificates() {
+    let local_ca = LocalCa::new().unwrap();
+    let certified = local_ca.issue("api.example.test").unwrap();
+    assert_eq!(2, certified.cert.len());
+    assert_eq!(local_ca.ca_der, certified.cert[1].0);
+    assert!(local_ca
+        .ca
+        .serialize_pem()
+        .unwrap()
+        .starts_with("-----BEGIN CERTIFICATE-----"));
+}
+
+#[test]
+fn test_local_ca_bounds_issued_certificates() {
+    let local_ca = LocalCa::new().unwrap();
+    let first = local_ca.certified("0.example.test".to_string()).unwrap();
+    let again = local_ca.certified("0.example.test".to_string()).unwrap();
+    assert!(Arc::ptr_eq(&first, &again));
+
+    for i in 1..=MAX_ISSUED_CERTIFICATES {
+        local_ca.certified(format!("{}.example.test", i)).unwrap();
+    }
+    assert_eq!(1, local_ca.issued.lock().unwrap().len());
+}
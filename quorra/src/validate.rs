@@ -0,0 +1,353 @@
+use anyhow::anyhow;
+use clap::Parser;
+use quorra_config::prelude::*;
+use quorra_plugin_tcp::TcpPluginBuilder;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tracing::{error, info, warn};
+
+use crate::config::validate_payload;
+
+#[derive(Parser, Debug)]
+pub struct ValidateCommandConfig {
+    #[clap(long = "config", short, env = "CONFIG_PATH")]
+    /// Location of root config file
+    pub config_file: PathBuf,
+
+    #[clap(long("deny-warnings"))]
+    /// Fail when any warning is found, not only on errors
+    pub deny_warnings: bool,
+}
+
+impl ValidateCommandConfig {
+    pub async fn run_validate(&self) -> Result<(), anyhow::Error> {
+        let mut validated = ConfigContainer::new(&self.config_file).validate_config();
+
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+        let mut protocols = BTreeMap::new();
+        for (name, listener) in validated.root.iter().flat_map(|root| &root.listeners) {
+            protocols.insert(name.as_str(), listener.protocol);
+            if listener.tls.is_some() && listener.protocol != ListenerProtocol::Http {
+                errors.push(ConfigError::Invalid {
+                    location: Box::new(ConfigLocation {
+                        key_path: Some(format!("listeners.{}.tls", name)),
+                        ..ConfigLocation::new(&self.config_file)
+                    }),
+                    message: "`tls` only applies to HTTP listeners".to_string(),
+                });
+            }
+            if listener.protocol != ListenerProtocol::Tcp {
+                continue;
+            }
+            let framing = listener.framing.clone().unwrap_or_default();
+            if let Err(e) = TcpPluginBuilder::new().with_framing(framing).build() {
+                errors.push(ConfigError::Invalid {
+                    location: Box::new(ConfigLocation {
+                        key_path: Some(format!("listeners.{}.framing", name)),
+                        ..ConfigLocation::new(&self.config_file)
+                    }),
+                    message: e.to_string(),
+                });
+            }
+        }
+
+        // A file used by several listeners is only checked once.
+        let mut checked: Vec<(PathBuf, &ResponseConfig<String>)> = Vec::new();
+        let mut payloads: Vec<(PathBuf, &StaticHttpConfig<String>)> = Vec::new();
+        let mut listeners: BTreeMap<Option<&str>, Vec<(PathBuf, &StaticHttpConfig<String>)>> =
+            BTreeMap::new();
+        for response in &validated.responses {
+            let listener_protocol = response
+                .listener
+                .as_deref()
+                .and_then(|name| protocols.get(name).copied())
+                .unwrap_or_default();
+            let payload_protocol = match response.config {
+                ResponseConfig::Tcp(_) => ListenerProtocol::Tcp,
+                ResponseConfig::Smtp(_) => ListenerProtocol::Smtp,
+                _ => ListenerProtocol::Http,
+            };
+            if listener_protocol != payload_protocol {
+                let listener = response.listener.as_deref().unwrap_or("default");
+                warnings.push(issue(
+                    &response.file,
+                    match listener_protocol {
+                        ListenerProtocol::Http => format!(
+                            "listener {} is an HTTP listener, it doesn't use `{}` payloads",
+                            listener,
+                            protocol_name(payload_protocol)
+                        ),
+                        _ => format!(
+                            "listener {} is a `{name}` listener, it only uses `{name}` payloads",
+                            listener,
+                            name = protocol_name(listener_protocol)
+                        ),
+                    },
+                ));
+            }
+
+            if !checked.iter().any(|(file, _)| file == &response.file) {
+                checked.push((response.file.clone(), &response.config));
+            }
+
+            let payload = match &response.config {
+                ResponseConfig::StaticHttp(http) => (response.file.clone(), http),
+                _ => continue,
+            };
+            if !payloads.iter().any(|(file, _)| file == &response.file) {
+                payloads.push(payload.clone());
+            }
+            listeners
+                .entry(response.listener.as_deref())
+                .or_default()
+                .push(payload);
+        }
+
+        for (file, config) in &checked {
+            errors.extend(validate_payload(file, config));
+        }
+
+        for (file, payload) in &payloads {
+            for response in &payload.responses {
+                if response.weight == 0 {
+                    warnings.push(issue(
+                        file,
+                        format!(
+                            "response {} has a weight of 0 and will never be sent",
+                            response.id
+                        ),
+                    ));
+                }
+            }
+        }
+
+        lint_duplicate_ids(&checked, &mut warnings);
+        // Each listener tries its own payloads.
+        for listener_payloads in listeners.values() {
+            lint_shadowed_payloads(listener_payloads, &mut warnings);
+        }
+
+        validated.errors.append(&mut errors);
+        validated.warnings.append(&mut warnings);
+
+        for warning in &validated.warnings {
+            warn!("{}", warning);
+        }
+        for e in &validated.errors {
+            error!("{}", e);
+            if let Some(snippet) = e.snippet() {
+                eprint!("{}", snippet);
+            }
+        }
+
+        let failed =
+            !validated.errors.is_empty() || (self.deny_warnings && !validated.warnings.is_empty());
+        if failed {
+            return Err(anyhow!(
+                "{} errors and {} warnings found",
+                validated.errors.len(),
+                validated.warnings.len()
+            ));
+        }
+
+        info!(
+            "{} payloads are valid, {} warnings found",
+            checked.len(),
+            validated.warnings.len()
+        );
+        Ok(())
+    }
+}
+
+fn issue(file: &Path, message: String) -> ConfigIssue {
+    ConfigIssue {
+        file: file.to_path_buf(),
+        message,
+    }
+}
+
+/// The name of the protocol in the payload types and in `listeners.*.protocol`.
+fn protocol_name(protocol: ListenerProtocol) -> &'static str {
+    match protocol {
+        ListenerProtocol::Http => "static-http",
+        ListenerProtocol::Tcp => "tcp",
+        ListenerProtocol::Smtp => "smtp",
+    }
+}
+
+/// Payload ids tell the payloads apart in the logs, and response ids name the
+/// templates of static responses, so a duplicate replaces the template of the
+/// other response.
+fn lint_duplicate_ids(
+    payloads: &[(PathBuf, &ResponseConfig<String>)],
+    warnings: &mut Vec<ConfigIssue>,
+) {
+    let mut payload_ids: BTreeMap<&str, &PathBuf> = BTreeMap::new();
+    let mut response_ids: BTreeMap<&str, &PathBuf> = BTreeMap::new();
+    for (file, payload) in payloads {
+        if let Some(first) = payload_ids.insert(payload.id(), file) {
+            warnings.push(issue(
+                file,
+                format!(
+                    "payload id {} is also used in {}",
+                    payload.id(),
+                    first.display()
+                ),
+            ));
+        }
+
+        let responses = match payload {
+            ResponseConfig::StaticHttp(http) => &http.responses,
+            _ => continue,
+        };
+        for response in responses {
+            if let Some(first) = response_ids.insert(&response.id, file) {
+                warnings.push(issue(
+                    file,
+                    format!(
+                        "response id {} is also used in {}",
+                        response.id,
+                        first.display()
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Payloads are tried in order, so a payload is never used when every one of its
+/// matchers is covered by a matcher of an earlier payload.
+fn lint_shadowed_payloads(
+    payloads: &[(PathBuf, &StaticHttpConfig<String>)],
+    warnings: &mut Vec<ConfigIssue>,
+) {
+    for (index, (file, payload)) in payloads.iter().enumerate() {
+        if payload.matches.is_empty() {
+            continue;
+        }
+
+        let shadowed_by = payloads[..index].iter().find(|(_, earlier)| {
+            payload.matches.iter().all(|matcher| {
+                earlier
+                    .matches
+                    .iter()
+                    .any(|earlier| matcher_covers(earlier, matcher))
+            })
+        });
+
+        if let Some((earlier_file, earlier)) = shadowed_by {
+            warnings.push(issue(
+                file,
+                format!(
+                    "payload {} is shadowed by payload {} in {}",
+                    payload.id,
+                    earlier.id,
+                    earlier_file.display()
+                ),
+            ));
+        }
+    }
+}
+
+/// True when every request `later` matches is also matched by `earlier`.
+fn matcher_covers(earlier: &StaticMatchesConfig, later: &StaticMatchesConfig) -> bool {
+    let host_covered = match (&earlier.host, &later.host) {
+        (None, _) => true,
+        (Some(earlier), Some(later)) => earlier == later,
+        (Some(_), None) => false,
+    };
+    let protocol_covered = match (&earlier.protocol, &later.protocol) {
+        (None, _) => true,
+        (Some(earlier), Some(later)) => earlier == later,
+        (Some(_), None) => false,
+    };
+    if !host_covered
+        || !protocol_covered
+        || !earlier.query.is_empty()
+        || !earlier.headers.is_empty()
+        || earlier.graphql.is_some()
+        || earlier.body.is_some()
+    {
+        return false;
+    }
+
+    let methods_covered = earlier.methods.is_empty()
+        || (!later.methods.is_empty()
+            && later.methods.iter().all(|method| {
+                earlier
+                    .methods
+                    .iter()
+                    .any(|x| x.eq_ignore_ascii_case(method))
+            }));
+
+    methods_covered && path_covers(&earlier.path, &later.path)
+}
+
+fn path_covers(earlier: &str, later: &str) -> bool {
+    if earlier == later || earlier == ".*" || (earlier == "/.*" && later.starts_with('/')) {
+        return true;
+    }
+
+    // Only literal paths can be checked against another regex.
+    let literal = later.replace('\\', "");
+    if regex::escape(&literal) != later {
+        return false;
+    }
+
+    match Regex::new(&format!("^(?:{})$", earlier)) {
+        Ok(regex) => regex.is_match(&literal),
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn test_matcher_covers() {
+    let matcher = |methods: &[&str], path: &str| StaticMatchesConfig {
+        path: path.to_string(),
+        host: None,
+        protocol: None,
+        query: Default::default(),
+        headers: Default::default(),
+        methods: methods.iter().map(|x| x.to_string()).collect(),
+        graphql: None,
+        body: None,
+    };
+
+    assert!(matcher_covers(
+        &matcher(&[], ".*"),
+        &matcher(&["GET"], "/users")
+    ));
+    assert!(matcher_covers(
+        &matcher(&["GET", "POST"], "/users/[0-9]+"),
+        &matcher(&["get"], r"/users/42")
+    ));
+    assert!(!matcher_covers(
+        &matcher(&["GET"], ".*"),
+        &matcher(&[], "/users")
+    ));
+    assert!(!matcher_covers(
+        &matcher(&[], "/users/[0-9]+"),
+        &matcher(&[], "/users/[a-z]+")
+    ));
+
+    let mut with_header = matcher(&[], ".*");
+    with_header
+        .headers
+        .insert("Host".to_string(), "example.com".to_string());
+    assert!(!matcher_covers(&with_header, &matcher(&[], "/users")));
+
+    let mut with_host = matcher(&[], ".*");
+    with_host.host = Some("api\\.example\\.test".to_string());
+    assert!(!matcher_covers(&with_host, &matcher(&[], "/users")));
+    assert!(matcher_covers(&with_host, &with_host.clone()));
+
+    let mut with_protocol = matcher(&[], ".*");
+    with_protocol.protocol = Some("HTTP/2".to_string());
+    assert!(!matcher_covers(&with_protocol, &matcher(&[], "/users")));
+    assert!(matcher_covers(&with_protocol, &with_protocol.clone()));
+    assert!(matcher_covers(&matcher(&[], ".*"), &with_protocol));
+}